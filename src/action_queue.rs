@@ -0,0 +1,589 @@
+use crate::bot_engine::{send_log, LogLevel, LogMessage};
+use crate::input_backend::InputError;
+use crate::input_manager::InputManager;
+use crate::keymap::{KeyCombo, KeyboardLayout};
+use crate::safety::{InputTarget, SafetyGuard};
+use crate::worker::{CancelToken, Worker};
+use rdev::Key;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default gap between two input actions.
+pub const DEFAULT_SPACING: Duration = Duration::from_millis(80);
+/// Upper bound on how long the worker sleeps before re-checking delayed requests.
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ActionPriority {
+    Normal,
+    /// Cancels everything else, queued or running, then runs. With no actions it only
+    /// clears the way, as the emergency stop does.
+    Emergency,
+}
+
+/// Why the queue is holding requests back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    /// The user is using the mouse or keyboard; lifts once they've been idle long enough.
+    UserActivity,
+    /// The panic hotkey was pressed; lifts only when resumed by hand.
+    EmergencyStop,
+}
+
+impl std::fmt::Display for PauseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PauseReason::UserActivity => write!(f, "user activity"),
+            PauseReason::EmergencyStop => write!(f, "emergency stop"),
+        }
+    }
+}
+
+/// One input step. Gestures run whole; cancellation happens between steps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputAction {
+    Focus(i32),
+    Click { x: f64, y: f64 },
+    RightClick { x: f64, y: f64 },
+    DoubleClick { x: f64, y: f64 },
+    Drag { from: (f64, f64), to: (f64, f64) },
+    Scroll { x: f64, y: f64, notches: i32 },
+    Hover { x: f64, y: f64, hold: Duration },
+    Key(Key),
+    Combo(KeyCombo),
+    Type { text: String, layout: KeyboardLayout },
+    /// Pause inside a sequence, holding the queue.
+    Wait(Duration),
+}
+
+impl InputAction {
+    fn execute(&self, input: &InputManager) -> Result<(), InputError> {
+        match self {
+            InputAction::Focus(pid) => input.focus_window(*pid),
+            InputAction::Click { x, y } => input.click_at(*x, *y),
+            InputAction::RightClick { x, y } => input.right_click_at(*x, *y),
+            InputAction::DoubleClick { x, y } => input.double_click_at(*x, *y),
+            InputAction::Drag { from, to } => input.drag(*from, *to),
+            InputAction::Scroll { x, y, notches } => input.scroll_at(*x, *y, *notches),
+            InputAction::Hover { x, y, hold } => input.hover(*x, *y, *hold),
+            InputAction::Key(key) => input.press_key(*key),
+            InputAction::Combo(combo) => input.press_combo(combo),
+            InputAction::Type { text, layout } => input.type_text(text, *layout),
+            InputAction::Wait(duration) => {
+                thread::sleep(*duration);
+                Ok(())
+            }
+        }
+    }
+
+    /// Screen points the pointer goes to.
+    pub fn points(&self) -> Vec<(f64, f64)> {
+        match self {
+            InputAction::Click { x, y }
+            | InputAction::RightClick { x, y }
+            | InputAction::DoubleClick { x, y }
+            | InputAction::Scroll { x, y, .. }
+            | InputAction::Hover { x, y, .. } => vec![(*x, *y)],
+            InputAction::Drag { from, to } => vec![*from, *to],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Checked before each step; returning false cancels the rest of the request.
+pub type ActionGuard = Box<dyn Fn() -> bool + Send>;
+
+/// A sequence of actions run back to back, without input from other requests in between.
+pub struct ActionRequest {
+    pub label: String,
+    pub actions: Vec<InputAction>,
+    pub priority: ActionPriority,
+    not_before: Option<Instant>,
+    guard: Option<ActionGuard>,
+    target: Option<InputTarget>,
+}
+
+impl ActionRequest {
+    pub fn new(label: &str, actions: Vec<InputAction>) -> Self {
+        Self {
+            label: label.to_string(),
+            actions,
+            priority: ActionPriority::Normal,
+            not_before: None,
+            guard: None,
+            target: None,
+        }
+    }
+
+    pub fn priority(mut self, priority: ActionPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Holds the request back for `delay` without blocking the queue in the meantime.
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.not_before = Some(Instant::now() + delay);
+        self
+    }
+
+    /// Cancels the request if `guard` stops holding, e.g. the screen it was planned for changed.
+    pub fn guard(mut self, guard: impl Fn() -> bool + Send + 'static) -> Self {
+        self.guard = Some(Box::new(guard));
+        self
+    }
+
+    /// Only lets the input through while `target` is frontmost and the pointer stays inside it.
+    pub fn target(mut self, target: InputTarget) -> Self {
+        self.target = Some(target);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionOutcome {
+    Done,
+    Failed(InputError),
+    Cancelled(String),
+}
+
+impl ActionOutcome {
+    /// Folds the outcome into the input API's error type.
+    pub fn into_result(self) -> Result<(), InputError> {
+        match self {
+            ActionOutcome::Done => Ok(()),
+            ActionOutcome::Failed(err) => Err(err),
+            ActionOutcome::Cancelled(reason) => Err(InputError::Cancelled(reason)),
+        }
+    }
+}
+
+/// Handle on a submitted request.
+pub struct ActionTicket {
+    cancelled: Arc<AtomicBool>,
+    outcome: Receiver<ActionOutcome>,
+}
+
+impl ActionTicket {
+    /// Blocks until the request has run or was cancelled.
+    pub fn wait(self) -> ActionOutcome {
+        self.outcome
+            .recv()
+            .unwrap_or_else(|_| ActionOutcome::Cancelled("action queue stopped".to_string()))
+    }
+
+    /// Like `wait`, but cancels the request and returns as soon as `token` is cancelled.
+    pub fn wait_or_cancel(self, token: &CancelToken) -> ActionOutcome {
+        loop {
+            match self.outcome.recv_timeout(IDLE_WAIT) {
+                Ok(outcome) => return outcome,
+                Err(RecvTimeoutError::Timeout) if token.is_cancelled() => {
+                    self.cancel();
+                    return ActionOutcome::Cancelled("cancelled".to_string());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return ActionOutcome::Cancelled("action queue stopped".to_string()),
+            }
+        }
+    }
+
+    pub fn try_outcome(&self) -> Option<ActionOutcome> {
+        self.outcome.try_recv().ok()
+    }
+
+    /// Cancels the request; a running one stops before its next step.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+struct Pending {
+    id: u64,
+    request: ActionRequest,
+    cancelled: Arc<AtomicBool>,
+    notify: Sender<ActionOutcome>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    pending: Vec<Pending>,
+    /// Cancel flag of the request being executed.
+    running: Option<Arc<AtomicBool>>,
+    next_id: u64,
+    last_done: Option<Instant>,
+    paused: Option<PauseReason>,
+    /// Set by `shutdown`; new requests are cancelled straight away.
+    closed: bool,
+}
+
+impl QueueState {
+    /// Highest priority request that is due, oldest first among equals.
+    fn next_due(&self, now: Instant) -> Option<usize> {
+        self.pending
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.request.not_before.is_none_or(|t| t <= now))
+            .max_by_key(|(_, p)| (p.request.priority, std::cmp::Reverse(p.id)))
+            .map(|(i, _)| i)
+    }
+
+    /// How long until something could become due.
+    fn next_wake(&self, now: Instant) -> Duration {
+        self.pending
+            .iter()
+            .filter_map(|p| p.request.not_before)
+            .map(|t| t.saturating_duration_since(now))
+            .min()
+            .unwrap_or(IDLE_WAIT)
+            .min(IDLE_WAIT)
+    }
+}
+
+/// Serialises all input through one worker thread.
+///
+/// Requests from different tasks and sessions never interleave, consecutive actions are
+/// at least `spacing` apart, and higher priorities go first. Cheap to clone; clones share
+/// the same queue. Steps of requests with a target pass `safety` first; refusals are
+/// logged as warnings.
+#[derive(Clone)]
+pub struct ActionQueue {
+    state: Arc<(Mutex<QueueState>, Condvar)>,
+    safety: SafetyGuard,
+    worker: Arc<Mutex<Option<Worker>>>,
+    log_tx: Sender<LogMessage>,
+}
+
+impl ActionQueue {
+    pub fn new(input: InputManager, spacing: Duration, safety: SafetyGuard, log_tx: Sender<LogMessage>) -> Self {
+        let queue = Self {
+            state: Arc::new((Mutex::new(QueueState::default()), Condvar::new())),
+            safety,
+            worker: Arc::new(Mutex::new(None)),
+            log_tx,
+        };
+        let runner = queue.clone();
+        let worker = Worker::spawn("action-queue", move |token| runner.work(input, spacing, token));
+        *queue.worker.lock().unwrap() = Some(worker);
+        queue
+    }
+
+    /// Queues a request; the ticket reports how it ended.
+    pub fn submit(&self, request: ActionRequest) -> ActionTicket {
+        let (tx, rx) = mpsc::channel();
+        let cancelled = self.push(request, tx);
+        ActionTicket { cancelled, outcome: rx }
+    }
+
+    /// Runs a request and waits for it.
+    pub fn run(&self, request: ActionRequest) -> Result<(), InputError> {
+        self.submit(request).wait().into_result()
+    }
+
+    /// Cancels every queued request matching `predicate` and returns how many there were.
+    pub fn cancel_where(&self, reason: &str, predicate: impl Fn(&ActionRequest) -> bool) -> usize {
+        let cancelled: Vec<Pending> = {
+            let mut state = self.state.0.lock().unwrap();
+            let (matching, kept) = std::mem::take(&mut state.pending)
+                .into_iter()
+                .partition(|p| predicate(&p.request));
+            state.pending = kept;
+            matching
+        };
+        let count = cancelled.len();
+        for pending in cancelled {
+            let _ = pending.notify.send(ActionOutcome::Cancelled(reason.to_string()));
+        }
+        count
+    }
+
+    /// Cancels everything queued and stops the running request before its next step.
+    pub fn cancel_all(&self, reason: &str) {
+        if let Some(flag) = &self.state.0.lock().unwrap().running {
+            flag.store(true, Ordering::SeqCst);
+        }
+        self.cancel_where(reason, |_| true);
+    }
+
+    /// Stops the running request and holds everything queued until `resume`. An
+    /// emergency stop is never downgraded to a user-activity pause, and while it is in
+    /// effect new requests are cancelled instead of queued.
+    pub fn pause(&self, reason: PauseReason) {
+        let mut state = self.state.0.lock().unwrap();
+        if state.paused == Some(PauseReason::EmergencyStop) {
+            return;
+        }
+        state.paused = Some(reason);
+        if let Some(flag) = &state.running {
+            flag.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Lifts a pause, if it is the one currently in effect.
+    pub fn resume(&self, reason: PauseReason) {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if state.paused == Some(reason) {
+            state.paused = None;
+            cvar.notify_all();
+        }
+    }
+
+    pub fn paused(&self) -> Option<PauseReason> {
+        self.state.0.lock().unwrap().paused
+    }
+
+    /// Cancels everything, refuses new requests and waits up to `timeout` for the worker
+    /// to finish the step it is on. Returns false if it didn't.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.state.0.lock().unwrap().closed = true;
+        self.cancel_all("shutting down");
+        let Some(mut worker) = self.worker.lock().unwrap().take() else { return true };
+        worker.cancel();
+        self.state.1.notify_all();
+        worker.stop(timeout)
+    }
+
+    fn push(&self, request: ActionRequest, notify: Sender<ActionOutcome>) -> Arc<AtomicBool> {
+        if request.priority == ActionPriority::Emergency {
+            let label = request.label.clone();
+            self.cancel_all(&format!("preempted by {}", label));
+        }
+
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let refused = if state.closed {
+            Some("shutting down".to_string())
+        } else if state.paused == Some(PauseReason::EmergencyStop) {
+            Some(format!("paused: {}", PauseReason::EmergencyStop))
+        } else {
+            None
+        };
+        if let Some(reason) = refused {
+            drop(state);
+            cancelled.store(true, Ordering::SeqCst);
+            let _ = notify.send(ActionOutcome::Cancelled(reason));
+            return cancelled;
+        }
+        state.pending.push(Pending {
+            id,
+            request,
+            cancelled: Arc::clone(&cancelled),
+            notify,
+        });
+        cvar.notify_all();
+        cancelled
+    }
+
+    fn work(&self, input: InputManager, spacing: Duration, token: CancelToken) {
+        let (lock, cvar) = &*self.state;
+        loop {
+            // 1. Wait for a due request and for the spacing since the last one
+            let job = {
+                let mut state = lock.lock().unwrap();
+                loop {
+                    if token.is_cancelled() {
+                        return;
+                    }
+                    let now = Instant::now();
+                    let spaced = state.last_done.is_none_or(|t| now.duration_since(t) >= spacing);
+                    if spaced
+                        && state.paused.is_none()
+                        && let Some(index) = state.next_due(now)
+                    {
+                        let job = state.pending.remove(index);
+                        state.running = Some(Arc::clone(&job.cancelled));
+                        break job;
+                    }
+                    let wait = if spaced {
+                        state.next_wake(now)
+                    } else {
+                        spacing.saturating_sub(state.last_done.map(|t| now.duration_since(t)).unwrap_or_default())
+                    };
+                    state = cvar.wait_timeout(state, wait).unwrap().0;
+                }
+            };
+
+            // 2. Run its steps, stopping early if it gets cancelled
+            let outcome = self.execute(&job, &input, spacing);
+
+            // 3. Report
+            {
+                let mut state = lock.lock().unwrap();
+                state.running = None;
+                state.last_done = Some(Instant::now());
+            }
+            let _ = job.notify.send(outcome);
+        }
+    }
+
+    fn execute(&self, job: &Pending, input: &InputManager, spacing: Duration) -> ActionOutcome {
+        for (i, action) in job.request.actions.iter().enumerate() {
+            if job.cancelled.load(Ordering::SeqCst) {
+                return match self.paused() {
+                    Some(reason) => ActionOutcome::Cancelled(format!("paused: {}", reason)),
+                    None => ActionOutcome::Cancelled("cancelled".to_string()),
+                };
+            }
+            if let Some(guard) = &job.request.guard
+                && !guard()
+            {
+                return ActionOutcome::Cancelled("no longer valid".to_string());
+            }
+            if i > 0 {
+                thread::sleep(spacing);
+            }
+            if let Some(target) = &job.request.target
+                && let Err(err) = self.safety.check(input, target, action)
+            {
+                let msg = format!("Refused {}: {}", job.request.label, err);
+                send_log(&self.log_tx, &msg, LogLevel::Warning);
+                return ActionOutcome::Failed(err);
+            }
+            if let Err(err) = action.execute(input) {
+                return ActionOutcome::Failed(err);
+            }
+        }
+        ActionOutcome::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_backend::{InputEvent, RecordingBackend};
+    use rdev::Button;
+
+    const SPACING: Duration = Duration::from_millis(60);
+
+    fn queue() -> (ActionQueue, Arc<RecordingBackend>) {
+        let backend = Arc::new(RecordingBackend::new());
+        let (log_tx, _log_rx) = mpsc::channel();
+        let input = InputManager::with_backend(backend.clone());
+        (ActionQueue::new(input, SPACING, SafetyGuard::default(), log_tx), backend)
+    }
+
+    fn click(label: &str, x: f64) -> ActionRequest {
+        ActionRequest::new(label, vec![InputAction::Click { x, y: 0.0 }])
+    }
+
+    fn clicked(backend: &RecordingBackend) -> Vec<f64> {
+        backend.clicks().into_iter().map(|(x, _)| x).collect()
+    }
+
+    /// Holds the worker busy for `hold`, then clicks, so later requests queue up behind it.
+    fn busy(queue: &ActionQueue, hold: Duration) -> ActionTicket {
+        let ticket = queue.submit(ActionRequest::new(
+            "busy",
+            vec![InputAction::Wait(hold), InputAction::Click { x: 0.0, y: 0.0 }],
+        ));
+        thread::sleep(Duration::from_millis(20));
+        ticket
+    }
+
+    fn cancelled(reason: &str) -> ActionOutcome {
+        ActionOutcome::Cancelled(reason.to_string())
+    }
+
+    #[test]
+    fn requests_are_spaced_apart() {
+        let (queue, backend) = queue();
+        let first = queue.submit(click("first", 1.0));
+        let second = queue.submit(click("second", 2.0));
+        assert_eq!(first.wait(), ActionOutcome::Done);
+        assert_eq!(second.wait(), ActionOutcome::Done);
+
+        let events = backend.events();
+        let released = events
+            .iter()
+            .find(|e| e.event == InputEvent::Button { button: Button::Left, pressed: false })
+            .unwrap()
+            .at;
+        let next_move = events
+            .iter()
+            .find(|e| e.event == InputEvent::MouseMove { x: 2.0, y: 0.0 })
+            .unwrap()
+            .at;
+        assert!(next_move - released >= SPACING, "only {:?} apart", next_move - released);
+        queue.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn equal_priorities_run_in_order_and_delayed_ones_let_others_pass() {
+        let (queue, backend) = queue();
+        let running = busy(&queue, Duration::from_millis(100));
+        let delayed = queue.submit(click("delayed", 1.0).after(Duration::from_millis(800)));
+        let a = queue.submit(click("a", 2.0));
+        let b = queue.submit(click("b", 3.0));
+
+        for ticket in [running, a, b, delayed] {
+            assert_eq!(ticket.wait(), ActionOutcome::Done);
+        }
+        assert_eq!(clicked(&backend), [0.0, 2.0, 3.0, 1.0]);
+        queue.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn emergency_cancels_running_and_queued_requests() {
+        let (queue, backend) = queue();
+        let running = busy(&queue, Duration::from_millis(100));
+        let queued = queue.submit(click("harvest", 1.0));
+        let emergency = queue.submit(click("stop", 2.0).priority(ActionPriority::Emergency));
+
+        assert_eq!(queued.wait(), cancelled("preempted by stop"));
+        assert_eq!(running.wait(), cancelled("cancelled"));
+        assert_eq!(emergency.wait(), ActionOutcome::Done);
+        assert_eq!(clicked(&backend), [2.0]);
+        queue.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn paused_queue_holds_requests_until_resumed() {
+        let (queue, backend) = queue();
+        queue.pause(PauseReason::UserActivity);
+        let ticket = queue.submit(click("click", 1.0));
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(ticket.try_outcome(), None);
+        assert!(backend.events().is_empty());
+
+        queue.resume(PauseReason::UserActivity);
+        assert_eq!(ticket.wait(), ActionOutcome::Done);
+        assert_eq!(clicked(&backend), [1.0]);
+        queue.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn emergency_stop_refuses_new_requests_until_resumed_by_hand() {
+        let (queue, backend) = queue();
+        queue.pause(PauseReason::EmergencyStop);
+        queue.pause(PauseReason::UserActivity);
+        assert_eq!(queue.paused(), Some(PauseReason::EmergencyStop));
+        assert_eq!(queue.submit(click("click", 1.0)).wait(), cancelled("paused: emergency stop"));
+
+        queue.resume(PauseReason::UserActivity);
+        assert_eq!(queue.paused(), Some(PauseReason::EmergencyStop));
+        queue.resume(PauseReason::EmergencyStop);
+        assert_eq!(queue.submit(click("click", 2.0)).wait(), ActionOutcome::Done);
+        assert_eq!(clicked(&backend), [2.0]);
+        queue.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cancelled_tickets_never_run() {
+        let (queue, backend) = queue();
+        queue.pause(PauseReason::UserActivity);
+        let dropped = queue.submit(click("dropped", 1.0));
+        let kept = queue.submit(click("kept", 2.0));
+        dropped.cancel();
+        queue.resume(PauseReason::UserActivity);
+
+        assert_eq!(dropped.wait(), cancelled("cancelled"));
+        assert_eq!(kept.wait(), ActionOutcome::Done);
+        assert_eq!(clicked(&backend), [2.0]);
+        queue.shutdown(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::action_queue::{ActionPriority, ActionQueue, ActionRequest, PauseReason};
+use crate::bot_engine::{send_log, LogLevel, LogMessage};
+use crate::input_backend::InjectionLog;
+use crate::keymap::KeyCombo;
+use crate::task::TaskRegistry;
+use crate::worker::Worker;
+use rdev::{listen, Event, EventType, Key};
+use std::collections::HashSet;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the idle timer is checked.
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+pub struct ActivityConfig {
+    /// Stops all automation until resumed by hand.
+    pub panic_hotkey: KeyCombo,
+    /// How long the user must leave mouse and keyboard alone before input resumes.
+    pub idle_resume: Duration,
+}
+
+impl Default for ActivityConfig {
+    fn default() -> Self {
+        Self {
+            panic_hotkey: KeyCombo {
+                modifiers: vec![Key::ControlLeft, Key::ShiftLeft],
+                key: Key::Escape,
+            },
+            idle_resume: Duration::from_secs(3),
+        }
+    }
+}
+
+/// Left and right modifiers are interchangeable in the hotkey.
+fn same_modifier(a: Key, b: Key) -> bool {
+    let side = |k: Key| match k {
+        Key::ControlRight => Key::ControlLeft,
+        Key::ShiftRight => Key::ShiftLeft,
+        Key::AltGr => Key::Alt,
+        Key::MetaRight => Key::MetaLeft,
+        other => other,
+    };
+    side(a) == side(b)
+}
+
+/// Watches the real mouse and keyboard. The panic hotkey cancels every task and all
+/// queued input and stops the queue; any other activity from the user pauses the queue until they've been
+/// idle for `idle_resume`. Events the bot injected itself are ignored.
+///
+/// There is a single OS listener per process; everything else interested in the user's
+/// input subscribes here. Cheap to clone.
+#[derive(Clone)]
+pub struct ActivityMonitor {
+    subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+    idle: Arc<Mutex<Option<Worker>>>,
+}
+
+impl ActivityMonitor {
+    /// Receives every mouse and keyboard event the user makes, but not the bot's own.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Stops the idle timer and waits up to `timeout` for it. The OS listener has no way
+    /// to be stopped and ends with the process.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.idle.lock().unwrap().take().is_none_or(|mut worker| worker.stop(timeout))
+    }
+
+    fn publish(&self, event: &Event) {
+        // Dropped receivers fall out here.
+        self.subscribers.lock().unwrap().retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+pub fn start(
+    config: ActivityConfig,
+    injected: Arc<InjectionLog>,
+    actions: ActionQueue,
+    tasks: TaskRegistry,
+    log_tx: Sender<LogMessage>,
+) -> ActivityMonitor {
+    let monitor = ActivityMonitor {
+        subscribers: Arc::new(Mutex::new(Vec::new())),
+        idle: Arc::new(Mutex::new(None)),
+    };
+    let last_activity = Arc::new(Mutex::new(None::<Instant>));
+    let idle_resume = config.idle_resume;
+
+    // 1. Listen for user input
+    {
+        let last_activity = Arc::clone(&last_activity);
+        let actions = actions.clone();
+        let log_tx = log_tx.clone();
+        let monitor = monitor.clone();
+        let mut held: HashSet<Key> = HashSet::new();
+        thread::spawn(move || {
+            let error_tx = log_tx.clone();
+            let result = listen(move |event| {
+                if injected.is_echo(&event.event_type) {
+                    return;
+                }
+                monitor.publish(&event);
+                match event.event_type {
+                    EventType::KeyPress(key) => {
+                        let hotkey = &config.panic_hotkey;
+                        let modifiers_held =
+                            hotkey.modifiers.iter().all(|m| held.iter().any(|h| same_modifier(*h, *m)));
+                        held.insert(key);
+                        if key == hotkey.key && modifiers_held {
+                            tasks.cancel_all();
+                            actions.submit(ActionRequest::new("emergency stop", Vec::new()).priority(ActionPriority::Emergency));
+                            actions.pause(PauseReason::EmergencyStop);
+                            send_log(&log_tx, "Emergency stop: all tasks and input cancelled. Resume by hand.", LogLevel::Error);
+                            return;
+                        }
+                    }
+                    EventType::KeyRelease(key) => {
+                        held.remove(&key);
+                    }
+                    _ => {}
+                }
+
+                *last_activity.lock().unwrap() = Some(Instant::now());
+                if actions.paused().is_none() {
+                    actions.pause(PauseReason::UserActivity);
+                    send_log(&log_tx, "User activity: input paused until mouse and keyboard are idle.", LogLevel::Warning);
+                }
+            });
+            if let Err(err) = result {
+                send_log(
+                    &error_tx,
+                    &format!("Cannot watch user input ({:?}); emergency stop and auto-pause are off.", err),
+                    LogLevel::Error,
+                );
+            }
+        });
+    }
+
+    // 2. Resume once the user has been idle long enough
+    let idle = Worker::spawn("idle-resume", move |token| {
+        while token.sleep(IDLE_POLL) {
+            let idle = last_activity.lock().unwrap().is_none_or(|t| t.elapsed() >= idle_resume);
+            if idle && actions.paused() == Some(PauseReason::UserActivity) {
+                actions.resume(PauseReason::UserActivity);
+                send_log(&log_tx, "User idle: input resumed.", LogLevel::Info);
+            }
+        }
+    });
+    *monitor.idle.lock().unwrap() = Some(idle);
+
+    monitor
+}
@@ -0,0 +1,978 @@
+use crate::action_queue::{ActionQueue, ActionRequest, ActionTicket, InputAction};
+use crate::bot_engine::LogLevel;
+use crate::detector::ScreenKind;
+use crate::expect::{Expectation, Verifier};
+use crate::game_state::{GameState, Observed};
+use crate::macros::Macro;
+use crate::safety::InputTarget;
+use crate::task::{Task, TaskContext, Tick};
+use crate::vision_engine::WindowGeometry;
+use crate::worker::Worker;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub const BEHAVIOR_DIR: &str = "./behaviors";
+/// How long a leaf with an `expect` parameter waits for it after each try.
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How many more times such a leaf sends its steps when the expectation doesn't show.
+const EXPECT_RETRIES: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Failure,
+    Running,
+}
+
+/// A question about the game state, as written in behavior files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "is", rename_all = "snake_case")]
+pub enum Check {
+    InCombat,
+    OnScreen { screen: ScreenKind },
+    WindowOpen { name: String },
+    /// HP at or below `fraction` of the maximum.
+    HpBelow { fraction: f32 },
+    /// Pods at or above `fraction` of the maximum.
+    PodsAbove { fraction: f32 },
+    /// A resource of `kind`, or of any kind, is in sight.
+    ResourceVisible {
+        #[serde(default)]
+        kind: Option<String>,
+    },
+    OnMap { x: i32, y: i32 },
+}
+
+impl Check {
+    pub fn holds(&self, state: &GameState) -> bool {
+        let ratio = |gauge: &Option<Observed<(u32, u32)>>| {
+            gauge
+                .as_ref()
+                .filter(|g| g.value.1 > 0)
+                .map(|g| g.value.0 as f32 / g.value.1 as f32)
+        };
+        match self {
+            Check::InCombat => state.in_combat(),
+            Check::OnScreen { screen } => state.screen() == *screen,
+            Check::WindowOpen { name } => state.is_window_open(name),
+            Check::HpBelow { fraction } => ratio(&state.hp).is_some_and(|hp| hp <= *fraction),
+            Check::PodsAbove { fraction } => ratio(&state.pods).is_some_and(|pods| pods >= *fraction),
+            Check::ResourceVisible { kind } => state
+                .resources
+                .as_ref()
+                .is_some_and(|r| r.value.iter().any(|s| kind.as_ref().is_none_or(|k| &s.kind == k))),
+            Check::OnMap { x, y } => state.map.as_ref().is_some_and(|m| m.value == (*x, *y)),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::InCombat => write!(f, "in combat"),
+            Check::OnScreen { screen } => write!(f, "on screen {:?}", screen),
+            Check::WindowOpen { name } => write!(f, "window {} open", name),
+            Check::HpBelow { fraction } => write!(f, "HP <= {:.0}%", fraction * 100.0),
+            Check::PodsAbove { fraction } => write!(f, "pods >= {:.0}%", fraction * 100.0),
+            Check::ResourceVisible { kind: Some(kind) } => write!(f, "{} in sight", kind),
+            Check::ResourceVisible { kind: None } => write!(f, "resource in sight"),
+            Check::OnMap { x, y } => write!(f, "on map [{},{}]", x, y),
+        }
+    }
+}
+
+/// A node as written in a behavior file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "node", rename_all = "snake_case")]
+pub enum NodeDef {
+    /// Runs its children in order; fails as soon as one fails.
+    Sequence { children: Vec<NodeDef> },
+    /// Tries its children in order until one doesn't fail.
+    Selector { children: Vec<NodeDef> },
+    /// Runs all its children together; succeeds once `success` of them have, all of
+    /// them by default.
+    Parallel {
+        children: Vec<NodeDef>,
+        #[serde(default)]
+        success: Option<usize>,
+    },
+    Invert { child: Box<NodeDef> },
+    /// Runs the child again after a failure, up to `times` more times.
+    Retry { times: u32, child: Box<NodeDef> },
+    /// Fails the child if it runs longer than `ms`.
+    Timeout { ms: u64, child: Box<NodeDef> },
+    /// Fails without running the child for `ms` after it last finished.
+    Cooldown { ms: u64, child: Box<NodeDef> },
+    Condition { check: Check },
+    /// A leaf from `Leaves`, such as `wait`, `key` or `macro`, with its parameters.
+    Action {
+        action: String,
+        #[serde(default)]
+        params: Value,
+    },
+}
+
+/// A behavior file under `./behaviors`, e.g.
+///
+/// ```json
+/// { "name": "farm", "repeat": true, "root": { "node": "selector", "children": [
+///     { "node": "sequence", "children": [
+///         { "node": "condition", "check": { "is": "in_combat" } },
+///         { "node": "action", "action": "macro", "params": { "name": "fight" } } ] },
+///     { "node": "action", "action": "macro", "params": { "name": "harvest" } },
+///     { "node": "action", "action": "macro", "params": { "name": "next_map" } } ] } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Behavior {
+    pub name: String,
+    /// Starts over when the tree finishes instead of ending the task.
+    #[serde(default)]
+    pub repeat: bool,
+    pub root: NodeDef,
+}
+
+impl Behavior {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read behavior {}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Failed to parse behavior {}: {}", path.display(), e))
+    }
+
+    /// Behavior files in `dir`, sorted by name.
+    pub fn list(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        paths.sort();
+        paths
+    }
+}
+
+/// What a leaf sees on each tick.
+pub struct TreeContext<'a> {
+    pub state: &'a GameState,
+    pub task: &'a TaskContext,
+}
+
+/// An action at the bottom of the tree. It returns `Running` while its work is in
+/// flight and starts over on the tick after it succeeds or fails.
+pub trait Leaf: Send {
+    fn tick(&mut self, ctx: &TreeContext) -> Status;
+
+    /// Drops work in flight when the tree moves on without waiting for it. Called on
+    /// idle leaves too.
+    fn halt(&mut self) {}
+}
+
+pub type LeafFactory = Box<dyn Fn(&Value) -> Result<Box<dyn Leaf>, String>>;
+
+/// Reads parameter `key` of an action.
+pub fn param<T: DeserializeOwned>(params: &Value, key: &str) -> Result<T, String> {
+    let value = params.get(key).ok_or_else(|| format!("missing parameter '{}'", key))?;
+    serde_json::from_value(value.clone()).map_err(|e| format!("bad parameter '{}': {}", key, e))
+}
+
+/// The actions a behavior file can name.
+pub struct Leaves {
+    factories: HashMap<String, LeafFactory>,
+}
+
+impl Leaves {
+    /// With the actions that need nothing from a client: `wait` and `log`.
+    pub fn builtin() -> Self {
+        let mut leaves = Self {
+            factories: HashMap::new(),
+        };
+        leaves.register("wait", |params| {
+            let ms: u64 = param(params, "ms")?;
+            Ok(Box::new(WaitLeaf {
+                duration: Duration::from_millis(ms),
+                started: None,
+            }))
+        });
+        leaves.register("log", |params| Ok(Box::new(LogLeaf { message: param(params, "message")? })));
+        leaves
+    }
+
+    pub fn register(&mut self, action: &str, factory: impl Fn(&Value) -> Result<Box<dyn Leaf>, String> + 'static) {
+        self.factories.insert(action.to_string(), Box::new(factory));
+    }
+
+    fn build(&self, action: &str, params: &Value) -> Result<Box<dyn Leaf>, String> {
+        let factory = self.factories.get(action).ok_or_else(|| format!("Unknown action '{}'", action))?;
+        factory(params).map_err(|err| format!("Action {}: {}", action, err))
+    }
+}
+
+enum Kind {
+    Sequence { children: Vec<Node>, current: usize },
+    Selector { children: Vec<Node> },
+    Parallel { children: Vec<Node>, success: usize, done: Vec<Option<Status>> },
+    Invert(Box<Node>),
+    Retry { child: Box<Node>, times: u32, failures: u32 },
+    Timeout { child: Box<Node>, limit: Duration, started: Option<Instant> },
+    Cooldown { child: Box<Node>, period: Duration, ready_at: Option<Instant> },
+    Condition(Check),
+    Action(Box<dyn Leaf>),
+}
+
+/// A built tree node with what it returned on its last tick, for the status dump.
+pub struct Node {
+    label: String,
+    kind: Kind,
+    last: Option<Status>,
+}
+
+impl Node {
+    pub fn build(def: &NodeDef, leaves: &Leaves) -> Result<Self, String> {
+        let all = |children: &[NodeDef]| children.iter().map(|c| Node::build(c, leaves)).collect::<Result<Vec<_>, _>>();
+        let one = |child: &NodeDef| Node::build(child, leaves).map(Box::new);
+        let (label, kind) = match def {
+            NodeDef::Sequence { children } => ("sequence".to_string(), Kind::Sequence { children: all(children)?, current: 0 }),
+            NodeDef::Selector { children } => ("selector".to_string(), Kind::Selector { children: all(children)? }),
+            NodeDef::Parallel { children, success } => {
+                let success = success.unwrap_or(children.len());
+                if success > children.len() {
+                    return Err(format!("Parallel needs {} successes but has {} children", success, children.len()));
+                }
+                let label = format!("parallel ({} of {})", success, children.len());
+                let done = vec![None; children.len()];
+                (label, Kind::Parallel { children: all(children)?, success, done })
+            }
+            NodeDef::Invert { child } => ("invert".to_string(), Kind::Invert(one(child)?)),
+            NodeDef::Retry { times, child } => (
+                format!("retry x{}", times),
+                Kind::Retry { child: one(child)?, times: *times, failures: 0 },
+            ),
+            NodeDef::Timeout { ms, child } => (
+                format!("timeout {}ms", ms),
+                Kind::Timeout { child: one(child)?, limit: Duration::from_millis(*ms), started: None },
+            ),
+            NodeDef::Cooldown { ms, child } => (
+                format!("cooldown {}ms", ms),
+                Kind::Cooldown { child: one(child)?, period: Duration::from_millis(*ms), ready_at: None },
+            ),
+            NodeDef::Condition { check } => (format!("if {}", check), Kind::Condition(check.clone())),
+            NodeDef::Action { action, params } => {
+                let label = if params.is_null() { action.clone() } else { format!("{} {}", action, params) };
+                (label, Kind::Action(leaves.build(action, params)?))
+            }
+        };
+        Ok(Self { label, kind, last: None })
+    }
+
+    pub fn tick(&mut self, ctx: &TreeContext) -> Status {
+        let status = self.tick_kind(ctx);
+        self.last = Some(status);
+        status
+    }
+
+    fn tick_kind(&mut self, ctx: &TreeContext) -> Status {
+        match &mut self.kind {
+            Kind::Sequence { children, current } => {
+                // Conditions already passed are checked again, so a guard that stops
+                // holding interrupts the action behind it.
+                let broken = children[..*current]
+                    .iter_mut()
+                    .any(|c| matches!(c.kind, Kind::Condition(_)) && c.tick(ctx) == Status::Failure);
+                if broken {
+                    children[*current].halt();
+                    *current = 0;
+                    return Status::Failure;
+                }
+                while *current < children.len() {
+                    match children[*current].tick(ctx) {
+                        Status::Success => *current += 1,
+                        Status::Running => return Status::Running,
+                        Status::Failure => {
+                            *current = 0;
+                            return Status::Failure;
+                        }
+                    }
+                }
+                *current = 0;
+                Status::Success
+            }
+            // Starts from the first child on every tick, so a more important branch
+            // takes over as soon as it can run.
+            Kind::Selector { children } => {
+                let mut result = Status::Failure;
+                for child in children.iter_mut() {
+                    if result != Status::Failure {
+                        if child.last == Some(Status::Running) {
+                            child.halt();
+                        }
+                        continue;
+                    }
+                    result = child.tick(ctx);
+                }
+                result
+            }
+            Kind::Parallel { children, success, done } => {
+                for (child, done) in children.iter_mut().zip(done.iter_mut()) {
+                    if done.is_none() && child.tick(ctx) != Status::Running {
+                        *done = child.last;
+                    }
+                }
+                let succeeded = done.iter().filter(|d| **d == Some(Status::Success)).count();
+                let failed = done.iter().filter(|d| **d == Some(Status::Failure)).count();
+                let status = if succeeded >= *success {
+                    Status::Success
+                } else if children.len() - failed < *success {
+                    Status::Failure
+                } else {
+                    Status::Running
+                };
+                if status != Status::Running {
+                    children.iter_mut().filter(|c| c.last == Some(Status::Running)).for_each(Node::halt);
+                    done.fill(None);
+                }
+                status
+            }
+            Kind::Invert(child) => match child.tick(ctx) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            Kind::Retry { child, times, failures } => match child.tick(ctx) {
+                Status::Failure if *failures < *times => {
+                    *failures += 1;
+                    ctx.task.log(&format!("Retrying {} ({}/{})", child.label, failures, times), LogLevel::Info);
+                    Status::Running
+                }
+                Status::Running => Status::Running,
+                status => {
+                    *failures = 0;
+                    status
+                }
+            },
+            Kind::Timeout { child, limit, started } => {
+                if started.get_or_insert_with(Instant::now).elapsed() >= *limit {
+                    ctx.task.log(&format!("{} timed out after {:?}", child.label, limit), LogLevel::Warning);
+                    child.halt();
+                    *started = None;
+                    return Status::Failure;
+                }
+                let status = child.tick(ctx);
+                if status != Status::Running {
+                    *started = None;
+                }
+                status
+            }
+            Kind::Cooldown { child, period, ready_at } => {
+                if ready_at.is_some_and(|t| Instant::now() < t) {
+                    return Status::Failure;
+                }
+                let status = child.tick(ctx);
+                if status != Status::Running {
+                    *ready_at = Some(Instant::now() + *period);
+                }
+                status
+            }
+            Kind::Condition(check) => {
+                if check.holds(ctx.state) {
+                    Status::Success
+                } else {
+                    Status::Failure
+                }
+            }
+            Kind::Action(leaf) => leaf.tick(ctx),
+        }
+    }
+
+    /// Interrupts whatever is running below this node and forgets its progress.
+    /// Cooldowns keep counting.
+    pub fn halt(&mut self) {
+        self.last = None;
+        match &mut self.kind {
+            Kind::Sequence { children, current } => {
+                *current = 0;
+                children.iter_mut().for_each(Node::halt);
+            }
+            Kind::Selector { children } => children.iter_mut().for_each(Node::halt),
+            Kind::Parallel { children, done, .. } => {
+                done.fill(None);
+                children.iter_mut().for_each(Node::halt);
+            }
+            Kind::Invert(child) | Kind::Cooldown { child, .. } => child.halt(),
+            Kind::Retry { child, failures, .. } => {
+                *failures = 0;
+                child.halt();
+            }
+            Kind::Timeout { child, started, .. } => {
+                *started = None;
+                child.halt();
+            }
+            Kind::Condition(_) => {}
+            Kind::Action(leaf) => leaf.halt(),
+        }
+    }
+
+    fn children(&self) -> Vec<&Node> {
+        match &self.kind {
+            Kind::Sequence { children, .. } | Kind::Selector { children } | Kind::Parallel { children, .. } => {
+                children.iter().collect()
+            }
+            Kind::Invert(child)
+            | Kind::Retry { child, .. }
+            | Kind::Timeout { child, .. }
+            | Kind::Cooldown { child, .. } => vec![child],
+            Kind::Condition(_) | Kind::Action(_) => Vec::new(),
+        }
+    }
+
+    /// The tree as indented text, each node marked with what it last returned:
+    /// ▶ running, ✔ succeeded, ✘ failed, · not reached.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(&mut out, 0);
+        out
+    }
+
+    fn dump_into(&self, out: &mut String, depth: usize) {
+        let mark = match self.last {
+            Some(Status::Running) => "▶",
+            Some(Status::Success) => "✔",
+            Some(Status::Failure) => "✘",
+            None => "·",
+        };
+        out.push_str(&format!("{}{} {}\n", "  ".repeat(depth), mark, self.label));
+        for child in self.children() {
+            child.dump_into(out, depth + 1);
+        }
+    }
+
+    /// Labels from here down to the deepest running node.
+    fn running_path(&self) -> Vec<&str> {
+        let mut path = vec![self.label.as_str()];
+        if let Some(child) = self.children().into_iter().find(|c| c.last == Some(Status::Running)) {
+            path.extend(child.running_path());
+        }
+        path
+    }
+}
+
+/// Runs a behavior tree as a task, one tree tick per task tick.
+pub struct BehaviorTask {
+    name: String,
+    repeat: bool,
+    root: Node,
+    state: watch::Receiver<GameState>,
+    /// Latest `Node::dump`, for the UI.
+    status: Arc<Mutex<String>>,
+}
+
+impl BehaviorTask {
+    pub fn new(
+        behavior: &Behavior,
+        leaves: &Leaves,
+        state: watch::Receiver<GameState>,
+        status: Arc<Mutex<String>>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            name: behavior.name.clone(),
+            repeat: behavior.repeat,
+            root: Node::build(&behavior.root, leaves)?,
+            state,
+            status,
+        })
+    }
+}
+
+impl Task for BehaviorTask {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn tick(&mut self, ctx: &TaskContext) -> Tick {
+        let state = self.state.borrow().clone();
+        let result = self.root.tick(&TreeContext { state: &state, task: ctx });
+        *self.status.lock().unwrap() = self.root.dump();
+        ctx.progress(None, &self.root.running_path().join(" > "));
+
+        match result {
+            Status::Running => Tick::Continue,
+            _ if self.repeat => {
+                self.root.halt();
+                Tick::Continue
+            }
+            Status::Success => Tick::Done,
+            Status::Failure => Tick::Failed("the tree failed".to_string()),
+        }
+    }
+
+    // Leaves are interrupted on pause; the tree starts over on resume.
+    fn pause(&mut self, _ctx: &TaskContext) {
+        self.root.halt();
+    }
+
+    fn cancel(&mut self, _ctx: &TaskContext) {
+        self.root.halt();
+    }
+}
+
+struct WaitLeaf {
+    duration: Duration,
+    started: Option<Instant>,
+}
+
+impl Leaf for WaitLeaf {
+    fn tick(&mut self, _ctx: &TreeContext) -> Status {
+        if self.started.get_or_insert_with(Instant::now).elapsed() < self.duration {
+            return Status::Running;
+        }
+        self.started = None;
+        Status::Success
+    }
+
+    fn halt(&mut self) {
+        self.started = None;
+    }
+}
+
+struct LogLeaf {
+    message: String,
+}
+
+impl Leaf for LogLeaf {
+    fn tick(&mut self, ctx: &TreeContext) -> Status {
+        ctx.task.log(&self.message, LogLevel::Info);
+        Status::Success
+    }
+}
+
+/// Sends `steps` to the target window through the action queue, running until the
+/// queue reports back.
+pub struct InputLeaf {
+    label: String,
+    steps: Vec<InputAction>,
+    target: InputTarget,
+    actions: ActionQueue,
+    ticket: Option<ActionTicket>,
+}
+
+impl InputLeaf {
+    pub fn new(label: &str, steps: Vec<InputAction>, target: InputTarget, actions: ActionQueue) -> Self {
+        Self {
+            label: label.to_string(),
+            steps,
+            target,
+            actions,
+            ticket: None,
+        }
+    }
+}
+
+impl Leaf for InputLeaf {
+    fn tick(&mut self, ctx: &TreeContext) -> Status {
+        let Some(ticket) = self.ticket.take() else {
+            let request = ActionRequest::new(&self.label, self.steps.clone()).target(self.target.clone());
+            self.ticket = Some(self.actions.submit(request));
+            return Status::Running;
+        };
+        match ticket.try_outcome().map(|outcome| outcome.into_result()) {
+            None => {
+                self.ticket = Some(ticket);
+                Status::Running
+            }
+            Some(Ok(())) => Status::Success,
+            Some(Err(err)) => {
+                ctx.task.log(&format!("{}: {}", self.label, err), LogLevel::Warning);
+                Status::Failure
+            }
+        }
+    }
+
+    fn halt(&mut self) {
+        if let Some(ticket) = self.ticket.take() {
+            ticket.cancel();
+        }
+    }
+}
+
+/// Where a leaf's worker thread leaves its result.
+type WorkerResult = Arc<Mutex<Option<Result<(), String>>>>;
+
+/// Like `InputLeaf`, but only succeeds once `expectation` shows on the frames that follow,
+/// sending the steps again if it doesn't.
+pub struct VerifiedLeaf {
+    label: String,
+    steps: Vec<InputAction>,
+    target: InputTarget,
+    expectation: Expectation,
+    verifier: Verifier,
+    sending: Option<(Worker, WorkerResult)>,
+}
+
+impl VerifiedLeaf {
+    pub fn new(label: &str, steps: Vec<InputAction>, target: InputTarget, expectation: Expectation, verifier: Verifier) -> Self {
+        Self {
+            label: label.to_string(),
+            steps,
+            target,
+            expectation,
+            verifier,
+            sending: None,
+        }
+    }
+}
+
+impl Leaf for VerifiedLeaf {
+    fn tick(&mut self, ctx: &TreeContext) -> Status {
+        let Some((worker, result)) = self.sending.take() else {
+            let result: WorkerResult = Arc::new(Mutex::new(None));
+            let (label, steps, target, expectation, verifier) = (
+                self.label.clone(),
+                self.steps.clone(),
+                self.target.clone(),
+                self.expectation.clone(),
+                self.verifier.clone(),
+            );
+            let out = Arc::clone(&result);
+            let worker = Worker::spawn(&self.label, move |token| {
+                let request = || Ok(ActionRequest::new(&label, steps.clone()).target(target.clone()));
+                let sent = verifier.act_and_expect(request, &expectation, EXPECT_TIMEOUT, EXPECT_RETRIES, &token);
+                *out.lock().unwrap() = Some(sent.map(|_| ()).map_err(|err| format!("{}: {}", label, err)));
+            });
+            self.sending = Some((worker, result));
+            return Status::Running;
+        };
+        if worker.is_running() {
+            self.sending = Some((worker, result));
+            return Status::Running;
+        }
+        match result.lock().unwrap().take() {
+            Some(Ok(())) => Status::Success,
+            Some(Err(err)) => {
+                ctx.task.log(&err, LogLevel::Warning);
+                Status::Failure
+            }
+            None => Status::Failure,
+        }
+    }
+
+    fn halt(&mut self) {
+        if let Some((worker, _)) = self.sending.take() {
+            worker.cancel();
+        }
+    }
+}
+
+/// An `InputLeaf` sending `steps`, or a `VerifiedLeaf` if the action has an `expect`
+/// parameter.
+pub fn input_leaf(
+    label: &str,
+    steps: Vec<InputAction>,
+    params: &Value,
+    target: &InputTarget,
+    actions: &ActionQueue,
+    verifier: &Verifier,
+) -> Result<Box<dyn Leaf>, String> {
+    if params.get("expect").is_none() {
+        return Ok(Box::new(InputLeaf::new(label, steps, target.clone(), actions.clone())));
+    }
+    let expectation = param(params, "expect")?;
+    Ok(Box::new(VerifiedLeaf::new(label, steps, target.clone(), expectation, verifier.clone())))
+}
+
+/// Plays a macro on its own thread, running until it's done.
+pub struct MacroLeaf {
+    recorded: Arc<Macro>,
+    actions: ActionQueue,
+    target: InputTarget,
+    geometry: Arc<Mutex<Option<WindowGeometry>>>,
+    state: watch::Receiver<GameState>,
+    playing: Option<(Worker, WorkerResult)>,
+}
+
+impl MacroLeaf {
+    pub fn new(
+        recorded: Macro,
+        actions: ActionQueue,
+        target: InputTarget,
+        geometry: Arc<Mutex<Option<WindowGeometry>>>,
+        state: watch::Receiver<GameState>,
+    ) -> Self {
+        Self {
+            recorded: Arc::new(recorded),
+            actions,
+            target,
+            geometry,
+            state,
+            playing: None,
+        }
+    }
+}
+
+impl Leaf for MacroLeaf {
+    fn tick(&mut self, ctx: &TreeContext) -> Status {
+        let Some((worker, result)) = self.playing.take() else {
+            let result: WorkerResult = Arc::new(Mutex::new(None));
+            let (recorded, actions, target, geometry, state) = (
+                Arc::clone(&self.recorded),
+                self.actions.clone(),
+                self.target.clone(),
+                Arc::clone(&self.geometry),
+                self.state.clone(),
+            );
+            let out = Arc::clone(&result);
+            let worker = Worker::spawn(&format!("macro {}", recorded.name), move |token| {
+                *out.lock().unwrap() = Some(recorded.play(&actions, &target, &geometry, &state, &token));
+            });
+            self.playing = Some((worker, result));
+            return Status::Running;
+        };
+        if worker.is_running() {
+            self.playing = Some((worker, result));
+            return Status::Running;
+        }
+        match result.lock().unwrap().take() {
+            Some(Ok(())) => Status::Success,
+            Some(Err(err)) => {
+                ctx.task.log(&err, LogLevel::Warning);
+                Status::Failure
+            }
+            None => Status::Failure,
+        }
+    }
+
+    fn halt(&mut self) {
+        // Cancelling the worker's token cancels the step it is waiting on in the queue,
+        // so nothing more reaches the window once the tree has moved on.
+        if let Some((worker, _)) = self.playing.take() {
+            worker.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::mpsc;
+
+    /// Stub leaves named by their `name` parameter. Each returns what the test last set
+    /// for it, `Running` by default, and records its ticks and the halts that interrupt it.
+    #[derive(Clone, Default)]
+    struct Stubs {
+        statuses: Arc<Mutex<HashMap<String, Status>>>,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct StubLeaf {
+        name: String,
+        stubs: Stubs,
+        running: bool,
+    }
+
+    impl Leaf for StubLeaf {
+        fn tick(&mut self, _ctx: &TreeContext) -> Status {
+            self.stubs.events.lock().unwrap().push(format!("tick {}", self.name));
+            let status = self.stubs.statuses.lock().unwrap().get(&self.name).copied().unwrap_or(Status::Running);
+            self.running = status == Status::Running;
+            status
+        }
+
+        fn halt(&mut self) {
+            if self.running {
+                self.stubs.events.lock().unwrap().push(format!("halt {}", self.name));
+                self.running = false;
+            }
+        }
+    }
+
+    impl Stubs {
+        fn build(&self, def: Value) -> Node {
+            let mut leaves = Leaves::builtin();
+            let stubs = self.clone();
+            leaves.register("stub", move |params| {
+                Ok(Box::new(StubLeaf {
+                    name: param(params, "name")?,
+                    stubs: stubs.clone(),
+                    running: false,
+                }))
+            });
+            Node::build(&serde_json::from_value(def).unwrap(), &leaves).unwrap()
+        }
+
+        fn set(&self, name: &str, status: Status) {
+            self.statuses.lock().unwrap().insert(name.to_string(), status);
+        }
+
+        /// Events since the last call.
+        fn events(&self) -> Vec<String> {
+            std::mem::take(&mut *self.events.lock().unwrap())
+        }
+    }
+
+    fn stub(name: &str) -> Value {
+        json!({ "node": "action", "action": "stub", "params": { "name": name } })
+    }
+
+    fn in_combat() -> Value {
+        json!({ "node": "condition", "check": { "is": "in_combat" } })
+    }
+
+    fn combat(value: bool) -> GameState {
+        GameState {
+            in_combat: Some(Observed {
+                value,
+                confidence: 1.0,
+                seen_at: Instant::now(),
+                frame_seq: 0,
+            }),
+            ..GameState::default()
+        }
+    }
+
+    fn tick(node: &mut Node, state: &GameState) -> Status {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let task = TaskContext::detached("tree", log_tx);
+        node.tick(&TreeContext { state, task: &task })
+    }
+
+    #[test]
+    fn sequence_resumes_at_the_running_child_and_rechecks_its_guards() {
+        let stubs = Stubs::default();
+        let mut tree = stubs.build(json!({ "node": "sequence", "children": [in_combat(), stub("a"), stub("b")] }));
+        let fighting = combat(true);
+
+        assert_eq!(tick(&mut tree, &fighting), Status::Running);
+        stubs.set("a", Status::Success);
+        assert_eq!(tick(&mut tree, &fighting), Status::Running);
+        assert_eq!(tick(&mut tree, &fighting), Status::Running);
+        // "a" isn't run again once it succeeded; "b" is picked up where it was.
+        assert_eq!(stubs.events(), ["tick a", "tick a", "tick b", "tick b"]);
+
+        // The guard stops holding: "b" is interrupted and the sequence starts over.
+        assert_eq!(tick(&mut tree, &combat(false)), Status::Failure);
+        assert_eq!(stubs.events(), ["halt b"]);
+        assert_eq!(tick(&mut tree, &fighting), Status::Running);
+        assert_eq!(stubs.events(), ["tick a", "tick b"]);
+    }
+
+    #[test]
+    fn selector_hands_over_to_a_more_important_branch() {
+        let stubs = Stubs::default();
+        let mut tree = stubs.build(json!({ "node": "selector", "children": [
+            { "node": "sequence", "children": [in_combat(), stub("fight")] },
+            stub("farm"),
+        ] }));
+
+        assert_eq!(tick(&mut tree, &combat(false)), Status::Running);
+        assert_eq!(stubs.events(), ["tick farm"]);
+
+        // A fight starts: farming is interrupted for it.
+        assert_eq!(tick(&mut tree, &combat(true)), Status::Running);
+        assert_eq!(stubs.events(), ["tick fight", "halt farm"]);
+
+        // It ends: the fight is interrupted and farming starts again.
+        assert_eq!(tick(&mut tree, &combat(false)), Status::Running);
+        assert_eq!(stubs.events(), ["halt fight", "tick farm"]);
+    }
+
+    #[test]
+    fn parallel_stops_once_its_threshold_is_reached_or_out_of_reach() {
+        let stubs = Stubs::default();
+        let mut tree = stubs.build(json!({ "node": "parallel", "success": 2, "children": [stub("a"), stub("b"), stub("c")] }));
+        let state = GameState::default();
+
+        stubs.set("a", Status::Success);
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+        stubs.set("b", Status::Success);
+        assert_eq!(tick(&mut tree, &state), Status::Success);
+        // "a" isn't ticked again once done, and "c" is interrupted.
+        assert_eq!(stubs.events(), ["tick a", "tick b", "tick c", "tick b", "tick c", "halt c"]);
+
+        // Two failures out of three leave one possible success: not enough.
+        stubs.set("a", Status::Failure);
+        stubs.set("b", Status::Failure);
+        assert_eq!(tick(&mut tree, &state), Status::Failure);
+        assert_eq!(stubs.events(), ["tick a", "tick b", "tick c", "halt c"]);
+    }
+
+    #[test]
+    fn retry_runs_the_child_again_until_out_of_attempts() {
+        let stubs = Stubs::default();
+        let mut tree = stubs.build(json!({ "node": "retry", "times": 2, "child": stub("a") }));
+        let state = GameState::default();
+
+        stubs.set("a", Status::Failure);
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+        assert_eq!(tick(&mut tree, &state), Status::Failure);
+        assert_eq!(stubs.events().len(), 3);
+
+        // The count starts over after giving up.
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+        stubs.set("a", Status::Success);
+        assert_eq!(tick(&mut tree, &state), Status::Success);
+    }
+
+    #[test]
+    fn timeout_interrupts_a_child_that_runs_too_long() {
+        let stubs = Stubs::default();
+        let mut tree = stubs.build(json!({ "node": "timeout", "ms": 30, "child": stub("a") }));
+        let state = GameState::default();
+
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(tick(&mut tree, &state), Status::Failure);
+        assert_eq!(stubs.events(), ["tick a", "halt a"]);
+
+        // The clock starts again on the next run.
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+    }
+
+    #[test]
+    fn cooldown_fails_without_running_the_child_until_it_has_passed() {
+        let stubs = Stubs::default();
+        let mut tree = stubs.build(json!({ "node": "cooldown", "ms": 50, "child": stub("a") }));
+        let state = GameState::default();
+
+        stubs.set("a", Status::Success);
+        assert_eq!(tick(&mut tree, &state), Status::Success);
+        assert_eq!(tick(&mut tree, &state), Status::Failure);
+        // Halting doesn't reset it.
+        tree.halt();
+        assert_eq!(tick(&mut tree, &state), Status::Failure);
+        assert_eq!(stubs.events(), ["tick a"]);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(tick(&mut tree, &state), Status::Success);
+    }
+
+    #[test]
+    fn halt_interrupts_running_leaves_and_forgets_progress() {
+        let stubs = Stubs::default();
+        let mut tree = stubs.build(json!({ "node": "sequence", "children": [
+            stub("a"),
+            { "node": "parallel", "children": [stub("b"), stub("c")] },
+        ] }));
+        let state = GameState::default();
+
+        stubs.set("a", Status::Success);
+        stubs.set("c", Status::Success);
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+        assert_eq!(stubs.events(), ["tick a", "tick b", "tick c"]);
+
+        tree.halt();
+        assert_eq!(stubs.events(), ["halt b"]);
+        assert!(tree.dump().lines().all(|line| line.trim_start().starts_with('·')));
+
+        // Starts over from the first child.
+        assert_eq!(tick(&mut tree, &state), Status::Running);
+        assert_eq!(stubs.events(), ["tick a", "tick b", "tick c"]);
+    }
+}
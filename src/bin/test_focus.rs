@@ -1,4 +1,10 @@
-use std::process::Command;
+#[allow(dead_code)]
+#[path = "../focus.rs"]
+mod focus;
+#[allow(dead_code)]
+#[path = "../input_backend.rs"]
+mod input_backend;
+
 use std::thread;
 use std::time::Duration;
 use screencapturekit::shareable_content::SCShareableContent;
@@ -23,35 +29,19 @@ fn main() {
         let pid = window.owning_application().process_id();
         println!("Found Dofus window: {} (PID: {})", window.title(), pid);
 
-        let focus_pid = |p: i32| {
-            let script = format!(
-                "tell application \"System Events\" to set frontmost of first process whose unix id is {} to true",
-                p
-            );
-            let output = Command::new("osascript")
-                .arg("-e")
-                .arg(script)
-                .output();
-
-            match output {
-                Ok(out) => {
-                    if !out.status.success() {
-                        eprintln!("AppleScript error: {}", String::from_utf8_lossy(&out.stderr));
-                    }
-                }
-                Err(e) => eprintln!("Failed to run osascript: {}", e),
-            }
+        let backend = focus::native();
+        let focus_pid = |p: i32| match focus::focus_and_verify(backend.as_ref(), p) {
+            Ok(()) => println!("PID {} is frontmost.", p),
+            Err(e) => eprintln!("{}", e),
         };
 
         println!("Focusing Dofus in 5 seconds...");
         thread::sleep(Duration::from_secs(5));
         focus_pid(pid);
-        println!("Dofus focused.");
 
         println!("Focusing this test program in 5 seconds...");
         thread::sleep(Duration::from_secs(5));
         focus_pid(std::process::id() as i32);
-        println!("Test program focused.");
 
         println!("Test complete.");
     } else {
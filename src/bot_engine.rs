@@ -1,11 +1,116 @@
-use crate::input_manager::InputManager;
-use crate::vision_engine::VisionEngine;
-use std::sync::mpsc::Sender;
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
-use chrono::Local;
-use image::RgbaImage;
+use crate::action_queue::{ActionQueue, ActionRequest, InputAction};
+use crate::activity_monitor::ActivityMonitor;
+use crate::behavior::{self, Behavior, BehaviorTask, Leaves, MacroLeaf};
+use crate::coords::{CoordTransform, FramePoint, ScreenLayout, ScreenPoint};
+use crate::detector::{Detection, DetectorRunner, FrameReport, WorkerPool};
+use crate::detectors;
+use crate::dry_run::DryRun;
+use crate::expect::Verifier;
+use crate::game_state::GameState;
+use crate::input_backend::InputError;
+use crate::keymap::{GameAction, Keymap};
+use crate::macros::{self, Macro, MacroRecorder};
+use crate::safety::InputTarget;
+use crate::session::SharedResources;
+use crate::stream_watchdog::StreamHealth;
+use crate::scheduler::{conditions, Scheduler, TaskPriority};
+use crate::tasks::{FocusTest, Harvest, HoldWhile, MissionProof};
+use crate::vision_engine::{TargetWindow, VisionEngine, VisionEvent, WindowGeometry};
+use crate::vision_pipeline::TemplateLibrary;
+use crate::worker::Worker;
+use crate::window_selector::WindowCandidate;
+use crate::world_model::WorldModel;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{Local, Utc};
+use tokio::sync::watch;
+
+pub fn send_log(tx: &Sender<LogMessage>, message: &str, level: LogLevel) {
+    let timestamp = Local::now().format("%H:%M:%S").to_string();
+    let _ = tx.send(LogMessage {
+        timestamp,
+        level,
+        message: message.to_string(),
+    });
+}
+
+/// Feeds the resources seen on this frame into the world model, as long as we know
+/// which map they're on and every resource detector ran on it. A detector that was
+/// skipped or late would make its nodes look gone.
+fn record_resources(world: &mut WorldModel, state: &GameState, report: &FrameReport) {
+    let Some(map) = state.map.as_ref().filter(|m| m.is_fresh(Duration::from_secs(2))) else {
+        return;
+    };
+    let incomplete = report
+        .skipped
+        .iter()
+        .chain(&report.late)
+        .any(|name| name.starts_with(detectors::RESOURCE_PREFIX));
+    if incomplete {
+        return;
+    }
+
+    let mut sightings = Vec::new();
+    let mut any = false;
+    for output in &report.outputs {
+        if let Detection::Resources(seen) = &output.detection {
+            sightings.extend(seen.iter().cloned());
+            any = true;
+        }
+    }
+    if any {
+        world.record_sightings(map.value, report.frame_size, &sightings, Utc::now().timestamp());
+    }
+}
+
+/// Clicks inside a captured window, `pos` being relative to its size.
+pub fn click_in_window(
+    actions: &ActionQueue,
+    target: &Mutex<Option<TargetWindow>>,
+    geometry: &Arc<Mutex<Option<WindowGeometry>>>,
+    pos: (f32, f32),
+) -> Result<(), InputError> {
+    let pid = target.lock().unwrap().as_ref().map(|t| t.pid).ok_or(InputError::TargetGone)?;
+    let window = geometry.lock().unwrap().ok_or(InputError::TargetGone)?;
+    let transform = CoordTransform::new(window, window.capture_size());
+    let target = InputTarget::new(pid, Arc::clone(geometry));
+    let point = transform.window_to_screen(transform.relative_to_window(pos));
+    let request = screen_click(target, &transform, point)?;
+    actions.run(request)
+}
+
+/// Focus-and-click request for `screen`, refusing points outside the window or off every display.
+fn screen_click(target: InputTarget, transform: &CoordTransform, screen: ScreenPoint) -> Result<ActionRequest, InputError> {
+    let visible = ScreenLayout::current().map(|layout| layout.is_visible(screen)).unwrap_or(true);
+    if !transform.contains(transform.screen_to_window(screen)) || !visible {
+        return Err(InputError::OutOfBounds { x: screen.x, y: screen.y });
+    }
+    Ok(ActionRequest::new(
+        "click",
+        vec![InputAction::Focus(target.pid), InputAction::Click { x: screen.x, y: screen.y }],
+    )
+    .target(target))
+}
+
+/// Focuses the client with `pid` and triggers a game shortcut.
+fn shortcut_steps(keymap: &Keymap, pid: i32, action: GameAction) -> Result<Vec<InputAction>, String> {
+    let combo = keymap.combo(action).cloned().ok_or_else(|| format!("no key bound to {:?}", action))?;
+    Ok(vec![InputAction::Focus(pid), InputAction::Combo(combo)])
+}
+
+/// Focuses the client with `pid` and sends a chat line, such as `/travel 5,-18` or a
+/// trade message.
+fn chat_steps(keymap: &Keymap, pid: i32, message: String) -> Result<Vec<InputAction>, String> {
+    let chat = keymap.combo(GameAction::Chat).cloned().ok_or("no key bound to Chat")?;
+    Ok(vec![
+        InputAction::Focus(pid),
+        InputAction::Combo(chat.clone()),
+        InputAction::Type { text: message, layout: keymap.layout },
+        InputAction::Combo(chat),
+    ])
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum LogLevel {
@@ -21,144 +126,465 @@ pub struct LogMessage {
     pub message: String,
 }
 
+/// Time allowed for all detectors on one frame before low-priority work is shed.
+const FRAME_BUDGET: Duration = Duration::from_millis(50);
+/// How often the vision event logger checks whether it was cancelled.
+const EVENT_POLL: Duration = Duration::from_millis(250);
+/// `window_<name>` template of the whisper tab.
+const WHISPER_WINDOW: &str = "whisper";
+/// Share of the maximum pods at which the inventory counts as full.
+const INVENTORY_FULL: f32 = 0.95;
+/// How often the world model is flushed to disk while it has unsaved changes.
+const WORLD_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the scheduled Mission Proof runs.
+const PROOF_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// Name of the recurring Mission Proof schedule.
+const PROOF_SCHEDULE: &str = "mission proof";
+
+/// Everything driving one Dofus client: its capture stream, detectors, perception
+/// state, input and log stream.
 pub struct BotEngine {
+    /// Name of the session this engine drives.
+    pub name: String,
     pub vision: VisionEngine,
-    pub input: InputManager,
+    /// Shared with every session, so clients never receive interleaved input.
+    pub actions: ActionQueue,
+    pub templates: Arc<TemplateLibrary>,
+    pub last_report: Arc<Mutex<Option<FrameReport>>>,
+    state_tx: Arc<watch::Sender<GameState>>,
+    pub world: Arc<Mutex<WorldModel>>,
+    pub keymap: Arc<Keymap>,
+    pub dry_run: Arc<DryRun>,
+    pool: Arc<WorkerPool>,
+    monitor: ActivityMonitor,
+    recorder: Mutex<Option<MacroRecorder>>,
+    /// Macro being played, if any.
+    playback: Mutex<Option<Worker>>,
+    /// Detector runner and vision event logger.
+    background: Vec<Worker>,
+    /// Status dump of the last behavior tree ticked.
+    behavior_status: Arc<Mutex<String>>,
+    /// Runs this client's tasks, one in the foreground at a time.
+    pub scheduler: Scheduler,
     log_tx: Sender<LogMessage>,
 }
 
 impl BotEngine {
-    pub fn new(log_tx: Sender<LogMessage>) -> Self {
-        Self {
+    pub fn new(name: &str, log_tx: Sender<LogMessage>, shared: &SharedResources) -> Self {
+        let state_tx = Arc::new(watch::channel(GameState::default()).0);
+        let mut engine = Self {
+            name: name.to_string(),
             vision: VisionEngine::new(),
-            input: InputManager::new(),
+            actions: shared.actions.clone(),
+            templates: Arc::clone(&shared.templates),
+            last_report: Arc::new(Mutex::new(None)),
+            scheduler: Scheduler::start(name, shared.tasks.clone(), state_tx.subscribe(), log_tx.clone()),
+            state_tx,
+            world: Arc::clone(&shared.world),
+            keymap: Arc::clone(&shared.keymap),
+            dry_run: Arc::clone(&shared.dry_run),
+            pool: Arc::clone(&shared.pool),
+            monitor: shared.monitor.clone(),
+            recorder: Mutex::new(None),
+            playback: Mutex::new(None),
+            background: Vec::new(),
+            behavior_status: Arc::new(Mutex::new(String::new())),
             log_tx,
+        };
+
+        engine.background = vec![engine.start_detectors(), engine.forward_vision_events()];
+        engine.register_triggers();
+        engine
+    }
+
+    /// Reactions to the game that preempt whatever this client is doing and hold it
+    /// until the user has dealt with them.
+    fn register_triggers(&self) {
+        let state = self.subscribe_state();
+        self.scheduler.on(
+            "combat started",
+            TaskPriority::Critical,
+            conditions::combat_started(),
+            Box::new(move || {
+                let message = "Fight started: play it by hand, the bot resumes afterwards.";
+                Box::new(HoldWhile::new("Fight", message, state.clone(), conditions::combat_started()))
+            }),
+        );
+
+        let state = self.subscribe_state();
+        self.scheduler.on(
+            "whisper received",
+            TaskPriority::Reactive,
+            conditions::window_open(WHISPER_WINDOW),
+            Box::new(move || {
+                let message = "Whisper received: close it once answered to resume.";
+                Box::new(HoldWhile::new("Whisper", message, state.clone(), conditions::window_open(WHISPER_WINDOW)))
+            }),
+        );
+
+        let state = self.subscribe_state();
+        self.scheduler.on(
+            "inventory full",
+            TaskPriority::Reactive,
+            conditions::inventory_full(INVENTORY_FULL),
+            Box::new(move || {
+                let message = "Inventory full: empty it to resume.";
+                Box::new(HoldWhile::new("Inventory full", message, state.clone(), conditions::inventory_full(INVENTORY_FULL)))
+            }),
+        );
+    }
+
+    /// Reports window and stream events in the log.
+    fn forward_vision_events(&self) -> Worker {
+        let events = self.vision.subscribe_events();
+        let log_tx = self.log_tx.clone();
+        Worker::spawn(&format!("vision-events-{}", self.name), move |token| {
+            while !token.is_cancelled() {
+                let event = match events.recv_timeout(EVENT_POLL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                match event {
+                    VisionEvent::WindowGeometryChanged { old, new } => {
+                        let msg = format!(
+                            "Dofus window changed: {}x{} at ({}, {}) -> {}x{} at ({}, {})",
+                            old.width, old.height, old.x, old.y, new.width, new.height, new.x, new.y
+                        );
+                        send_log(&log_tx, &msg, LogLevel::Info);
+                    }
+                    VisionEvent::HealthChanged { old, new, reason } => {
+                        let level = match new {
+                            StreamHealth::Active | StreamHealth::Static => LogLevel::Success,
+                            StreamHealth::Dead { .. } => LogLevel::Error,
+                            _ => LogLevel::Warning,
+                        };
+                        send_log(&log_tx, &format!("Stream: {} -> {} ({})", old, new, reason), level);
+                    }
+                    VisionEvent::TargetReplaced { old, new } => {
+                        let msg = format!(
+                            "Dofus window reappeared: {} (id {}) -> {} (id {}, PID {})",
+                            old.title, old.window_id, new.title, new.window_id, new.pid
+                        );
+                        send_log(&log_tx, &msg, LogLevel::Success);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Runs the detectors on every captured frame, off the UI thread.
+    fn start_detectors(&self) -> Worker {
+        let mut runner = DetectorRunner::new(Arc::clone(&self.pool), FRAME_BUDGET);
+        for detector in detectors::default_detectors(&self.templates) {
+            runner.add(detector);
         }
+
+        let last_report = Arc::clone(&self.last_report);
+        let state_tx = Arc::clone(&self.state_tx);
+        let world = Arc::clone(&self.world);
+        let log_tx = self.log_tx.clone();
+        let mut last_save = Instant::now();
+        runner.spawn(self.vision.subscribe_frames(), move |report| {
+            state_tx.send_modify(|state| state.apply(&report));
+
+            if let Ok(mut world) = world.lock() {
+                record_resources(&mut world, &state_tx.borrow(), &report);
+                if world.is_dirty() && last_save.elapsed() >= WORLD_SAVE_INTERVAL {
+                    last_save = Instant::now();
+                    if let Err(err) = world.save() {
+                        send_log(&log_tx, &err, LogLevel::Error);
+                    }
+                }
+            }
+
+            if let Ok(mut last) = last_report.lock() {
+                *last = Some(report);
+            }
+        })
+    }
+
+    /// Receives the perception snapshot every time a frame has been processed.
+    pub fn subscribe_state(&self) -> watch::Receiver<GameState> {
+        self.state_tx.subscribe()
+    }
+
+    /// Current perception snapshot.
+    pub fn state(&self) -> GameState {
+        self.state_tx.borrow().clone()
     }
 
     pub fn log(&self, message: &str, level: LogLevel) {
-        let timestamp = Local::now().format("%H:%M:%S").to_string();
-        let _ = self.log_tx.send(LogMessage {
-            timestamp,
-            level,
-            message: message.to_string(),
-        });
+        send_log(&self.log_tx, message, level);
     }
 
-    pub fn scan_for_window(&mut self) {
-        self.log("Scanning for Dofus window...", LogLevel::Info);
-        match self.vision.find_dofus_window() {
+    pub fn select_window(&mut self, candidate: &WindowCandidate) {
+        match self.vision.select_window(candidate) {
             Ok(msg) => self.log(&msg, LogLevel::Success),
             Err(err) => self.log(&err, LogLevel::Warning),
         }
     }
 
-    pub fn trigger_mission_proof(&self) {
-        let tx = self.log_tx.clone();
-        let vision_pid = self.vision.target_window_pid;
-        let bot_pid = std::process::id() as i32;
-        let latest_frame = Arc::clone(&self.vision.latest_frame);
-        let frame_size = Arc::clone(&self.vision.frame_size);
-        let input = InputManager::new();
-
-        self.log("Mission Proof requested. Processing async...", LogLevel::Info);
-
-        thread::spawn(move || {
-            let log = |msg: &str, level: LogLevel| {
-                let timestamp = Local::now().format("%H:%M:%S").to_string();
-                let _ = tx.send(LogMessage {
-                    timestamp,
-                    level,
-                    message: msg.to_string(),
-                });
-            };
-
-            if let Some(p) = vision_pid {
-                // 1. Focus Dofus
-                input.focus_window(p);
-
-                // 2. Wait 250ms
-                thread::sleep(Duration::from_millis(250));
-
-                // 3. Pull the current frame
-                let (data, size) = {
-                    let data = latest_frame.lock().unwrap().clone();
-                    let size = *frame_size.lock().unwrap();
-                    (data, size)
-                };
+    /// Request clicking a pixel of the captured frame, so detector output can be acted on
+    /// directly. Dropped if the screen changes before the click comes up.
+    pub fn frame_click(&self, point: FramePoint) -> Result<ActionRequest, InputError> {
+        let target = self.input_target()?;
+        let transform = self.vision.transform().ok_or(InputError::TargetGone)?;
+        let request = screen_click(target, &transform, transform.frame_to_screen(point))?;
+        Ok(request.guard(self.screen_guard()))
+    }
 
-                if data.is_empty() || size.0 == 0 || size.1 == 0 {
-                    log("Mission Proof failed: No frame captured yet.", LogLevel::Error);
-                    return;
-                }
+    /// Runs actions of this client and checks their effect on its frames.
+    pub fn verifier(&self) -> Verifier {
+        Verifier::new(
+            self.actions.clone(),
+            self.vision.frame_feed(),
+            self.subscribe_state(),
+            Arc::clone(&self.templates),
+            self.log_tx.clone(),
+        )
+    }
 
-                // 4. Save it
-                let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
-                let dir = "./mission_logs";
-                if let Err(e) = std::fs::create_dir_all(dir) {
-                    log(&format!("Failed to create directory: {}", e), LogLevel::Error);
-                    return;
-                }
+    /// The captured client, as the safety guard checks it.
+    fn input_target(&self) -> Result<InputTarget, InputError> {
+        let pid = self.vision.target_window_pid().ok_or(InputError::TargetGone)?;
+        Ok(InputTarget::new(pid, Arc::clone(&self.vision.geometry)))
+    }
 
-                let path = format!("{}/proof_{}.png", dir, timestamp);
-                if let Some(img) = RgbaImage::from_raw(size.0, size.1, data) {
-                    match img.save(&path) {
-                        Ok(_) => log(&format!("Mission Proof saved: {}", path), LogLevel::Success),
-                        Err(e) => log(&format!("Failed to save image: {}", e), LogLevel::Error),
-                    }
-                } else {
-                    log("Failed to create image from raw data.", LogLevel::Error);
-                }
+    /// Holds while the game stays on the screen it shows now.
+    pub fn screen_guard(&self) -> impl Fn() -> bool + Send + 'static {
+        let state = self.subscribe_state();
+        let screen = state.borrow().screen();
+        move || state.borrow().screen() == screen
+    }
 
-                // 5. Focus back to Bot
-                input.focus_window(bot_pid);
-            } else {
-                log("Mission Proof failed: Dofus window not found. Scan first.", LogLevel::Warning);
-            }
-        });
+    /// Starts a Mission Proof task: focus Dofus, save the frame, focus the bot again.
+    pub fn trigger_mission_proof(&self) {
+        let Some(pid) = self.vision.target_window_pid() else {
+            self.log("Mission Proof failed: Dofus window not found. Scan first.", LogLevel::Warning);
+            return;
+        };
+        let task = MissionProof::new(pid, self.verifier(), self.actions.clone());
+        self.scheduler.submit(TaskPriority::Normal, Box::new(task), "requested by the user");
     }
 
-    pub fn focus_dofus(&self) {
-        if let Some(pid) = self.vision.target_window_pid {
-            self.log(&format!("Focusing Dofus window (PID: {}) natively...", pid), LogLevel::Info);
-            self.input.focus_window(pid);
-        } else {
-            self.log("Cannot focus Dofus: Window not found.", LogLevel::Warning);
+    /// Whether a Mission Proof is queued or running, so it isn't started twice.
+    pub fn mission_proof_busy(&self) -> bool {
+        self.scheduler.is_busy(MissionProof::NAME)
+    }
+
+    /// Starts or stops taking a Mission Proof every `PROOF_INTERVAL`, the first one right
+    /// away. Each one pauses a running behavior until it's done.
+    pub fn schedule_mission_proof(&self, enabled: bool) {
+        if !enabled {
+            if self.scheduler.cancel_every(PROOF_SCHEDULE) {
+                self.log("Scheduled Mission Proof stopped", LogLevel::Info);
+            }
+            return;
         }
+        let Some(pid) = self.vision.target_window_pid() else {
+            self.log("Cannot schedule Mission Proof: Dofus window not found. Scan first.", LogLevel::Warning);
+            return;
+        };
+        let (verifier, actions) = (self.verifier(), self.actions.clone());
+        self.scheduler.every(
+            PROOF_SCHEDULE,
+            PROOF_INTERVAL,
+            TaskPriority::Normal,
+            Box::new(move || Box::new(MissionProof::new(pid, verifier.clone(), actions.clone()))),
+        );
+    }
+
+    pub fn mission_proof_scheduled(&self) -> bool {
+        self.scheduler.is_scheduled(PROOF_SCHEDULE)
+    }
+
+    /// Starts a Harvest task on a known resource node of the current map.
+    pub fn harvest(&self, node_id: u64) {
+        let Some(node) = self.world.lock().unwrap().node(node_id).cloned() else {
+            return self.log(&format!("Cannot harvest: unknown resource node {}", node_id), LogLevel::Warning);
+        };
+        let (width, height) = *self.vision.frame_size.lock().unwrap();
+        let point = FramePoint {
+            x: node.position.0 as f64 * width as f64,
+            y: node.position.1 as f64 * height as f64,
+        };
+        let click = match self.frame_click(point) {
+            Ok(click) => click,
+            Err(err) => return self.log(&format!("Cannot harvest {} #{}: {}", node.kind, node.id, err), LogLevel::Warning),
+        };
+        let task = Harvest::new(node, click, Arc::clone(&self.world), self.actions.clone());
+        self.scheduler.submit(TaskPriority::Normal, Box::new(task), "requested by the user");
     }
 
-    pub fn focus_bot(&self) {
-        self.log("Focusing Bot window natively...", LogLevel::Info);
-        self.input.focus_window(std::process::id() as i32);
+    pub fn harvest_busy(&self) -> bool {
+        self.scheduler.is_busy(Harvest::NAME)
     }
 
+    /// Starts the test focus dance: Dofus after 5s, the bot 5s later.
     pub fn run_test_sequence(&self) {
+        let Some(pid) = self.vision.target_window_pid() else {
+            self.log("Test: Dofus PID not found. Scan first.", LogLevel::Warning);
+            return;
+        };
+        let task = FocusTest::new(pid, &self.actions);
+        self.scheduler.submit(TaskPriority::Normal, Box::new(task), "requested by the user");
+    }
+
+    pub fn test_sequence_busy(&self) -> bool {
+        self.scheduler.is_busy(FocusTest::NAME)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.lock().unwrap().is_some()
+    }
+
+    /// Starts recording what the user does on the Dofus window.
+    pub fn start_recording(&self) {
+        let mut recorder = self.recorder.lock().unwrap();
+        if recorder.is_some() {
+            return;
+        }
+        *recorder = Some(MacroRecorder::start(
+            self.monitor.subscribe(),
+            Arc::clone(&self.vision.geometry),
+            self.subscribe_state(),
+        ));
+        self.log("Recording macro. Play it out in the Dofus window, then stop here.", LogLevel::Info);
+    }
+
+    /// Stops recording and saves the macro under `./macros`.
+    pub fn stop_recording(&self) {
+        let Some(recorder) = self.recorder.lock().unwrap().take() else { return };
+        let name = format!("macro_{}", Local::now().format("%Y%m%d_%H%M%S"));
+        let recorded = recorder.stop(&name);
+        let path = macros::macro_path(&name);
+        match recorded.save(&path) {
+            Ok(()) => self.log(
+                &format!("Saved macro with {} steps: {}", recorded.steps.len(), path.display()),
+                LogLevel::Success,
+            ),
+            Err(err) => self.log(&err, LogLevel::Error),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.lock().unwrap().as_ref().is_some_and(|w| w.is_running())
+    }
+
+    /// Plays a macro file into the Dofus window in the background, unless one is
+    /// already playing.
+    pub fn play_macro(&self, path: &Path) {
+        let mut playback = self.playback.lock().unwrap();
+        if let Some(worker) = playback.as_ref()
+            && worker.is_running()
+        {
+            return self.log(&format!("Cannot play macro: {} is still playing.", worker.name()), LogLevel::Warning);
+        }
+        let recorded = match Macro::load(path) {
+            Ok(recorded) => recorded,
+            Err(err) => return self.log(&err, LogLevel::Error),
+        };
+        let target = match self.input_target() {
+            Ok(target) => target,
+            Err(err) => return self.log(&format!("Cannot play macro: {}", err), LogLevel::Warning),
+        };
+        let actions = self.actions.clone();
+        let geometry = Arc::clone(&self.vision.geometry);
+        let state = self.subscribe_state();
         let tx = self.log_tx.clone();
-        let vision_pid = self.vision.target_window_pid;
-        let bot_pid = std::process::id() as i32;
-        let input = InputManager::new();
-
-        thread::spawn(move || {
-            let log = |msg: &str, level: LogLevel| {
-                let timestamp = Local::now().format("%H:%M:%S").to_string();
-                let _ = tx.send(LogMessage {
-                    timestamp,
-                    level,
-                    message: msg.to_string(),
-                });
-            };
-
-            if let Some(p) = vision_pid {
-                log("Test: Focusing Dofus in 5s...", LogLevel::Info);
-                thread::sleep(Duration::from_secs(5));
-                input.focus_window(p);
-                log("Test: Dofus focused. Focusing Bot in 5s...", LogLevel::Info);
-                thread::sleep(Duration::from_secs(5));
-                input.focus_window(bot_pid);
-                log("Test: Bot focused. Sequence complete.", LogLevel::Success);
-            } else {
-                log("Test: Dofus PID not found. Scan first.", LogLevel::Warning);
+
+        self.log(&format!("Playing macro {} ({} steps)...", recorded.name, recorded.steps.len()), LogLevel::Info);
+        let name = format!("macro {}", recorded.name);
+        *playback = Some(Worker::spawn(&name, move |token| {
+            match recorded.play(&actions, &target, &geometry, &state, &token) {
+                Ok(()) => send_log(&tx, &format!("Macro {} done.", recorded.name), LogLevel::Success),
+                Err(err) if token.is_cancelled() => send_log(&tx, &err, LogLevel::Info),
+                Err(err) => send_log(&tx, &err, LogLevel::Error),
             }
+        }));
+    }
+
+    /// Stops the macro being played after its current step.
+    pub fn stop_macro(&self) {
+        if let Some(worker) = self.playback.lock().unwrap().as_ref() {
+            worker.cancel();
+        }
+    }
+
+    /// Stops everything this client runs in the background: the scheduler, macro
+    /// playback and recording, the detectors, the event logger and the capture stream.
+    /// Waits up to `timeout` for each thread and returns the names of those that didn't
+    /// stop in time.
+    pub fn shutdown(&mut self, timeout: Duration) -> Vec<String> {
+        let mut stuck = Vec::new();
+        if !self.scheduler.shutdown(timeout) {
+            stuck.push(format!("scheduler-{}", self.name));
+        }
+        for mut worker in self.background.drain(..) {
+            if !worker.stop(timeout) {
+                stuck.push(worker.name().to_string());
+            }
+        }
+        if let Some(mut worker) = self.playback.lock().unwrap().take()
+            && !worker.stop(timeout)
+        {
+            stuck.push(worker.name().to_string());
+        }
+        // Keep what was recorded so far rather than losing it.
+        self.stop_recording();
+        self.vision.stop_streaming();
+        stuck
+    }
+
+    /// Runs a behavior file as a background task of this client's scheduler.
+    pub fn run_behavior(&self, path: &Path) {
+        let result = Behavior::load(path).and_then(|loaded| {
+            let target = self.input_target().map_err(|e| format!("Cannot run behavior: {}", e))?;
+            let task = BehaviorTask::new(
+                &loaded,
+                &self.behavior_leaves(target),
+                self.subscribe_state(),
+                Arc::clone(&self.behavior_status),
+            )?;
+            self.scheduler.submit(TaskPriority::Background, Box::new(task), "behavior started by the user");
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.log(&err, LogLevel::Error);
+        }
+    }
+
+    pub fn behavior_status(&self) -> String {
+        self.behavior_status.lock().unwrap().clone()
+    }
+
+    /// The built-in leaves plus the ones acting on this client's window: `key` presses a
+    /// game action, `chat` sends a line, `macro` plays a recorded macro. `key` and `chat`
+    /// take an optional `expect` to check their effect on screen.
+    fn behavior_leaves(&self, target: InputTarget) -> Leaves {
+        let mut leaves = Leaves::builtin();
+
+        let (actions, keymap, focus, verifier) = (self.actions.clone(), Arc::clone(&self.keymap), target.clone(), self.verifier());
+        leaves.register("key", move |params| {
+            let action: GameAction = behavior::param(params, "action")?;
+            let steps = shortcut_steps(&keymap, focus.pid, action)?;
+            behavior::input_leaf(&format!("{:?}", action), steps, params, &focus, &actions, &verifier)
+        });
+
+        let (actions, keymap, focus, verifier) = (self.actions.clone(), Arc::clone(&self.keymap), target.clone(), self.verifier());
+        leaves.register("chat", move |params| {
+            let message: String = behavior::param(params, "message")?;
+            let steps = chat_steps(&keymap, focus.pid, message)?;
+            behavior::input_leaf("chat", steps, params, &focus, &actions, &verifier)
+        });
+
+        let (actions, geometry, state) = (self.actions.clone(), Arc::clone(&self.vision.geometry), self.subscribe_state());
+        leaves.register("macro", move |params| {
+            let name: String = behavior::param(params, "name")?;
+            let recorded = Macro::load(&macros::macro_path(&name))?;
+            Ok(Box::new(MacroLeaf::new(recorded, actions.clone(), target.clone(), Arc::clone(&geometry), state.clone())))
         });
+        leaves
     }
 }
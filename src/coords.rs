@@ -0,0 +1,254 @@
+use crate::vision_engine::WindowGeometry;
+use screencapturekit::shareable_content::SCShareableContent;
+
+/// A pixel in a captured frame. With Retina capture there are several per window point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FramePoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A point inside the window, in points from its top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A point in the global desktop space shared by every display, which is what mouse
+/// events use. The main display's top-left is the origin; displays left of or above it
+/// have negative coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Converts between frame pixels, window points and screen points for one window.
+///
+/// The scale is whatever the capture actually delivered (frame pixels per window point),
+/// so it stays right whether the stream runs at 1x or at the display's Retina scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoordTransform {
+    pub window: WindowGeometry,
+    pub frame_size: (u32, u32),
+}
+
+impl CoordTransform {
+    pub fn new(window: WindowGeometry, frame_size: (u32, u32)) -> Self {
+        Self { window, frame_size }
+    }
+
+    /// Frame pixels per window point, horizontally and vertically.
+    pub fn scale(&self) -> (f64, f64) {
+        // Before the first frame (or for a zero-sized window) assume 1x.
+        let ratio = |px: u32, pt: f64| if px > 0 && pt > 0.0 { px as f64 / pt } else { 1.0 };
+        (ratio(self.frame_size.0, self.window.width), ratio(self.frame_size.1, self.window.height))
+    }
+
+    pub fn frame_to_window(&self, p: FramePoint) -> WindowPoint {
+        let (sx, sy) = self.scale();
+        WindowPoint { x: p.x / sx, y: p.y / sy }
+    }
+
+    pub fn window_to_frame(&self, p: WindowPoint) -> FramePoint {
+        let (sx, sy) = self.scale();
+        FramePoint { x: p.x * sx, y: p.y * sy }
+    }
+
+    pub fn window_to_screen(&self, p: WindowPoint) -> ScreenPoint {
+        ScreenPoint {
+            x: self.window.x + p.x,
+            y: self.window.y + p.y,
+        }
+    }
+
+    pub fn screen_to_window(&self, p: ScreenPoint) -> WindowPoint {
+        WindowPoint {
+            x: p.x - self.window.x,
+            y: p.y - self.window.y,
+        }
+    }
+
+    pub fn frame_to_screen(&self, p: FramePoint) -> ScreenPoint {
+        self.window_to_screen(self.frame_to_window(p))
+    }
+
+    pub fn screen_to_frame(&self, p: ScreenPoint) -> FramePoint {
+        self.window_to_frame(self.screen_to_window(p))
+    }
+
+    /// A position given as a fraction of the window size, as the world model stores them.
+    /// Fractions outside 0..1 are pulled back to the window's edge.
+    pub fn relative_to_window(&self, pos: (f32, f32)) -> WindowPoint {
+        self.clamp(WindowPoint {
+            x: self.window.width * pos.0 as f64,
+            y: self.window.height * pos.1 as f64,
+        })
+    }
+
+    pub fn contains(&self, p: WindowPoint) -> bool {
+        (0.0..=self.window.width).contains(&p.x) && (0.0..=self.window.height).contains(&p.y)
+    }
+
+    /// The nearest point inside the window.
+    pub fn clamp(&self, p: WindowPoint) -> WindowPoint {
+        WindowPoint {
+            x: p.x.clamp(0.0, self.window.width.max(0.0)),
+            y: p.y.clamp(0.0, self.window.height.max(0.0)),
+        }
+    }
+}
+
+/// A display's area in screen points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Display {
+    pub id: u32,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Display {
+    pub fn contains(&self, p: ScreenPoint) -> bool {
+        p.x >= self.x && p.x < self.x + self.width && p.y >= self.y && p.y < self.y + self.height
+    }
+}
+
+/// How the displays are arranged in the global space.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScreenLayout {
+    pub displays: Vec<Display>,
+}
+
+impl ScreenLayout {
+    pub fn current() -> Result<Self, String> {
+        let content = SCShareableContent::get().map_err(|_| "Failed to get shareable content.".to_string())?;
+        let displays = content
+            .displays()
+            .iter()
+            .map(|d| {
+                let frame = d.frame();
+                Display {
+                    id: d.display_id(),
+                    x: frame.origin.x,
+                    y: frame.origin.y,
+                    width: frame.size.width,
+                    height: frame.size.height,
+                }
+            })
+            .collect();
+        Ok(Self { displays })
+    }
+
+    pub fn display_at(&self, p: ScreenPoint) -> Option<&Display> {
+        self.displays.iter().find(|d| d.contains(p))
+    }
+
+    /// Whether a click at `p` lands on some display. A window hanging off the edge of the
+    /// desktop has parts no click can reach.
+    pub fn is_visible(&self, p: ScreenPoint) -> bool {
+        // Without display information, don't block input.
+        self.displays.is_empty() || self.display_at(p).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn geometry() -> impl Strategy<Value = WindowGeometry> {
+        (-4000.0..4000.0, -2000.0..2000.0, 1.0..3000.0, 1.0..2000.0)
+            .prop_map(|(x, y, width, height)| WindowGeometry { x, y, width, height })
+    }
+
+    /// A window with the frame size a 1x, 2x or odd-scaled capture would deliver.
+    fn transform() -> impl Strategy<Value = CoordTransform> {
+        (geometry(), prop_oneof![Just(1.0), Just(2.0), 0.5..3.0]).prop_map(|(window, scale)| {
+            let frame = ((window.width * scale).round().max(1.0) as u32, (window.height * scale).round().max(1.0) as u32);
+            CoordTransform::new(window, frame)
+        })
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-6 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    proptest! {
+        #[test]
+        fn frame_window_screen_round_trip(t in transform(), fx in 0.0..1.0f64, fy in 0.0..1.0f64) {
+            let frame = FramePoint { x: fx * t.frame_size.0 as f64, y: fy * t.frame_size.1 as f64 };
+            let window = t.frame_to_window(frame);
+            let screen = t.window_to_screen(window);
+
+            let back = t.screen_to_window(screen);
+            prop_assert!(close(back.x, window.x) && close(back.y, window.y));
+            let back = t.window_to_frame(back);
+            prop_assert!(close(back.x, frame.x) && close(back.y, frame.y));
+            let direct = t.screen_to_frame(t.frame_to_screen(frame));
+            prop_assert!(close(direct.x, frame.x) && close(direct.y, frame.y));
+        }
+
+        #[test]
+        fn frame_points_land_inside_the_window(t in transform(), fx in 0.0..=1.0f64, fy in 0.0..=1.0f64) {
+            let frame = FramePoint { x: fx * t.frame_size.0 as f64, y: fy * t.frame_size.1 as f64 };
+            let window = t.frame_to_window(frame);
+            prop_assert!(t.contains(t.clamp(window)));
+            // Points the frame covers are inside, up to rounding of the frame size.
+            prop_assert!(window.x <= t.window.width + 1.0 && window.y <= t.window.height + 1.0);
+        }
+
+        #[test]
+        fn clamp_keeps_inside_points_and_pulls_others_in(t in transform(), x in -1e4..1e4f64, y in -1e4..1e4f64) {
+            let p = WindowPoint { x, y };
+            let clamped = t.clamp(p);
+            prop_assert!(t.contains(clamped));
+            if t.contains(p) {
+                prop_assert_eq!(clamped, p);
+            } else {
+                prop_assert!(clamped != p);
+            }
+            prop_assert_eq!(t.clamp(clamped), clamped);
+        }
+
+        #[test]
+        fn relative_positions_stay_in_the_window(t in transform(), rx in -1.0..2.0f32, ry in -1.0..2.0f32) {
+            let p = t.relative_to_window((rx, ry));
+            prop_assert!(t.contains(p));
+            if (0.0..=1.0).contains(&rx) {
+                prop_assert!(close(p.x, t.window.width * rx as f64));
+            }
+        }
+
+        #[test]
+        fn screen_points_belong_to_the_display_under_them(
+            w in 100.0..3000.0f64,
+            h in 100.0..2000.0f64,
+            x in -3000.0..6000.0f64,
+            y in -2000.0..2000.0f64,
+        ) {
+            // A main display with a second one to its left.
+            let layout = ScreenLayout {
+                displays: vec![
+                    Display { id: 1, x: 0.0, y: 0.0, width: w, height: h },
+                    Display { id: 2, x: -w, y: 0.0, width: w, height: h },
+                ],
+            };
+            let p = ScreenPoint { x, y };
+            let expected = if !(0.0..h).contains(&y) {
+                None
+            } else if (0.0..w).contains(&x) {
+                Some(1)
+            } else if (-w..0.0).contains(&x) {
+                Some(2)
+            } else {
+                None
+            };
+            prop_assert_eq!(layout.display_at(p).map(|d| d.id), expected);
+            prop_assert_eq!(layout.is_visible(p), expected.is_some());
+            prop_assert!(ScreenLayout::default().is_visible(p));
+        }
+    }
+}
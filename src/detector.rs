@@ -0,0 +1,460 @@
+use crate::vision_pipeline::{Frame, Roi};
+use crate::worker::Worker;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often a detector runner with no new frame checks whether it was cancelled.
+const RUNNER_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DetectorPriority {
+    /// Deferred to every Nth frame when processing falls behind.
+    Low,
+    /// Dropped for a frame if it is still busy or misses the budget.
+    Normal,
+    /// Always waited for, even past the budget.
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScreenKind {
+    Unknown,
+    Login,
+    CharacterSelect,
+    Loading,
+    World,
+    Combat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceSighting {
+    pub kind: String,
+    pub bounds: Roi,
+}
+
+/// Something a detector recognised on a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Detection {
+    Screen(ScreenKind),
+    Health { current: u32, max: u32 },
+    ActionPoints(u32),
+    MovementPoints(u32),
+    MapCoords { x: i32, y: i32 },
+    Pods { current: u32, max: u32 },
+    OpenWindows(Vec<String>),
+    Resources(Vec<ResourceSighting>),
+}
+
+pub trait Detector: Send + Sync {
+    fn name(&self) -> &str;
+    fn priority(&self) -> DetectorPriority;
+    /// Returns what was seen along with a confidence in `0.0..=1.0`, or `None` if nothing was found.
+    fn detect(&self, frame: &Frame) -> Option<(Detection, f32)>;
+    /// For detectors built on a vision pipeline: the pipeline's name and the per-stage timing
+    /// of its last run, or the error that stopped it.
+    fn stage_timings(&self) -> Option<(String, Result<String, String>)> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DetectorOutput {
+    pub detector: String,
+    pub detection: Detection,
+    pub confidence: f32,
+    pub elapsed: Duration,
+}
+
+/// Everything the detectors produced for one frame.
+#[derive(Debug, Clone)]
+pub struct FrameReport {
+    pub seq: u64,
+    pub captured_at: Instant,
+    pub frame_size: (u32, u32),
+    pub outputs: Vec<DetectorOutput>,
+    pub elapsed: Duration,
+    /// Detectors not started on this frame, either still busy or deferred.
+    pub skipped: Vec<String>,
+    /// Detectors that were started but missed the budget.
+    pub late: Vec<String>,
+    /// Frames that arrived while this one was queued and were never processed.
+    pub frames_dropped: u64,
+    /// Low-priority detectors run once every this many frames, from the next frame on.
+    pub low_interval: u32,
+    /// Stage timings of the pipeline detectors that finished in time, by pipeline name.
+    pub stage_timings: Vec<(String, Result<String, String>)>,
+}
+
+impl FrameReport {
+    pub fn summary(&self) -> String {
+        let mut s = format!(
+            "frame #{}: {} detections in {:.1}ms",
+            self.seq,
+            self.outputs.len(),
+            self.elapsed.as_secs_f64() * 1000.0
+        );
+        if !self.skipped.is_empty() {
+            s.push_str(&format!(" | skipped: {}", self.skipped.join(", ")));
+        }
+        if !self.late.is_empty() {
+            s.push_str(&format!(" | late: {}", self.late.join(", ")));
+        }
+        if self.frames_dropped > 0 {
+            s.push_str(&format!(" | dropped {} frames", self.frames_dropped));
+        }
+        if self.low_interval > 1 {
+            s.push_str(&format!(" | low priority every {} frames", self.low_interval));
+        }
+        if let Some(slowest) = self.outputs.iter().max_by_key(|o| o.elapsed) {
+            s.push_str(&format!(
+                " | slowest: {} {:.1}ms",
+                slowest.detector,
+                slowest.elapsed.as_secs_f64() * 1000.0
+            ));
+        }
+        s
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of threads running jobs from a shared queue.
+pub struct WorkerPool {
+    tx: Mutex<Option<Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        let workers = (0..size.max(1))
+            .map(|i| {
+                let rx = Arc::clone(&rx);
+                thread::Builder::new()
+                    .name(format!("vision-worker-{}", i))
+                    .spawn(move || loop {
+                        let job = match rx.lock() {
+                            Ok(rx) => rx.recv(),
+                            Err(_) => return,
+                        };
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => return,
+                        }
+                    })
+                    .expect("failed to spawn vision worker")
+            })
+            .collect();
+
+        Self {
+            tx: Mutex::new(Some(tx)),
+            workers,
+        }
+    }
+
+    /// Sized to leave one core for the UI and capture threads.
+    pub fn with_default_size() -> Self {
+        let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+        Self::new(cores.saturating_sub(1).max(2))
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Ok(tx) = self.tx.lock()
+            && let Some(tx) = tx.as_ref()
+        {
+            let _ = tx.send(Box::new(job));
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Closing the queue makes every worker's `recv` fail and exit.
+        if let Ok(mut tx) = self.tx.lock() {
+            tx.take();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct Slot {
+    detector: Arc<dyn Detector>,
+    busy: Arc<AtomicBool>,
+}
+
+/// Runs a set of detectors concurrently on each frame within a time budget.
+///
+/// Detectors still working on an older frame are skipped rather than queued. When a frame
+/// overruns the budget, low-priority detectors are pushed back to every 2nd, 4th, ... frame,
+/// and brought back once frames finish comfortably within budget again.
+pub struct DetectorRunner {
+    slots: Vec<Slot>,
+    pool: Arc<WorkerPool>,
+    pub budget: Duration,
+    pub max_low_interval: u32,
+    low_interval: u32,
+    frames_seen: u64,
+}
+
+impl DetectorRunner {
+    pub fn new(pool: Arc<WorkerPool>, budget: Duration) -> Self {
+        Self {
+            slots: Vec::new(),
+            pool,
+            budget,
+            max_low_interval: 16,
+            low_interval: 1,
+            frames_seen: 0,
+        }
+    }
+
+    pub fn add(&mut self, detector: Arc<dyn Detector>) {
+        self.slots.push(Slot {
+            detector,
+            busy: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    pub fn process(&mut self, frame: &Frame) -> FrameReport {
+        let started = Instant::now();
+        let deadline = started + self.budget;
+        self.frames_seen += 1;
+
+        let (tx, rx) = mpsc::channel::<(usize, Option<DetectorOutput>, Option<(String, Result<String, String>)>)>();
+        let mut skipped = Vec::new();
+        let mut pending = Vec::new();
+
+        // 1. Dispatch every detector that is due and idle
+        for (idx, slot) in self.slots.iter().enumerate() {
+            let detector = &slot.detector;
+            let deferred = detector.priority() == DetectorPriority::Low
+                && !self.frames_seen.is_multiple_of(self.low_interval as u64);
+            if deferred || slot.busy.swap(true, Ordering::SeqCst) {
+                skipped.push(detector.name().to_string());
+                continue;
+            }
+
+            pending.push(idx);
+            let detector = Arc::clone(detector);
+            let busy = Arc::clone(&slot.busy);
+            let frame = frame.clone();
+            let tx = tx.clone();
+            self.pool.execute(move || {
+                let t = Instant::now();
+                let output = detector.detect(&frame).map(|(detection, confidence)| DetectorOutput {
+                    detector: detector.name().to_string(),
+                    detection,
+                    confidence,
+                    elapsed: t.elapsed(),
+                });
+                let timings = detector.stage_timings();
+                busy.store(false, Ordering::SeqCst);
+                let _ = tx.send((idx, output, timings));
+            });
+        }
+        drop(tx);
+
+        // 2. Collect results until the budget runs out, then only wait for high priority
+        let mut outputs = Vec::new();
+        let mut stage_timings = Vec::new();
+        while !pending.is_empty() {
+            let high_pending = pending
+                .iter()
+                .any(|&i| self.slots[i].detector.priority() == DetectorPriority::High);
+            let now = Instant::now();
+            let result = if now < deadline {
+                rx.recv_timeout(deadline - now)
+            } else if high_pending {
+                rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                break;
+            };
+
+            match result {
+                Ok((idx, output, timings)) => {
+                    pending.retain(|&i| i != idx);
+                    outputs.extend(output);
+                    stage_timings.extend(timings);
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let late: Vec<String> = pending
+            .iter()
+            .map(|&i| self.slots[i].detector.name().to_string())
+            .collect();
+        let elapsed = started.elapsed();
+
+        // 3. Adapt how often low-priority detectors get a turn
+        if !late.is_empty() || elapsed > self.budget {
+            self.low_interval = (self.low_interval * 2).min(self.max_low_interval.max(1));
+        } else if elapsed < self.budget / 2 {
+            self.low_interval = (self.low_interval / 2).max(1);
+        }
+
+        FrameReport {
+            seq: frame.seq,
+            captured_at: frame.captured_at,
+            frame_size: frame.image.dimensions(),
+            outputs,
+            elapsed,
+            skipped,
+            late,
+            frames_dropped: 0,
+            low_interval: self.low_interval,
+            stage_timings,
+        }
+    }
+
+    /// Processes frames on a worker thread, always jumping to the newest queued frame.
+    /// Stops when the frame source closes or the worker is cancelled.
+    pub fn spawn(mut self, frames: Receiver<Frame>, mut on_report: impl FnMut(FrameReport) + Send + 'static) -> Worker {
+        Worker::spawn("detector-runner", move |token| {
+            while !token.is_cancelled() {
+                let mut frame = match frames.recv_timeout(RUNNER_POLL) {
+                    Ok(frame) => frame,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                let mut dropped = 0;
+                loop {
+                    match frames.try_recv() {
+                        Ok(newer) => {
+                            frame = newer;
+                            dropped += 1;
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+
+                let mut report = self.process(&frame);
+                report.frames_dropped = dropped;
+                on_report(report);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use std::sync::atomic::AtomicU64;
+
+    /// Sleeps for `delay_ms` and then always reports one AP.
+    struct Sleepy {
+        name: &'static str,
+        priority: DetectorPriority,
+        delay_ms: Arc<AtomicU64>,
+    }
+
+    impl Detector for Sleepy {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn priority(&self) -> DetectorPriority {
+            self.priority
+        }
+
+        fn detect(&self, _frame: &Frame) -> Option<(Detection, f32)> {
+            thread::sleep(Duration::from_millis(self.delay_ms.load(Ordering::SeqCst)));
+            Some((Detection::ActionPoints(1), 1.0))
+        }
+    }
+
+    fn sleepy(name: &'static str, priority: DetectorPriority, delay_ms: u64) -> (Arc<dyn Detector>, Arc<AtomicU64>) {
+        let delay_ms = Arc::new(AtomicU64::new(delay_ms));
+        let detector = Sleepy { name, priority, delay_ms: Arc::clone(&delay_ms) };
+        (Arc::new(detector), delay_ms)
+    }
+
+    fn runner(budget_ms: u64) -> DetectorRunner {
+        DetectorRunner::new(Arc::new(WorkerPool::new(4)), Duration::from_millis(budget_ms))
+    }
+
+    fn frame(seq: u64) -> Frame {
+        Frame::new(seq, RgbaImage::new(4, 4))
+    }
+
+    fn names(outputs: &[DetectorOutput]) -> Vec<&str> {
+        outputs.iter().map(|o| o.detector.as_str()).collect()
+    }
+
+    #[test]
+    fn normal_detectors_over_budget_are_reported_late() {
+        let mut runner = runner(20);
+        runner.add(sleepy("fast", DetectorPriority::Normal, 0).0);
+        runner.add(sleepy("slow", DetectorPriority::Normal, 200).0);
+
+        let report = runner.process(&frame(1));
+        assert_eq!(names(&report.outputs), ["fast"]);
+        assert_eq!(report.late, ["slow"]);
+        assert!(report.elapsed < Duration::from_millis(200));
+    }
+
+    #[test]
+    fn high_priority_detectors_are_always_awaited() {
+        let mut runner = runner(10);
+        runner.add(sleepy("hp", DetectorPriority::High, 60).0);
+
+        let report = runner.process(&frame(1));
+        assert_eq!(names(&report.outputs), ["hp"]);
+        assert!(report.late.is_empty());
+        assert!(report.elapsed >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn busy_detectors_are_skipped() {
+        let mut runner = runner(10);
+        runner.add(sleepy("slow", DetectorPriority::Normal, 200).0);
+
+        assert_eq!(runner.process(&frame(1)).late, ["slow"]);
+        let report = runner.process(&frame(2));
+        assert_eq!(report.skipped, ["slow"]);
+        assert!(report.outputs.is_empty());
+    }
+
+    #[test]
+    fn low_priority_deferral_doubles_then_halves() {
+        let mut runner = runner(40);
+        runner.max_low_interval = 8;
+        runner.add(sleepy("low", DetectorPriority::Low, 0).0);
+        let (high, delay_ms) = sleepy("high", DetectorPriority::High, 80);
+        runner.add(high);
+
+        // Over budget: every frame doubles the interval up to the maximum
+        let mut seq = 0;
+        let mut step = |runner: &mut DetectorRunner| {
+            seq += 1;
+            let report = runner.process(&frame(seq));
+            (report.low_interval, report.skipped.contains(&"low".to_string()))
+        };
+        assert_eq!(step(&mut runner), (2, false));
+        assert_eq!(step(&mut runner), (4, false));
+        assert_eq!(step(&mut runner), (8, true));
+        assert_eq!(step(&mut runner), (8, true));
+
+        // Well within budget: halved back down to every frame
+        delay_ms.store(0, Ordering::SeqCst);
+        assert_eq!(step(&mut runner), (4, true));
+        assert_eq!(step(&mut runner), (2, true));
+        assert_eq!(step(&mut runner), (1, true));
+        assert_eq!(step(&mut runner), (1, false));
+    }
+}
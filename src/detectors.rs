@@ -0,0 +1,392 @@
+use crate::detector::{Detection, Detector, DetectorPriority, ResourceSighting, ScreenKind};
+use crate::vision_pipeline::{
+    ColorThreshold, ConnectedComponents, CropRelative, Frame, GlyphOcr, PipelineRun, Roi, Stage, StageData,
+    TemplateLibrary, TemplateVerify, VisionPipeline,
+};
+use image::{imageops, GrayImage, Luma, RgbaImage};
+use std::sync::{Arc, Mutex};
+
+/// Templates named `resource_<kind>` each get a resource detector of the same name.
+pub const RESOURCE_PREFIX: &str = "resource_";
+
+/// Light text on the HUD (HP counter, map coordinates).
+const HUD_TEXT_MIN: [u8; 3] = [200, 200, 200];
+const HUD_TEXT_MAX: [u8; 3] = [255, 255, 255];
+
+/// Detector backed by a vision pipeline plus a function turning its output into a detection.
+pub struct PipelineDetector {
+    name: String,
+    priority: DetectorPriority,
+    pipeline: Mutex<VisionPipeline>,
+    interpret: fn(&PipelineRun) -> Option<Detection>,
+}
+
+impl PipelineDetector {
+    pub fn new(
+        name: &str,
+        priority: DetectorPriority,
+        pipeline: VisionPipeline,
+        interpret: fn(&PipelineRun) -> Option<Detection>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            priority,
+            pipeline: Mutex::new(pipeline),
+            interpret,
+        }
+    }
+}
+
+impl Detector for PipelineDetector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn priority(&self) -> DetectorPriority {
+        self.priority
+    }
+
+    fn detect(&self, frame: &Frame) -> Option<(Detection, f32)> {
+        let mut pipeline = self.pipeline.lock().ok()?;
+        let run = pipeline.run(frame);
+        if run.error.is_some() {
+            return None;
+        }
+        let detection = (self.interpret)(run)?;
+        Some((detection, run.output.confidence.unwrap_or(1.0)))
+    }
+
+    fn stage_timings(&self) -> Option<(String, Result<String, String>)> {
+        let pipeline = self.pipeline.lock().ok()?;
+        let run = pipeline.last_run()?;
+        let timings = match &run.error {
+            Some(err) => Err(err.clone()),
+            None => Ok(run.timing_summary()),
+        };
+        Some((pipeline.name.clone(), timings))
+    }
+}
+
+/// Glyph masks from templates named `glyph_<char>` (plus `glyph_slash`, `glyph_minus`, `glyph_comma`).
+pub fn glyphs_from_library(library: &TemplateLibrary) -> Vec<(char, GrayImage)> {
+    library
+        .iter()
+        .filter_map(|(name, img)| {
+            let suffix = name.strip_prefix("glyph_")?;
+            let ch = match suffix {
+                "slash" => '/',
+                "minus" => '-',
+                "comma" => ',',
+                s if s.chars().count() == 1 => s.chars().next()?,
+                _ => return None,
+            };
+            Some((ch, to_mask(img)))
+        })
+        .collect()
+}
+
+fn to_mask(img: &RgbaImage) -> GrayImage {
+    GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y).0;
+        let luma = (p[0] as u32 * 3 + p[1] as u32 * 6 + p[2] as u32) / 10;
+        Luma([if luma > 127 { 255 } else { 0 }])
+    })
+}
+
+fn hud_text_pipeline(name: &str, region: CropRelative, glyphs: Vec<(char, GrayImage)>) -> VisionPipeline {
+    VisionPipeline::new(name)
+        .stage(region)
+        .stage(ColorThreshold {
+            min: HUD_TEXT_MIN,
+            max: HUD_TEXT_MAX,
+        })
+        .stage(ConnectedComponents { min_area: 3 })
+        .stage(GlyphOcr { glyphs, min_score: 0.75 })
+}
+
+/// Reads the `current/max` HP counter in the bottom HUD.
+pub fn health_detector(glyphs: Vec<(char, GrayImage)>) -> PipelineDetector {
+    let region = CropRelative { x: 0.44, y: 0.88, width: 0.12, height: 0.08 };
+    PipelineDetector::new(
+        "hp",
+        DetectorPriority::High,
+        hud_text_pipeline("hp", region, glyphs),
+        |run| {
+            let (current, max) = parse_fraction(run.output.text.as_deref()?)?;
+            Some(Detection::Health { current, max })
+        },
+    )
+}
+
+/// Reads the AP counter to the right of the HP heart.
+pub fn action_points_detector(glyphs: Vec<(char, GrayImage)>) -> PipelineDetector {
+    let region = CropRelative { x: 0.56, y: 0.88, width: 0.03, height: 0.05 };
+    PipelineDetector::new(
+        "ap",
+        DetectorPriority::Normal,
+        hud_text_pipeline("ap", region, glyphs),
+        |run| Some(Detection::ActionPoints(run.output.text.as_deref()?.parse().ok()?)),
+    )
+}
+
+/// Reads the MP counter below the AP counter.
+pub fn movement_points_detector(glyphs: Vec<(char, GrayImage)>) -> PipelineDetector {
+    let region = CropRelative { x: 0.56, y: 0.93, width: 0.03, height: 0.05 };
+    PipelineDetector::new(
+        "mp",
+        DetectorPriority::Normal,
+        hud_text_pipeline("mp", region, glyphs),
+        |run| Some(Detection::MovementPoints(run.output.text.as_deref()?.parse().ok()?)),
+    )
+}
+
+/// Reads the `current/max` pods counter under the inventory weight bar.
+pub fn pods_detector(glyphs: Vec<(char, GrayImage)>) -> PipelineDetector {
+    let region = CropRelative { x: 0.62, y: 0.95, width: 0.1, height: 0.04 };
+    PipelineDetector::new(
+        "pods",
+        DetectorPriority::Low,
+        hud_text_pipeline("pods", region, glyphs),
+        |run| {
+            let (current, max) = parse_fraction(run.output.text.as_deref()?)?;
+            Some(Detection::Pods { current, max })
+        },
+    )
+}
+
+fn parse_fraction(text: &str) -> Option<(u32, u32)> {
+    let (current, max) = text.split_once('/')?;
+    Some((current.parse().ok()?, max.parse().ok()?))
+}
+
+/// Reads the `x,y` map coordinates in the top-left corner.
+pub fn map_coords_detector(glyphs: Vec<(char, GrayImage)>) -> PipelineDetector {
+    let region = CropRelative { x: 0.0, y: 0.0, width: 0.2, height: 0.08 };
+    PipelineDetector::new(
+        "map",
+        DetectorPriority::Normal,
+        hud_text_pipeline("map", region, glyphs),
+        |run| {
+            let (x, y) = parse_coords(run.output.text.as_deref()?)?;
+            Some(Detection::MapCoords { x, y })
+        },
+    )
+}
+
+/// Parses the first `x,y` pair in `text`, ignoring anything after it (e.g. the sub-area name).
+pub fn parse_coords(text: &str) -> Option<(i32, i32)> {
+    let (x, rest) = text.split_once(',')?;
+    let y: String = rest
+        .chars()
+        .enumerate()
+        .take_while(|(i, c)| c.is_ascii_digit() || (*i == 0 && *c == '-'))
+        .map(|(_, c)| c)
+        .collect();
+    Some((x.trim().parse().ok()?, y.parse().ok()?))
+}
+
+/// Finds a resource by thresholding around the template's average colour, then verifying
+/// each blob against the template.
+pub fn resource_detector(kind: &str, template: Arc<RgbaImage>) -> PipelineDetector {
+    const TOLERANCE: u8 = 24;
+    let mean = mean_rgb(&template);
+    let min_area = (template.width() * template.height() / 4).max(1);
+
+    let pipeline = VisionPipeline::new(kind)
+        .stage(CropRelative { x: 0.0, y: 0.0, width: 1.0, height: 0.85 })
+        .stage(ColorThreshold {
+            min: mean.map(|c| c.saturating_sub(TOLERANCE)),
+            max: mean.map(|c| c.saturating_add(TOLERANCE)),
+        })
+        .stage(ConnectedComponents { min_area })
+        .stage(TemplateVerify::new(kind, template, 0.8));
+
+    PipelineDetector::new(kind, DetectorPriority::Low, pipeline, |run| {
+        let sightings: Vec<ResourceSighting> = run
+            .output
+            .matches
+            .iter()
+            .map(|m| ResourceSighting {
+                kind: m.template.trim_start_matches(RESOURCE_PREFIX).to_string(),
+                bounds: m.bounds,
+            })
+            .collect();
+        // An empty list still counts: it tells the world model the nodes are gone
+        Some(Detection::Resources(sightings))
+    })
+}
+
+fn mean_rgb(img: &RgbaImage) -> [u8; 3] {
+    let n = (img.width() * img.height()).max(1) as u64;
+    let mut sum = [0u64; 3];
+    for p in img.pixels() {
+        for (total, channel) in sum.iter_mut().zip(p.0) {
+            *total += channel as u64;
+        }
+    }
+    sum.map(|s| (s / n) as u8)
+}
+
+/// Size frames and references are shrunk to before being compared.
+const THUMBNAIL: (u32, u32) = (64, 36);
+
+/// Classifies the whole frame by comparing a thumbnail of it to reference screenshots.
+pub struct ScreenStateDetector {
+    references: Vec<(ScreenKind, RgbaImage)>,
+    pub min_score: f32,
+}
+
+impl ScreenStateDetector {
+    /// Uses templates named `screen_login`, `screen_character_select`, `screen_loading`,
+    /// `screen_world` and `screen_combat`.
+    pub fn from_library(library: &TemplateLibrary) -> Self {
+        let kinds = [
+            ("screen_login", ScreenKind::Login),
+            ("screen_character_select", ScreenKind::CharacterSelect),
+            ("screen_loading", ScreenKind::Loading),
+            ("screen_world", ScreenKind::World),
+            ("screen_combat", ScreenKind::Combat),
+        ];
+        let references = kinds
+            .iter()
+            .filter_map(|(name, kind)| Some((*kind, thumbnail(library.get(name)?.as_ref()))))
+            .collect();
+        Self { references, min_score: 0.85 }
+    }
+}
+
+fn thumbnail(img: &RgbaImage) -> RgbaImage {
+    imageops::resize(img, THUMBNAIL.0, THUMBNAIL.1, imageops::FilterType::Triangle)
+}
+
+impl Detector for ScreenStateDetector {
+    fn name(&self) -> &str {
+        "screen"
+    }
+
+    fn priority(&self) -> DetectorPriority {
+        DetectorPriority::High
+    }
+
+    fn detect(&self, frame: &Frame) -> Option<(Detection, f32)> {
+        let thumb = thumbnail(&frame.image);
+
+        let best = self
+            .references
+            .iter()
+            .map(|(kind, reference)| {
+                let diff: u64 = thumb
+                    .pixels()
+                    .zip(reference.pixels())
+                    .map(|(a, b)| (0..3).map(|c| a.0[c].abs_diff(b.0[c]) as u64).sum::<u64>())
+                    .sum();
+                let max = (THUMBNAIL.0 * THUMBNAIL.1) as u64 * 3 * 255;
+                (*kind, 1.0 - diff as f32 / max as f32)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((kind, score)) = best
+            && score >= self.min_score
+        {
+            return Some((Detection::Screen(kind), score));
+        }
+
+        // Loading screens are close to black; good enough without a reference.
+        let dark = thumb.pixels().filter(|p| p.0[..3].iter().all(|&c| c < 24)).count();
+        if dark * 10 >= thumb.pixels().count() * 9 {
+            return Some((Detection::Screen(ScreenKind::Loading), 0.6));
+        }
+
+        Some((Detection::Screen(ScreenKind::Unknown), best.map(|b| b.1).unwrap_or(0.0)))
+    }
+}
+
+/// How much frames and window templates are shrunk before searching for open windows.
+const WINDOW_SEARCH_SCALE: u32 = 4;
+
+/// Lists open game windows (inventory, bank, trade...) by looking for their title bars.
+pub struct OpenWindowsDetector {
+    /// `(name, downscaled title bar)` for every `window_<name>` template.
+    title_bars: Vec<(String, RgbaImage)>,
+    pub min_score: f32,
+}
+
+impl OpenWindowsDetector {
+    pub fn from_library(library: &TemplateLibrary) -> Self {
+        let title_bars = library
+            .iter()
+            .filter_map(|(name, img)| {
+                let window = name.strip_prefix("window_")?;
+                Some((window.to_string(), downscale(img, WINDOW_SEARCH_SCALE)))
+            })
+            .collect();
+        Self { title_bars, min_score: 0.9 }
+    }
+}
+
+fn downscale(img: &RgbaImage, factor: u32) -> RgbaImage {
+    let w = (img.width() / factor).max(1);
+    let h = (img.height() / factor).max(1);
+    imageops::resize(img, w, h, imageops::FilterType::Triangle)
+}
+
+impl Detector for OpenWindowsDetector {
+    fn name(&self) -> &str {
+        "windows"
+    }
+
+    fn priority(&self) -> DetectorPriority {
+        DetectorPriority::Low
+    }
+
+    fn detect(&self, frame: &Frame) -> Option<(Detection, f32)> {
+        let small = Frame::new(frame.seq, downscale(&frame.image, WINDOW_SEARCH_SCALE));
+
+        let mut open = Vec::new();
+        let mut scores = Vec::new();
+        for (name, title_bar) in &self.title_bars {
+            let verify = TemplateVerify::new(name, Arc::new(title_bar.clone()), self.min_score);
+            // The title bar can be anywhere, so this one searches the whole (downscaled) frame.
+            let data = StageData {
+                roi: Some(Roi::new(0, 0, small.image.width(), small.image.height())),
+                image: Some(Arc::clone(&small.image)),
+                ..Default::default()
+            };
+            if let Ok(result) = verify.run(&small, data)
+                && let Some(m) = result.matches.first()
+            {
+                open.push(name.clone());
+                scores.push(m.score);
+            }
+        }
+
+        // An empty list is still an observation: no window is open.
+        let confidence = if scores.is_empty() {
+            0.5
+        } else {
+            scores.iter().sum::<f32>() / scores.len() as f32
+        };
+        Some((Detection::OpenWindows(open), confidence))
+    }
+}
+
+/// The standard detector set: screen state, HP/AP/MP, map coordinates, pods, open windows
+/// and one detector per `resource_<kind>` template.
+pub fn default_detectors(library: &TemplateLibrary) -> Vec<Arc<dyn Detector>> {
+    let glyphs = glyphs_from_library(library);
+    let mut detectors: Vec<Arc<dyn Detector>> = vec![
+        Arc::new(ScreenStateDetector::from_library(library)),
+        Arc::new(health_detector(glyphs.clone())),
+        Arc::new(action_points_detector(glyphs.clone())),
+        Arc::new(movement_points_detector(glyphs.clone())),
+        Arc::new(map_coords_detector(glyphs.clone())),
+        Arc::new(pods_detector(glyphs)),
+        Arc::new(OpenWindowsDetector::from_library(library)),
+    ];
+    for (name, template) in library.iter() {
+        if name.starts_with(RESOURCE_PREFIX) {
+            detectors.push(Arc::new(resource_detector(name, Arc::clone(template))));
+        }
+    }
+    detectors
+}
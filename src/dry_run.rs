@@ -0,0 +1,110 @@
+use crate::action_queue::InputAction;
+use crate::bot_engine::{send_log, LogLevel, LogMessage};
+#[cfg(test)]
+use rdev::Key;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an intended action stays on the preview.
+pub const MARKER_LIFETIME: Duration = Duration::from_secs(2);
+
+/// An action that would have been sent.
+#[derive(Debug, Clone)]
+pub struct Intent {
+    pub at: Instant,
+    pub action: InputAction,
+}
+
+/// Global switch that keeps input from reaching the OS. While it's on, `InputManager`
+/// logs each action instead of sending it and keeps the recent ones for the preview.
+/// Everything upstream, detectors, tasks and the safety guard included, runs as usual.
+pub struct DryRun {
+    enabled: AtomicBool,
+    /// The PID a dry-run focus would have brought forward, so the safety guard's
+    /// frontmost check still passes.
+    focused: Mutex<Option<i32>>,
+    /// Keys whose key-down was held back, so their key-up is too, even if dry run is
+    /// switched off in between. Other key-ups always go through.
+    #[cfg(test)]
+    held: Mutex<Vec<Key>>,
+    recent: Mutex<VecDeque<Intent>>,
+    log_tx: Sender<LogMessage>,
+}
+
+impl DryRun {
+    pub fn new(log_tx: Sender<LogMessage>) -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            focused: Mutex::new(None),
+            #[cfg(test)]
+            held: Mutex::new(Vec::new()),
+            recent: Mutex::new(VecDeque::new()),
+            log_tx,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.swap(enabled, Ordering::SeqCst) != enabled {
+            let msg = if enabled { "Dry run on: input is logged, not sent." } else { "Dry run off: input goes to the game again." };
+            send_log(&self.log_tx, msg, LogLevel::Warning);
+        }
+    }
+
+    /// Records `action` and returns true if it must not be sent.
+    pub fn intercept(&self, action: &InputAction) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        if let InputAction::Focus(pid) = action {
+            *self.focused.lock().unwrap() = Some(*pid);
+        }
+        send_log(&self.log_tx, &format!("[dry run] {:?}", action), LogLevel::Info);
+
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|i| i.at.elapsed() < MARKER_LIFETIME);
+        recent.push_back(Intent {
+            at: Instant::now(),
+            action: action.clone(),
+        });
+        true
+    }
+
+    /// Like `intercept`, for a key that stays down until `release`.
+    #[cfg(test)]
+    pub fn intercept_key_down(&self, key: Key) -> bool {
+        let intercepted = self.intercept(&InputAction::Key(key));
+        if intercepted {
+            let mut held = self.held.lock().unwrap();
+            if !held.contains(&key) {
+                held.push(key);
+            }
+        }
+        intercepted
+    }
+
+    /// Returns true if the key-down of `key` was held back, so its key-up must be too.
+    #[cfg(test)]
+    pub fn release(&self, key: Key) -> bool {
+        let mut held = self.held.lock().unwrap();
+        let before = held.len();
+        held.retain(|k| *k != key);
+        held.len() != before
+    }
+
+    pub fn focused(&self) -> Option<i32> {
+        *self.focused.lock().unwrap()
+    }
+
+    /// Intents from the last `MARKER_LIFETIME`, oldest first.
+    pub fn recent(&self) -> Vec<Intent> {
+        let recent = self.recent.lock().unwrap();
+        recent.iter().filter(|i| i.at.elapsed() < MARKER_LIFETIME).cloned().collect()
+    }
+}
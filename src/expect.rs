@@ -0,0 +1,363 @@
+use crate::action_queue::{ActionQueue, ActionRequest};
+use crate::bot_engine::{send_log, LogLevel, LogMessage};
+use crate::detector::ScreenKind;
+use crate::game_state::GameState;
+use crate::input_backend::InputError;
+use crate::vision_engine::FrameFeed;
+use crate::vision_pipeline::{CropRoi, Frame, Roi, TemplateLibrary, TemplateVerify, VisionPipeline};
+use crate::worker::CancelToken;
+use chrono::Local;
+use image::{imageops, GenericImageView};
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Where frames of failed expectations are saved.
+const PROOF_DIR: &str = "./mission_logs";
+/// How often `act_and_expect` re-checks when no new frame arrives.
+const EXPECT_POLL: Duration = Duration::from_millis(100);
+
+/// What should be visible once an action has taken effect, as written in behavior files.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "see", rename_all = "snake_case")]
+pub enum Expectation {
+    /// The game switches to this screen.
+    Screen { screen: ScreenKind },
+    /// A game window with this name is open.
+    WindowOpen { name: String },
+    /// A template from the library shows up inside `roi`. Matching is only affordable on
+    /// a small region, so there is no full-frame variant.
+    Template { name: String, roi: Roi, min_score: f32 },
+    /// Any frame captured after the action, for actions with no visible effect of their own.
+    NewFrame,
+    /// The pixels in `roi` differ from before the action by at least `min_change`, in `0.0..=1.0`.
+    RegionChanged { roi: Roi, min_change: f32 },
+}
+
+impl Expectation {
+    /// Checks `frame` and `state`, both taken after the action. `before` is the last frame
+    /// from before it.
+    pub fn met(&self, before: Option<&Frame>, frame: &Frame, state: &GameState, templates: &TemplateLibrary) -> bool {
+        match self {
+            Expectation::Screen { screen } => state.screen() == *screen,
+            Expectation::WindowOpen { name } => state.is_window_open(name),
+            Expectation::Template { name, roi, min_score } => {
+                let Some(template) = templates.get(name) else { return false };
+                let run = VisionPipeline::new("expect")
+                    .stage(CropRoi(*roi))
+                    .stage(TemplateVerify::new(name, template, *min_score))
+                    .run_uncached(frame);
+                !run.output.matches.is_empty()
+            }
+            Expectation::RegionChanged { roi, min_change } => match before {
+                Some(before) => region_change(before, frame, *roi) >= *min_change,
+                // Nothing to compare against; any new frame counts.
+                None => true,
+            },
+            Expectation::NewFrame => true,
+        }
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expectation::Screen { screen } => write!(f, "screen {:?}", screen),
+            Expectation::WindowOpen { name } => write!(f, "window {} open", name),
+            Expectation::Template { name, .. } => write!(f, "template {} visible", name),
+            Expectation::RegionChanged { roi, .. } => write!(f, "region {:?} changed", roi),
+            Expectation::NewFrame => write!(f, "a new frame"),
+        }
+    }
+}
+
+/// Mean absolute RGB difference of `roi` between two frames, in `0.0..=1.0`. Frames of
+/// different sizes (the window was resized) count as fully changed.
+fn region_change(before: &Frame, after: &Frame, roi: Roi) -> f32 {
+    if before.image.dimensions() != after.image.dimensions() {
+        return 1.0;
+    }
+    let (w, h) = after.image.dimensions();
+    let roi = roi.clamp_to(w, h);
+    if roi.width == 0 || roi.height == 0 {
+        return 0.0;
+    }
+
+    let a = imageops::crop_imm(before.image.as_ref(), roi.x, roi.y, roi.width, roi.height);
+    let b = imageops::crop_imm(after.image.as_ref(), roi.x, roi.y, roi.width, roi.height);
+    let diff: u64 = a
+        .pixels()
+        .zip(b.pixels())
+        .map(|((_, _, p), (_, _, q))| (0..3).map(|c| p.0[c].abs_diff(q.0[c]) as u64).sum::<u64>())
+        .sum();
+    diff as f32 / ((roi.width * roi.height) as u64 * 3 * 255) as f32
+}
+
+/// Why `act_and_expect` gave up.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpectError {
+    /// The action itself could not be sent.
+    Input(InputError),
+    /// The action ran every time, but the screen never showed the expected result.
+    NotMet {
+        expectation: String,
+        attempts: u32,
+        /// Last frame seen, saved for inspection.
+        proof: Option<PathBuf>,
+    },
+}
+
+impl fmt::Display for ExpectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpectError::Input(err) => write!(f, "{}", err),
+            ExpectError::NotMet { expectation, attempts, proof } => {
+                write!(f, "Never saw {} in {} attempt(s).", expectation, attempts)?;
+                match proof {
+                    Some(path) => write!(f, " Last frame: {}", path.display()),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl From<InputError> for ExpectError {
+    fn from(err: InputError) -> Self {
+        ExpectError::Input(err)
+    }
+}
+
+/// Saves `frame` as `<dir>/expect_<time>.png`.
+pub fn save_proof(frame: &Frame, dir: &Path) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+    let path = dir.join(format!("expect_{}.png", Local::now().format("%Y%m%d_%H%M%S")));
+    frame
+        .image
+        .save(&path)
+        .map_err(|e| format!("Failed to save image: {}", e))?;
+    Ok(path)
+}
+
+/// Runs actions of one client and checks their effect on its frames. Cheap to clone, so
+/// tasks can hand it to a worker thread.
+#[derive(Clone)]
+pub struct Verifier {
+    actions: ActionQueue,
+    frames: FrameFeed,
+    state: watch::Receiver<GameState>,
+    templates: Arc<TemplateLibrary>,
+    log_tx: Sender<LogMessage>,
+    proof_dir: PathBuf,
+}
+
+impl Verifier {
+    pub fn new(
+        actions: ActionQueue,
+        frames: FrameFeed,
+        state: watch::Receiver<GameState>,
+        templates: Arc<TemplateLibrary>,
+        log_tx: Sender<LogMessage>,
+    ) -> Self {
+        Self {
+            actions,
+            frames,
+            state,
+            templates,
+            log_tx,
+            proof_dir: PathBuf::from(PROOF_DIR),
+        }
+    }
+
+    fn log(&self, message: &str, level: LogLevel) {
+        send_log(&self.log_tx, message, level);
+    }
+
+    /// Sends the request built by `action`, then watches the following frames until
+    /// `expectation` holds and returns the frame it held on. If it doesn't within
+    /// `timeout`, the request is built and sent again, up to `retries` more times. After
+    /// that the last frame is saved as proof and the failure is logged. Cancelling `token`
+    /// cancels the request in flight and gives up.
+    pub fn act_and_expect(
+        &self,
+        action: impl Fn() -> Result<ActionRequest, InputError>,
+        expectation: &Expectation,
+        timeout: Duration,
+        retries: u32,
+        token: &CancelToken,
+    ) -> Result<Frame, ExpectError> {
+        let mut last_frame = None;
+        for attempt in 1..=retries + 1 {
+            // 1. Note the screen before acting
+            let frames = self.frames.subscribe();
+            let before = self.frames.latest();
+
+            // 2. Act; a transient input failure uses up an attempt
+            let sent = action().and_then(|request| self.actions.submit(request).wait_or_cancel(token).into_result());
+            match sent {
+                Ok(()) => {}
+                Err(err) if err.is_retryable() && attempt <= retries && !token.is_cancelled() => {
+                    self.log(&format!("Attempt {} failed: {}. Retrying.", attempt, err), LogLevel::Warning);
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            }
+
+            // 3. Watch frames captured after the action finished, until the expectation
+            //    holds or time runs out. Detector state lags the frames a little, so keep
+            //    re-checking the newest one.
+            let since = self.frames.seq();
+            let deadline = Instant::now() + timeout;
+            let mut newest = None;
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                if token.is_cancelled() {
+                    return Err(InputError::Cancelled("cancelled".to_string()).into());
+                }
+                match frames.recv_timeout(left.min(EXPECT_POLL)) {
+                    Ok(frame) if frame.seq > since => newest = Some(frame),
+                    Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if let Some(frame) = &newest
+                    && expectation.met(before.as_ref(), frame, &self.state.borrow(), &self.templates)
+                {
+                    return Ok(frame.clone());
+                }
+            }
+
+            if attempt <= retries {
+                self.log(&format!("Attempt {}: {} not seen. Retrying.", attempt, expectation), LogLevel::Warning);
+            }
+            last_frame = newest.or(last_frame);
+        }
+
+        // 4. Escalate with the last frame we saw
+        let proof = last_frame.or_else(|| self.frames.latest()).and_then(|frame| match save_proof(&frame, &self.proof_dir) {
+            Ok(path) => Some(path),
+            Err(err) => {
+                self.log(&err, LogLevel::Error);
+                None
+            }
+        });
+        let err = ExpectError::NotMet {
+            expectation: expectation.to_string(),
+            attempts: retries + 1,
+            proof,
+        };
+        self.log(&err.to_string(), LogLevel::Error);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_backend::RecordingBackend;
+    use crate::input_manager::InputManager;
+    use crate::safety::SafetyGuard;
+    use image::{Rgba, RgbaImage};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_millis(300);
+    /// How long after an attempt the fake game shows its result.
+    const LAG: Duration = Duration::from_millis(50);
+
+    fn fixture(value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(8, 8, Rgba([value, value, value, 255]))
+    }
+
+    fn changed() -> Expectation {
+        Expectation::RegionChanged { roi: Roi::new(0, 0, 8, 8), min_change: 0.5 }
+    }
+
+    /// A verifier on a detached frame feed showing a black frame, saving proofs under `proof_dir`.
+    fn verifier(proof_dir: &Path) -> (Verifier, FrameFeed) {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let input = InputManager::with_backend(Arc::new(RecordingBackend::new()));
+        let actions = ActionQueue::new(input, Duration::ZERO, SafetyGuard::default(), log_tx.clone());
+        let frames = FrameFeed::detached();
+        frames.publish(fixture(0));
+        let (_state_tx, state) = watch::channel(GameState::default());
+        let mut verifier = Verifier::new(actions, frames.clone(), state, Arc::new(TemplateLibrary::new()), log_tx);
+        verifier.proof_dir = proof_dir.to_path_buf();
+        (verifier, frames)
+    }
+
+    /// An action whose attempts each make the game show a frame a little later: black
+    /// before attempt `turns_white_on`, white from then on.
+    fn action(frames: &FrameFeed, attempts: &Arc<AtomicU32>, turns_white_on: u32) -> impl Fn() -> Result<ActionRequest, InputError> {
+        let (frames, attempts) = (frames.clone(), Arc::clone(attempts));
+        move || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            let frames = frames.clone();
+            thread::spawn(move || {
+                thread::sleep(LAG);
+                frames.publish(fixture(if attempt >= turns_white_on { 255 } else { 0 }));
+            });
+            Ok(ActionRequest::new("act", Vec::new()))
+        }
+    }
+
+    fn proof_dir(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dofus_bot_expect_{}_{}", test, std::process::id()))
+    }
+
+    #[test]
+    fn succeeds_on_the_first_frame_showing_the_change() {
+        let (verifier, frames) = verifier(&proof_dir("first"));
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let frame = verifier
+            .act_and_expect(action(&frames, &attempts, 1), &changed(), TIMEOUT, 2, &CancelToken::new())
+            .unwrap();
+        assert_eq!(frame.image.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn acts_again_until_the_change_shows() {
+        let (verifier, frames) = verifier(&proof_dir("retry"));
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let frame = verifier
+            .act_and_expect(action(&frames, &attempts, 2), &changed(), TIMEOUT, 2, &CancelToken::new())
+            .unwrap();
+        assert_eq!(frame.seq, 3);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry_and_saves_the_last_frame() {
+        let dir = proof_dir("exhausted");
+        let (verifier, frames) = verifier(&dir);
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let result = verifier.act_and_expect(action(&frames, &attempts, u32::MAX), &changed(), TIMEOUT, 1, &CancelToken::new());
+        let Err(ExpectError::NotMet { expectation, attempts: tries, proof }) = result else {
+            panic!("expected NotMet");
+        };
+        assert_eq!((expectation.as_str(), tries), ("region Roi { x: 0, y: 0, width: 8, height: 8 } changed", 2));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        let proof = proof.unwrap();
+        assert!(proof.starts_with(&dir) && proof.exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn expectations_read_from_behavior_files() {
+        let parse = |json: &str| serde_json::from_str::<Expectation>(json).unwrap();
+        assert_eq!(parse(r#"{"see": "window_open", "name": "inventory"}"#), Expectation::WindowOpen { name: "inventory".to_string() });
+        assert_eq!(parse(r#"{"see": "screen", "screen": "World"}"#), Expectation::Screen { screen: ScreenKind::World });
+        assert_eq!(
+            parse(r#"{"see": "template", "name": "ok", "roi": {"x": 1, "y": 2, "width": 3, "height": 4}, "min_score": 0.9}"#),
+            Expectation::Template { name: "ok".to_string(), roi: Roi::new(1, 2, 3, 4), min_score: 0.9 }
+        );
+        assert_eq!(parse(r#"{"see": "new_frame"}"#), Expectation::NewFrame);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::input_backend::InputError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the window manager gets to bring the window forward.
+pub const FOCUS_TIMEOUT: Duration = Duration::from_millis(800);
+const POLL_INTERVAL: Duration = Duration::from_millis(40);
+
+/// Asks the OS to raise an application and reports which one is in front.
+pub trait FocusBackend: Send + Sync {
+    /// Requests that the application with this PID comes to the front. The switch may
+    /// happen asynchronously; `focus_and_verify` waits for it.
+    fn activate(&self, pid: i32) -> Result<(), InputError>;
+    /// PID of the application that currently has keyboard focus.
+    fn frontmost_pid(&self) -> Option<i32>;
+}
+
+/// Focus backend for the platform we're running on.
+pub fn native() -> Box<dyn FocusBackend> {
+    #[cfg(target_os = "macos")]
+    {
+        Box::new(macos::AppKitFocus)
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(x11::EwmhFocus::default())
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        Box::new(Unsupported)
+    }
+}
+
+/// Activates `pid` and waits until it really is frontmost.
+pub fn focus_and_verify(backend: &dyn FocusBackend, pid: i32) -> Result<(), InputError> {
+    // 1. Nothing to do if it's already in front
+    if backend.frontmost_pid() == Some(pid) {
+        return Ok(());
+    }
+
+    // 2. Ask for the switch
+    backend.activate(pid)?;
+
+    // 3. Poll until the window manager has done it
+    let started = Instant::now();
+    loop {
+        let frontmost = backend.frontmost_pid();
+        if frontmost == Some(pid) {
+            return Ok(());
+        }
+        if started.elapsed() >= FOCUS_TIMEOUT {
+            return Err(InputError::FocusFailed { pid, frontmost });
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::FocusBackend;
+    use crate::input_backend::InputError;
+    use objc::rc::autoreleasepool;
+    use objc::runtime::{Object, BOOL, NO};
+    use objc::{class, msg_send, sel, sel_impl};
+
+    #[link(name = "AppKit", kind = "framework")]
+    unsafe extern "C" {}
+
+    /// NSApplicationActivateIgnoringOtherApps
+    const ACTIVATE_IGNORING_OTHER_APPS: usize = 1 << 1;
+
+    /// `NSRunningApplication` / `NSWorkspace`.
+    pub struct AppKitFocus;
+
+    impl FocusBackend for AppKitFocus {
+        fn activate(&self, pid: i32) -> Result<(), InputError> {
+            // We're called from worker threads without a run loop, so drain the autoreleased
+            // objects here instead of leaking them.
+            autoreleasepool(|| {
+                // SAFETY: plain AppKit class and instance messages; the returned objects are
+                // autoreleased and only used inside this pool.
+                unsafe {
+                    let app: *mut Object =
+                        msg_send![class!(NSRunningApplication), runningApplicationWithProcessIdentifier: pid];
+                    if app.is_null() {
+                        return Err(InputError::TargetGone);
+                    }
+                    let accepted: BOOL = msg_send![app, activateWithOptions: ACTIVATE_IGNORING_OTHER_APPS];
+                    if accepted == NO {
+                        return Err(InputError::Backend(format!("AppKit refused to activate PID {}", pid)));
+                    }
+                }
+                Ok(())
+            })
+        }
+
+        fn frontmost_pid(&self) -> Option<i32> {
+            autoreleasepool(|| {
+                // SAFETY: as above.
+                unsafe {
+                    let workspace: *mut Object = msg_send![class!(NSWorkspace), sharedWorkspace];
+                    let app: *mut Object = msg_send![workspace, frontmostApplication];
+                    if app.is_null() {
+                        return None;
+                    }
+                    let pid: i32 = msg_send![app, processIdentifier];
+                    Some(pid)
+                }
+            })
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::FocusBackend;
+    use crate::input_backend::InputError;
+    use std::sync::Mutex;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ClientMessageEvent, ConnectionExt, EventMask, Window};
+    use x11rb::rust_connection::RustConnection;
+
+    /// Source indication for `_NET_ACTIVE_WINDOW`: a pager or other tool acting for the user,
+    /// which window managers honour without focus-stealing prevention.
+    const SOURCE_PAGER: u32 = 2;
+
+    fn backend_err(err: impl std::fmt::Display) -> InputError {
+        InputError::Backend(format!("X11: {}", err))
+    }
+
+    struct Ewmh {
+        conn: RustConnection,
+        root: Window,
+        active_window: u32,
+        client_list: u32,
+        wm_pid: u32,
+    }
+
+    impl Ewmh {
+        fn connect() -> Result<Self, InputError> {
+            let (conn, screen) = x11rb::connect(None).map_err(backend_err)?;
+            let root = conn.setup().roots[screen].root;
+            let atom = |name: &[u8]| -> Result<u32, InputError> {
+                Ok(conn.intern_atom(false, name).map_err(backend_err)?.reply().map_err(backend_err)?.atom)
+            };
+            let active_window = atom(b"_NET_ACTIVE_WINDOW")?;
+            let client_list = atom(b"_NET_CLIENT_LIST")?;
+            let wm_pid = atom(b"_NET_WM_PID")?;
+            Ok(Self { conn, root, active_window, client_list, wm_pid })
+        }
+
+        fn property(&self, window: Window, property: u32, kind: AtomEnum) -> Result<Vec<u32>, InputError> {
+            let reply = self
+                .conn
+                .get_property(false, window, property, kind, 0, u32::MAX)
+                .map_err(backend_err)?
+                .reply()
+                .map_err(backend_err)?;
+            Ok(reply.value32().map(|values| values.collect()).unwrap_or_default())
+        }
+
+        fn pid_of(&self, window: Window) -> Option<i32> {
+            self.property(window, self.wm_pid, AtomEnum::CARDINAL)
+                .ok()?
+                .first()
+                .map(|&pid| pid as i32)
+        }
+
+        fn window_of(&self, pid: i32) -> Result<Option<Window>, InputError> {
+            let clients = self.property(self.root, self.client_list, AtomEnum::WINDOW)?;
+            Ok(clients.into_iter().find(|&w| self.pid_of(w) == Some(pid)))
+        }
+
+        fn active(&self) -> Result<Option<Window>, InputError> {
+            let windows = self.property(self.root, self.active_window, AtomEnum::WINDOW)?;
+            Ok(windows.first().copied().filter(|&w| w != 0))
+        }
+    }
+
+    /// EWMH `_NET_ACTIVE_WINDOW`, understood by every current X11 window manager.
+    ///
+    /// Keeps one connection to the X server, opened on first use and reopened after an
+    /// error, since `focus_and_verify` polls `frontmost_pid` every few milliseconds.
+    #[derive(Default)]
+    pub struct EwmhFocus {
+        ewmh: Mutex<Option<Ewmh>>,
+    }
+
+    impl EwmhFocus {
+        /// Runs `f` on the shared connection, dropping the connection if `f` fails.
+        fn with<T>(&self, f: impl FnOnce(&Ewmh) -> Result<T, InputError>) -> Result<T, InputError> {
+            let mut guard = self.ewmh.lock().unwrap();
+            if guard.is_none() {
+                *guard = Some(Ewmh::connect()?);
+            }
+            let result = f(guard.as_ref().unwrap());
+            if matches!(result, Err(InputError::Backend(_))) {
+                *guard = None;
+            }
+            result
+        }
+    }
+
+    impl FocusBackend for EwmhFocus {
+        fn activate(&self, pid: i32) -> Result<(), InputError> {
+            self.with(|ewmh| {
+                // 1. Find the top-level window owned by the PID
+                let window = ewmh.window_of(pid)?.ok_or(InputError::TargetGone)?;
+
+                // 2. Ask the window manager to activate it
+                let event = ClientMessageEvent::new(32, window, ewmh.active_window, [SOURCE_PAGER, 0, 0, 0, 0]);
+                ewmh.conn
+                    .send_event(
+                        false,
+                        ewmh.root,
+                        EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                        event,
+                    )
+                    .map_err(backend_err)?;
+                ewmh.conn.flush().map_err(backend_err)?;
+                Ok(())
+            })
+        }
+
+        fn frontmost_pid(&self) -> Option<i32> {
+            self.with(|ewmh| {
+                let active = ewmh.active()?;
+                Ok(active.and_then(|window| ewmh.pid_of(window)))
+            })
+            .ok()
+            .flatten()
+        }
+    }
+}
+
+/// Platforms without a focus implementation.
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+struct Unsupported;
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+impl FocusBackend for Unsupported {
+    fn activate(&self, _pid: i32) -> Result<(), InputError> {
+        Err(InputError::Backend("window focus is not supported on this platform".to_string()))
+    }
+
+    fn frontmost_pid(&self) -> Option<i32> {
+        None
+    }
+}
@@ -0,0 +1,189 @@
+use crate::detector::{Detection, FrameReport, ResourceSighting, ScreenKind};
+use std::time::{Duration, Instant};
+
+/// A value read off the screen, with when it was seen and how sure the detector was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observed<T> {
+    pub value: T,
+    pub confidence: f32,
+    pub seen_at: Instant,
+    pub frame_seq: u64,
+}
+
+impl<T> Observed<T> {
+    pub fn age(&self) -> Duration {
+        self.seen_at.elapsed()
+    }
+
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        self.age() <= max_age
+    }
+}
+
+/// Everything the detectors know about the game, merged across frames.
+///
+/// A field keeps its last observation until a detector reports a new one, so check
+/// `Observed::age` before trusting anything that matters.
+#[derive(Debug, Clone, Default)]
+pub struct GameState {
+    pub screen: Option<Observed<ScreenKind>>,
+    pub hp: Option<Observed<(u32, u32)>>,
+    pub ap: Option<Observed<u32>>,
+    pub mp: Option<Observed<u32>>,
+    pub map: Option<Observed<(i32, i32)>>,
+    pub in_combat: Option<Observed<bool>>,
+    pub pods: Option<Observed<(u32, u32)>>,
+    pub open_windows: Option<Observed<Vec<String>>>,
+    pub resources: Option<Observed<Vec<ResourceSighting>>>,
+    /// Sequence number of the last frame merged in.
+    pub frame_seq: u64,
+    pub updated_at: Option<Instant>,
+}
+
+impl GameState {
+    /// Merges one frame's detections into the state.
+    pub fn apply(&mut self, report: &FrameReport) {
+        fn observe<T>(report: &FrameReport, value: T, confidence: f32) -> Option<Observed<T>> {
+            Some(Observed {
+                value,
+                confidence,
+                seen_at: report.captured_at,
+                frame_seq: report.seq,
+            })
+        }
+
+        // One detector per resource kind: their sightings are merged, and the merged list
+        // is only as sure as the least sure of them.
+        let mut resources: Option<(Vec<ResourceSighting>, f32)> = None;
+
+        for output in &report.outputs {
+            let c = output.confidence;
+            match &output.detection {
+                Detection::Screen(kind) => {
+                    self.screen = observe(report, *kind, c);
+                    match kind {
+                        ScreenKind::Combat => self.in_combat = observe(report, true, c),
+                        ScreenKind::World => self.in_combat = observe(report, false, c),
+                        _ => {}
+                    }
+                }
+                Detection::Health { current, max } => self.hp = observe(report, (*current, *max), c),
+                Detection::ActionPoints(ap) => self.ap = observe(report, *ap, c),
+                Detection::MovementPoints(mp) => self.mp = observe(report, *mp, c),
+                Detection::MapCoords { x, y } => self.map = observe(report, (*x, *y), c),
+                Detection::Pods { current, max } => self.pods = observe(report, (*current, *max), c),
+                Detection::OpenWindows(windows) => self.open_windows = observe(report, windows.clone(), c),
+                Detection::Resources(found) => {
+                    let (all, confidence) = resources.get_or_insert_with(|| (Vec::new(), c));
+                    all.extend(found.iter().cloned());
+                    *confidence = confidence.min(c);
+                }
+            }
+        }
+        if let Some((found, c)) = resources {
+            self.resources = observe(report, found, c);
+        }
+
+        self.frame_seq = report.seq;
+        self.updated_at = Some(report.captured_at);
+    }
+
+    pub fn screen(&self) -> ScreenKind {
+        self.screen.as_ref().map(|s| s.value).unwrap_or(ScreenKind::Unknown)
+    }
+
+    pub fn in_combat(&self) -> bool {
+        self.in_combat.as_ref().is_some_and(|c| c.value)
+    }
+
+    pub fn is_window_open(&self, name: &str) -> bool {
+        self.open_windows
+            .as_ref()
+            .is_some_and(|w| w.value.iter().any(|open| open == name))
+    }
+
+    /// `(label, value)` pairs for display, with each value's confidence and age.
+    pub fn display_rows(&self) -> Vec<(&'static str, String)> {
+        fn row<T>(field: &Option<Observed<T>>, fmt: impl Fn(&T) -> String) -> String {
+            match field {
+                Some(o) => format!(
+                    "{} ({:.0}%, {:.1}s ago)",
+                    fmt(&o.value),
+                    o.confidence * 100.0,
+                    o.age().as_secs_f32()
+                ),
+                None => "-".to_string(),
+            }
+        }
+
+        vec![
+            ("Screen", row(&self.screen, |s| format!("{:?}", s))),
+            ("HP", row(&self.hp, |(c, m)| format!("{}/{}", c, m))),
+            ("AP", row(&self.ap, |ap| ap.to_string())),
+            ("MP", row(&self.mp, |mp| mp.to_string())),
+            ("Map", row(&self.map, |(x, y)| format!("[{},{}]", x, y))),
+            ("In combat", row(&self.in_combat, |c| c.to_string())),
+            ("Pods", row(&self.pods, |(c, m)| format!("{}/{}", c, m))),
+            ("Windows", row(&self.open_windows, |w| w.join(", "))),
+            ("Resources", row(&self.resources, |r| r.len().to_string())),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detector::DetectorOutput;
+    use crate::vision_pipeline::Roi;
+
+    fn report(outputs: Vec<(&str, Detection, f32)>) -> FrameReport {
+        FrameReport {
+            seq: 7,
+            captured_at: Instant::now(),
+            frame_size: (800, 600),
+            outputs: outputs
+                .into_iter()
+                .map(|(detector, detection, confidence)| DetectorOutput {
+                    detector: detector.to_string(),
+                    detection,
+                    confidence,
+                    elapsed: Duration::ZERO,
+                })
+                .collect(),
+            elapsed: Duration::ZERO,
+            skipped: Vec::new(),
+            late: Vec::new(),
+            frames_dropped: 0,
+            low_interval: 1,
+            stage_timings: Vec::new(),
+        }
+    }
+
+    fn sighting(kind: &str, x: u32) -> ResourceSighting {
+        ResourceSighting { kind: kind.to_string(), bounds: Roi::new(x, 10, 8, 8) }
+    }
+
+    #[test]
+    fn resources_from_every_detector_are_kept() {
+        let mut state = GameState::default();
+        state.apply(&report(vec![
+            ("resource_wheat", Detection::Resources(vec![sighting("wheat", 10), sighting("wheat", 40)]), 0.9),
+            ("resource_ash", Detection::Resources(vec![sighting("ash", 100)]), 0.8),
+            ("resource_oat", Detection::Resources(Vec::new()), 0.95),
+        ]));
+
+        let resources = state.resources.unwrap();
+        let kinds: Vec<&str> = resources.value.iter().map(|s| s.kind.as_str()).collect();
+        assert_eq!(kinds, ["wheat", "wheat", "ash"]);
+        assert_eq!(resources.confidence, 0.8);
+        assert_eq!(resources.frame_seq, 7);
+    }
+
+    #[test]
+    fn resources_are_left_alone_when_no_detector_reported() {
+        let mut state = GameState::default();
+        state.apply(&report(vec![("resource_ash", Detection::Resources(vec![sighting("ash", 1)]), 0.9)]));
+        state.apply(&report(vec![("pods", Detection::Pods { current: 10, max: 100 }, 0.9)]));
+        assert_eq!(state.resources.unwrap().value.len(), 1);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::focus::{self, FocusBackend};
+use rdev::{simulate, Button, EventType, Key, SimulateError};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputError {
+    /// The OS refused to inject events; on macOS the app needs Accessibility access.
+    PermissionDenied,
+    /// The window we were acting on no longer exists.
+    TargetGone,
+    /// Focus was requested but another application stayed in front.
+    FocusFailed { pid: i32, frontmost: Option<i32> },
+    /// Input for this PID was refused because another application is in front.
+    NotFrontmost { pid: i32, frontmost: Option<i32> },
+    /// The point lies outside the target window.
+    OutOfBounds { x: f64, y: f64 },
+    /// No key types this character or triggers this action.
+    NoKey(String),
+    /// The action queue dropped the input before it ran.
+    Cancelled(String),
+    /// The backend failed for another reason.
+    Backend(String),
+}
+
+impl InputError {
+    /// Whether trying the same input again could work. Missing permissions or a closed
+    /// window won't fix themselves.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            InputError::Backend(_) | InputError::FocusFailed { .. } | InputError::NotFrontmost { .. }
+        )
+    }
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::PermissionDenied => write!(
+                f,
+                "Input permission denied. Grant Accessibility access in System Settings > Privacy & Security."
+            ),
+            InputError::TargetGone => write!(f, "Target window is gone."),
+            InputError::FocusFailed { pid, frontmost: Some(other) } => {
+                write!(f, "Could not focus PID {}; PID {} is still in front.", pid, other)
+            }
+            InputError::FocusFailed { pid, frontmost: None } => write!(f, "Could not focus PID {}.", pid),
+            InputError::NotFrontmost { pid, frontmost } => match frontmost {
+                Some(other) => write!(f, "PID {} is not in front (PID {} is); input refused.", pid, other),
+                None => write!(f, "PID {} is not in front; input refused.", pid),
+            },
+            InputError::OutOfBounds { x, y } => write!(f, "Point ({:.0}, {:.0}) is outside the target window's safe area.", x, y),
+            InputError::NoKey(what) => write!(f, "No key for {}.", what),
+            InputError::Cancelled(reason) => write!(f, "Input cancelled: {}", reason),
+            InputError::Backend(msg) => write!(f, "Input failed: {}", msg),
+        }
+    }
+}
+
+/// The raw operations everything in `InputManager` is built from.
+pub trait InputBackend: Send + Sync {
+    fn mouse_move(&self, x: f64, y: f64) -> Result<(), InputError>;
+    fn button(&self, button: Button, pressed: bool) -> Result<(), InputError>;
+    fn key(&self, key: Key, pressed: bool) -> Result<(), InputError>;
+    fn scroll(&self, dx: i64, dy: i64) -> Result<(), InputError>;
+    /// Brings the application with this PID to the front and confirms it got there.
+    fn focus(&self, pid: i32) -> Result<(), InputError>;
+    /// PID of the application currently in front, if known.
+    fn frontmost(&self) -> Option<i32>;
+}
+
+#[cfg(target_os = "macos")]
+#[link(name = "ApplicationServices", kind = "framework")]
+unsafe extern "C" {
+    fn AXIsProcessTrusted() -> bool;
+}
+
+/// Whether the OS lets this process inject input.
+fn input_permitted() -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        // SAFETY: takes no arguments and only reads the process's trust state.
+        unsafe { AXIsProcessTrusted() }
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        true
+    }
+}
+
+/// How long after injecting an event its echo may come back through `rdev::listen`.
+const ECHO_WINDOW: Duration = Duration::from_millis(500);
+/// Injected and observed pointer positions can differ by rounding.
+const ECHO_TOLERANCE: f64 = 2.0;
+
+/// Events we injected recently, so listeners can tell them from the user's own input.
+#[derive(Default)]
+pub struct InjectionLog {
+    recent: Mutex<VecDeque<(EventType, Instant)>>,
+}
+
+impl InjectionLog {
+    fn record(&self, event_type: EventType) {
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|(_, at)| at.elapsed() < ECHO_WINDOW);
+        recent.push_back((event_type, Instant::now()));
+    }
+
+    /// Whether `event_type` is the echo of something we injected. Each injection
+    /// matches one echo at most.
+    pub fn is_echo(&self, event_type: &EventType) -> bool {
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|(_, at)| at.elapsed() < ECHO_WINDOW);
+        match recent.iter().position(|(sent, _)| same_event(sent, event_type)) {
+            Some(index) => {
+                recent.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn same_event(sent: &EventType, seen: &EventType) -> bool {
+    match (sent, seen) {
+        (EventType::MouseMove { x: x1, y: y1 }, EventType::MouseMove { x: x2, y: y2 }) => {
+            (x1 - x2).abs() <= ECHO_TOLERANCE && (y1 - y2).abs() <= ECHO_TOLERANCE
+        }
+        // Scroll deltas get rescaled by the OS.
+        (EventType::Wheel { .. }, EventType::Wheel { .. }) => true,
+        _ => sent == seen,
+    }
+}
+
+/// Real input through `rdev::simulate`, with focus through the platform's window APIs.
+pub struct RdevBackend {
+    focus: Box<dyn FocusBackend>,
+    injected: Arc<InjectionLog>,
+}
+
+impl RdevBackend {
+    pub fn new() -> Self {
+        Self {
+            focus: focus::native(),
+            injected: Arc::new(InjectionLog::default()),
+        }
+    }
+
+    /// Everything this backend sends is noted here before it goes out.
+    pub fn injections(&self) -> Arc<InjectionLog> {
+        Arc::clone(&self.injected)
+    }
+
+    fn send_event(&self, event_type: &EventType) -> Result<(), InputError> {
+        self.injected.record(*event_type);
+        match simulate(event_type) {
+            Ok(()) => Ok(()),
+            // rdev doesn't say why; the usual reason is missing permissions.
+            Err(SimulateError) if !input_permitted() => Err(InputError::PermissionDenied),
+            Err(SimulateError) => Err(InputError::Backend(format!("could not simulate {:?}", event_type))),
+        }
+    }
+}
+
+impl InputBackend for RdevBackend {
+    fn mouse_move(&self, x: f64, y: f64) -> Result<(), InputError> {
+        self.send_event(&EventType::MouseMove { x, y })
+    }
+
+    fn button(&self, button: Button, pressed: bool) -> Result<(), InputError> {
+        if pressed {
+            self.send_event(&EventType::ButtonPress(button))
+        } else {
+            self.send_event(&EventType::ButtonRelease(button))
+        }
+    }
+
+    fn key(&self, key: Key, pressed: bool) -> Result<(), InputError> {
+        if pressed {
+            self.send_event(&EventType::KeyPress(key))
+        } else {
+            self.send_event(&EventType::KeyRelease(key))
+        }
+    }
+
+    fn scroll(&self, dx: i64, dy: i64) -> Result<(), InputError> {
+        self.send_event(&EventType::Wheel { delta_x: dx, delta_y: dy })
+    }
+
+    fn focus(&self, pid: i32) -> Result<(), InputError> {
+        focus::focus_and_verify(self.focus.as_ref(), pid)
+    }
+
+    fn frontmost(&self) -> Option<i32> {
+        self.focus.frontmost_pid()
+    }
+}
+
+impl Default for RdevBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    MouseMove { x: f64, y: f64 },
+    Button { button: Button, pressed: bool },
+    Key { key: Key, pressed: bool },
+    Scroll { dx: i64, dy: i64 },
+    Focus { pid: i32 },
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedEvent {
+    /// Time since the backend was created.
+    pub at: Duration,
+    pub event: InputEvent,
+}
+
+/// Test backend storing every event instead of sending it, so click sequences can be
+/// checked without touching the real mouse. Failures can be queued to test how callers
+/// handle them.
+#[cfg(test)]
+pub struct RecordingBackend {
+    started: Instant,
+    events: Mutex<Vec<RecordedEvent>>,
+    failures: Mutex<VecDeque<InputError>>,
+}
+
+#[cfg(test)]
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            events: Mutex::new(Vec::new()),
+            failures: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Makes the next event fail with `error` instead of being recorded. Queued failures
+    /// are used up one event at a time.
+    pub fn fail_next(&self, error: InputError) {
+        self.failures.lock().unwrap().push_back(error);
+    }
+
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Positions of every left click, taken from the last move before each press.
+    pub fn clicks(&self) -> Vec<(f64, f64)> {
+        let mut position = None;
+        let mut clicks = Vec::new();
+        for recorded in self.events.lock().unwrap().iter() {
+            match recorded.event {
+                InputEvent::MouseMove { x, y } => position = Some((x, y)),
+                InputEvent::Button { button: Button::Left, pressed: true } => clicks.extend(position),
+                _ => {}
+            }
+        }
+        clicks
+    }
+
+    fn record(&self, event: InputEvent) -> Result<(), InputError> {
+        if let Some(error) = self.failures.lock().unwrap().pop_front() {
+            return Err(error);
+        }
+        self.events.lock().unwrap().push(RecordedEvent {
+            at: self.started.elapsed(),
+            event,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Default for RecordingBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl InputBackend for RecordingBackend {
+    fn mouse_move(&self, x: f64, y: f64) -> Result<(), InputError> {
+        self.record(InputEvent::MouseMove { x, y })
+    }
+
+    fn button(&self, button: Button, pressed: bool) -> Result<(), InputError> {
+        self.record(InputEvent::Button { button, pressed })
+    }
+
+    fn key(&self, key: Key, pressed: bool) -> Result<(), InputError> {
+        self.record(InputEvent::Key { key, pressed })
+    }
+
+    fn scroll(&self, dx: i64, dy: i64) -> Result<(), InputError> {
+        self.record(InputEvent::Scroll { dx, dy })
+    }
+
+    fn focus(&self, pid: i32) -> Result<(), InputError> {
+        self.record(InputEvent::Focus { pid })
+    }
+
+    /// The last PID focused through this backend.
+    fn frontmost(&self) -> Option<i32> {
+        self.events.lock().unwrap().iter().rev().find_map(|recorded| match recorded.event {
+            InputEvent::Focus { pid } => Some(pid),
+            _ => None,
+        })
+    }
+}
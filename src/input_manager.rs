@@ -1,43 +1,500 @@
-use rdev::{simulate, Button, EventType, SimulateError};
+use crate::action_queue::InputAction;
+use crate::dry_run::DryRun;
+use crate::input_backend::{InputBackend, InputError};
+use crate::keymap::{KeyCombo, KeyboardLayout};
+use rdev::{Button, Key};
+use std::sync::Arc;
 use std::{thread, time::Duration};
 
-pub struct InputManager;
+/// Pause between key events, so the game doesn't miss or reorder them.
+const KEY_DELAY: Duration = Duration::from_millis(30);
+
+/// Pauses inside mouse gestures. The game drops clicks that come too fast after a move,
+/// and treats two clicks as a double-click only within its own gap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureTimings {
+    /// After moving, before pressing.
+    pub settle: Duration,
+    /// Between press and release.
+    pub hold: Duration,
+    /// Between the two clicks of a double-click.
+    pub double_click_gap: Duration,
+    /// Intermediate moves of a drag, so the game sees the item being carried.
+    pub drag_steps: u32,
+    pub drag_step_delay: Duration,
+    /// Between wheel notches.
+    pub scroll_delay: Duration,
+}
+
+impl Default for GestureTimings {
+    fn default() -> Self {
+        Self {
+            settle: Duration::from_millis(50),
+            hold: Duration::from_millis(50),
+            double_click_gap: Duration::from_millis(80),
+            drag_steps: 10,
+            drag_step_delay: Duration::from_millis(15),
+            scroll_delay: Duration::from_millis(40),
+        }
+    }
+}
+
+/// High-level input on top of an `InputBackend`. Cheap to clone; clones share the backend.
+#[derive(Clone)]
+pub struct InputManager {
+    backend: Arc<dyn InputBackend>,
+    pub timings: GestureTimings,
+    dry_run: Option<Arc<DryRun>>,
+}
 
 impl InputManager {
-    pub fn new() -> Self {
-        Self
+    pub fn with_backend(backend: Arc<dyn InputBackend>) -> Self {
+        Self {
+            backend,
+            timings: GestureTimings::default(),
+            dry_run: None,
+        }
+    }
+
+    /// Lets `dry_run` hold back every action while it is switched on.
+    pub fn with_dry_run(mut self, dry_run: Arc<DryRun>) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// True if dry run swallowed `action`.
+    fn dry(&self, action: InputAction) -> bool {
+        self.dry_run.as_ref().is_some_and(|d| d.intercept(&action))
     }
 
     /// Clicks at the specified coordinates using native input simulation.
-    pub fn click_at(&self, x: f64, y: f64) {
-        // Move to position
-        self.send_event(&EventType::MouseMove { x, y });
-        thread::sleep(Duration::from_millis(50));
-
-        // Click
-        self.send_event(&EventType::ButtonPress(Button::Left));
-        thread::sleep(Duration::from_millis(50));
-        self.send_event(&EventType::ButtonRelease(Button::Left));
-    }
-
-    /// Focuses the window with the given PID using native macOS calls.
-    pub fn focus_window(&self, pid: i32) {
-        // Phase B: Native Focus Logic
-        // In a real macOS implementation, this would use the Accessibility API or AppKit.
-        // Example (conceptual):
-        // let app = NSRunningApplication::runningApplicationWithProcessIdentifier(pid);
-        // app.activateWithOptions(NSApplicationActivateIgnoringOtherApps);
-
-        // For now, we'll log the intent as we've removed osascript as requested.
-        println!("Native focus requested for PID: {}", pid);
-    }
-
-    fn send_event(&self, event_type: &EventType) {
-        match simulate(event_type) {
-            Ok(()) => (),
-            Err(SimulateError) => {
-                eprintln!("Failed to simulate event: {:?}", event_type);
+    pub fn click_at(&self, x: f64, y: f64) -> Result<(), InputError> {
+        if self.dry(InputAction::Click { x, y }) {
+            return Ok(());
+        }
+        self.move_to(x, y)?;
+        self.click(Button::Left)
+    }
+
+    /// Right-click, for context menus.
+    pub fn right_click_at(&self, x: f64, y: f64) -> Result<(), InputError> {
+        if self.dry(InputAction::RightClick { x, y }) {
+            return Ok(());
+        }
+        self.move_to(x, y)?;
+        self.click(Button::Right)
+    }
+
+    /// Double-click, e.g. to use an item.
+    pub fn double_click_at(&self, x: f64, y: f64) -> Result<(), InputError> {
+        if self.dry(InputAction::DoubleClick { x, y }) {
+            return Ok(());
+        }
+        self.move_to(x, y)?;
+        self.click(Button::Left)?;
+        thread::sleep(self.timings.double_click_gap);
+        self.click(Button::Left)
+    }
+
+    /// Drags with the left button held from `from` to `to`, such as an item into the bank.
+    pub fn drag(&self, from: (f64, f64), to: (f64, f64)) -> Result<(), InputError> {
+        if self.dry(InputAction::Drag { from, to }) {
+            return Ok(());
+        }
+        // 1. Grab
+        self.move_to(from.0, from.1)?;
+        self.backend.button(Button::Left, true)?;
+        thread::sleep(self.timings.hold);
+
+        // 2. Carry in small steps
+        let steps = self.timings.drag_steps.max(1);
+        let carried = (1..=steps).try_for_each(|i| {
+            let t = i as f64 / steps as f64;
+            self.backend.mouse_move(from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)?;
+            thread::sleep(self.timings.drag_step_delay);
+            Ok(())
+        });
+
+        // 3. Drop, even if a move failed, so the button isn't left held
+        thread::sleep(self.timings.settle);
+        let released = self.backend.button(Button::Left, false);
+        carried.and(released)
+    }
+
+    /// Scrolls `notches` wheel steps over a point; positive scrolls down.
+    pub fn scroll_at(&self, x: f64, y: f64, notches: i32) -> Result<(), InputError> {
+        if self.dry(InputAction::Scroll { x, y, notches }) {
+            return Ok(());
+        }
+        self.move_to(x, y)?;
+        let step = if notches < 0 { 1 } else { -1 };
+        for _ in 0..notches.unsigned_abs() {
+            self.backend.scroll(0, step)?;
+            thread::sleep(self.timings.scroll_delay);
+        }
+        Ok(())
+    }
+
+    /// Rests the cursor over a point long enough for its tooltip to show.
+    pub fn hover(&self, x: f64, y: f64, hold: Duration) -> Result<(), InputError> {
+        if self.dry(InputAction::Hover { x, y, hold }) {
+            return Ok(());
+        }
+        self.backend.mouse_move(x, y)?;
+        thread::sleep(hold);
+        Ok(())
+    }
+
+    fn move_to(&self, x: f64, y: f64) -> Result<(), InputError> {
+        self.backend.mouse_move(x, y)?;
+        thread::sleep(self.timings.settle);
+        Ok(())
+    }
+
+    fn click(&self, button: Button) -> Result<(), InputError> {
+        self.backend.button(button, true)?;
+        thread::sleep(self.timings.hold);
+        self.backend.button(button, false)
+    }
+
+    /// Focuses the window with the given PID; fails if it didn't come to the front.
+    pub fn focus_window(&self, pid: i32) -> Result<(), InputError> {
+        if self.dry(InputAction::Focus(pid)) {
+            return Ok(());
+        }
+        self.backend.focus(pid)
+    }
+
+    /// PID of the application in front, if the backend can tell. In a dry run, the one
+    /// that would be.
+    pub fn frontmost(&self) -> Option<i32> {
+        if let Some(dry_run) = self.dry_run.as_ref().filter(|d| d.is_enabled()) {
+            return dry_run.focused();
+        }
+        self.backend.frontmost()
+    }
+
+    #[cfg(test)]
+    pub fn key_down(&self, key: Key) -> Result<(), InputError> {
+        if self.dry_run.as_ref().is_some_and(|d| d.intercept_key_down(key)) {
+            return Ok(());
+        }
+        self.backend.key(key, true)
+    }
+
+    #[cfg(test)]
+    pub fn key_up(&self, key: Key) -> Result<(), InputError> {
+        // Only held back if the matching key_down was; that one was already logged.
+        if self.dry_run.as_ref().is_some_and(|d| d.release(key)) {
+            return Ok(());
+        }
+        self.backend.key(key, false)
+    }
+
+    /// Presses and releases a key.
+    pub fn press_key(&self, key: Key) -> Result<(), InputError> {
+        if self.dry(InputAction::Key(key)) {
+            return Ok(());
+        }
+        self.press_with(&[], key)
+    }
+
+    /// Presses `key` while holding the combo's modifiers, e.g. Ctrl+C.
+    pub fn press_combo(&self, combo: &KeyCombo) -> Result<(), InputError> {
+        if self.dry(InputAction::Combo(combo.clone())) {
+            return Ok(());
+        }
+        self.press_with(&combo.modifiers, combo.key)
+    }
+
+    /// Types `text` as key presses for `layout`, accents included. Fails before pressing
+    /// anything if a character can't be typed on that layout.
+    pub fn type_text(&self, text: &str, layout: KeyboardLayout) -> Result<(), InputError> {
+        let strokes = text
+            .chars()
+            .map(|c| layout.strokes(c).ok_or_else(|| InputError::NoKey(format!("'{}' on {:?}", c, layout))))
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.dry(InputAction::Type { text: text.to_string(), layout }) {
+            return Ok(());
+        }
+
+        for stroke in strokes.into_iter().flatten() {
+            let mut modifiers = Vec::new();
+            if stroke.shift {
+                modifiers.push(Key::ShiftLeft);
             }
+            if stroke.alt {
+                modifiers.push(layout.alt_key());
+            }
+            self.press_with(&modifiers, stroke.key)?;
         }
+        Ok(())
+    }
+
+    fn press_with(&self, modifiers: &[Key], key: Key) -> Result<(), InputError> {
+        // 1. Hold the modifiers
+        for (i, modifier) in modifiers.iter().enumerate() {
+            if let Err(err) = self.backend.key(*modifier, true) {
+                self.release(&modifiers[..i]);
+                return Err(err);
+            }
+        }
+
+        // 2. Tap the key
+        let result = self.backend.key(key, true).and_then(|()| {
+            thread::sleep(KEY_DELAY);
+            self.backend.key(key, false)
+        });
+
+        // 3. Release the modifiers even if the key failed, so none stays stuck
+        self.release(modifiers);
+        thread::sleep(KEY_DELAY);
+        result
+    }
+
+    fn release(&self, modifiers: &[Key]) {
+        for modifier in modifiers.iter().rev() {
+            let _ = self.backend.key(*modifier, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_queue::{ActionQueue, ActionRequest};
+    use crate::input_backend::{InputEvent, RecordingBackend};
+    use crate::safety::SafetyGuard;
+    use std::sync::mpsc;
+
+    fn recording() -> (InputManager, Arc<RecordingBackend>) {
+        let backend = Arc::new(RecordingBackend::new());
+        (InputManager::with_backend(backend.clone()), backend)
+    }
+
+    fn events(backend: &RecordingBackend) -> Vec<InputEvent> {
+        backend.events().into_iter().map(|recorded| recorded.event).collect()
+    }
+
+    fn button(button: Button, pressed: bool) -> InputEvent {
+        InputEvent::Button { button, pressed }
+    }
+
+    fn key(key: Key, pressed: bool) -> InputEvent {
+        InputEvent::Key { key, pressed }
+    }
+
+    #[test]
+    fn click_moves_settles_then_presses() {
+        let (input, backend) = recording();
+        input.click_at(10.0, 20.0).unwrap();
+
+        assert_eq!(
+            events(&backend),
+            vec![InputEvent::MouseMove { x: 10.0, y: 20.0 }, button(Button::Left, true), button(Button::Left, false)]
+        );
+        let at: Vec<Duration> = backend.events().iter().map(|recorded| recorded.at).collect();
+        assert!(at[1] - at[0] >= input.timings.settle);
+        assert!(at[2] - at[1] >= input.timings.hold);
+    }
+
+    #[test]
+    fn combo_holds_modifiers_around_the_key() {
+        let (input, backend) = recording();
+        let combo = KeyCombo {
+            modifiers: vec![Key::ControlLeft, Key::ShiftLeft],
+            key: Key::KeyC,
+        };
+        input.press_combo(&combo).unwrap();
+
+        assert_eq!(
+            events(&backend),
+            vec![
+                key(Key::ControlLeft, true),
+                key(Key::ShiftLeft, true),
+                key(Key::KeyC, true),
+                key(Key::KeyC, false),
+                key(Key::ShiftLeft, false),
+                key(Key::ControlLeft, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn double_click_is_two_clicks_after_one_move() {
+        let (input, backend) = recording();
+        input.double_click_at(3.0, 4.0).unwrap();
+
+        assert_eq!(
+            events(&backend),
+            vec![
+                InputEvent::MouseMove { x: 3.0, y: 4.0 },
+                button(Button::Left, true),
+                button(Button::Left, false),
+                button(Button::Left, true),
+                button(Button::Left, false),
+            ]
+        );
+        let at: Vec<Duration> = backend.events().iter().map(|recorded| recorded.at).collect();
+        assert!(at[3] - at[2] >= input.timings.double_click_gap);
+    }
+
+    #[test]
+    fn drag_carries_in_steps_between_press_and_release() {
+        let (mut input, backend) = recording();
+        input.timings.drag_steps = 4;
+        input.drag((0.0, 0.0), (40.0, 80.0)).unwrap();
+
+        let mut expected = vec![InputEvent::MouseMove { x: 0.0, y: 0.0 }, button(Button::Left, true)];
+        expected.extend([(10.0, 20.0), (20.0, 40.0), (30.0, 60.0), (40.0, 80.0)].map(|(x, y)| InputEvent::MouseMove { x, y }));
+        expected.push(button(Button::Left, false));
+        assert_eq!(events(&backend), expected);
+    }
+
+    #[test]
+    fn drag_presses_nothing_when_the_grab_fails() {
+        let (mut input, backend) = recording();
+        input.timings.drag_steps = 2;
+        backend.fail_next(InputError::Backend("no pointer".into()));
+        assert!(input.drag((0.0, 0.0), (10.0, 10.0)).is_err());
+        assert!(backend.events().is_empty());
+    }
+
+    #[test]
+    fn scroll_moves_then_sends_one_event_per_notch() {
+        let (input, backend) = recording();
+        input.scroll_at(7.0, 8.0, 3).unwrap();
+        input.scroll_at(7.0, 8.0, -2).unwrap();
+
+        let move_to = InputEvent::MouseMove { x: 7.0, y: 8.0 };
+        let down = InputEvent::Scroll { dx: 0, dy: -1 };
+        let up = InputEvent::Scroll { dx: 0, dy: 1 };
+        assert_eq!(events(&backend), vec![move_to, down, down, down, move_to, up, up]);
+    }
+
+    #[test]
+    fn hover_moves_and_holds_without_clicking() {
+        let (input, backend) = recording();
+        let hold = Duration::from_millis(120);
+        let started = std::time::Instant::now();
+        input.hover(9.0, 9.0, hold).unwrap();
+
+        assert!(started.elapsed() >= hold);
+        assert_eq!(events(&backend), vec![InputEvent::MouseMove { x: 9.0, y: 9.0 }]);
+        assert!(backend.clicks().is_empty());
+    }
+
+    #[test]
+    fn failures_stop_the_gesture_and_reach_the_caller() {
+        let (input, backend) = recording();
+        backend.fail_next(InputError::PermissionDenied);
+        assert_eq!(input.click_at(1.0, 2.0), Err(InputError::PermissionDenied));
+        assert!(backend.events().is_empty());
+
+        // A failed modifier is reported and nothing is left held.
+        backend.fail_next(InputError::Backend("event tap dropped".into()));
+        let combo = KeyCombo {
+            modifiers: vec![Key::ControlLeft],
+            key: Key::KeyC,
+        };
+        assert!(input.press_combo(&combo).unwrap_err().is_retryable());
+        assert!(backend.events().is_empty());
+    }
+
+    #[test]
+    fn queued_request_reports_the_failed_step() {
+        let (input, backend) = recording();
+        let (log_tx, _log_rx) = mpsc::channel();
+        let queue = ActionQueue::new(input, Duration::ZERO, SafetyGuard::default(), log_tx);
+        backend.fail_next(InputError::FocusFailed { pid: 42, frontmost: Some(7) });
+        let request = ActionRequest::new("click", vec![InputAction::Focus(42), InputAction::Click { x: 5.0, y: 6.0 }]);
+
+        assert_eq!(queue.run(request), Err(InputError::FocusFailed { pid: 42, frontmost: Some(7) }));
+        assert!(backend.clicks().is_empty());
+        queue.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn queued_request_runs_its_steps_in_order() {
+        let (input, backend) = recording();
+        let (log_tx, _log_rx) = mpsc::channel();
+        let queue = ActionQueue::new(input, Duration::ZERO, SafetyGuard::default(), log_tx);
+        let request = ActionRequest::new("click", vec![InputAction::Focus(42), InputAction::Click { x: 5.0, y: 6.0 }]);
+        queue.run(request).unwrap();
+
+        assert_eq!(
+            events(&backend),
+            vec![
+                InputEvent::Focus { pid: 42 },
+                InputEvent::MouseMove { x: 5.0, y: 6.0 },
+                button(Button::Left, true),
+                button(Button::Left, false),
+            ]
+        );
+        assert_eq!(backend.clicks(), vec![(5.0, 6.0)]);
+        queue.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn type_text_presses_each_stroke_with_its_modifiers() {
+        let (input, backend) = recording();
+        input.type_text("a1ê", KeyboardLayout::AzertyMac).unwrap();
+
+        assert_eq!(
+            events(&backend),
+            vec![
+                key(Key::KeyQ, true),
+                key(Key::KeyQ, false),
+                key(Key::ShiftLeft, true),
+                key(Key::Num1, true),
+                key(Key::Num1, false),
+                key(Key::ShiftLeft, false),
+                key(Key::LeftBracket, true),
+                key(Key::LeftBracket, false),
+                key(Key::KeyE, true),
+                key(Key::KeyE, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn type_text_holds_altgr_on_pc_azerty() {
+        let (input, backend) = recording();
+        input.type_text("@", KeyboardLayout::AzertyPc).unwrap();
+
+        assert_eq!(
+            events(&backend),
+            vec![key(Key::AltGr, true), key(Key::Num0, true), key(Key::Num0, false), key(Key::AltGr, false)]
+        );
+    }
+
+    #[test]
+    fn type_text_refuses_untypeable_text_before_pressing_anything() {
+        let (input, backend) = recording();
+        let err = input.type_text("a€", KeyboardLayout::Qwerty).unwrap_err();
+
+        assert_eq!(err, InputError::NoKey("'€' on Qwerty".to_string()));
+        assert!(events(&backend).is_empty());
+    }
+
+    #[test]
+    fn dry_run_holds_back_only_the_key_ups_it_intercepted() {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let dry_run = Arc::new(DryRun::new(log_tx));
+        let backend = Arc::new(RecordingBackend::new());
+        let input = InputManager::with_backend(backend.clone()).with_dry_run(Arc::clone(&dry_run));
+
+        // Down while dry run was on: its up is held back even after switching it off.
+        dry_run.set_enabled(true);
+        input.key_down(Key::KeyA).unwrap();
+        dry_run.set_enabled(false);
+        input.key_up(Key::KeyA).unwrap();
+
+        // Down while it was off: its up goes through even after switching it on.
+        input.key_down(Key::KeyB).unwrap();
+        dry_run.set_enabled(true);
+        input.key_up(Key::KeyB).unwrap();
+
+        assert_eq!(events(&backend), vec![key(Key::KeyB, true), key(Key::KeyB, false)]);
     }
 }
@@ -0,0 +1,483 @@
+use rdev::Key;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// One physical key press, with the modifiers it needs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyStroke {
+    pub key: Key,
+    pub shift: bool,
+    /// Option on macOS, AltGr on PC layouts.
+    pub alt: bool,
+}
+
+impl KeyStroke {
+    fn plain(key: Key) -> Self {
+        Self { key, shift: false, alt: false }
+    }
+
+    fn shifted(key: Key) -> Self {
+        Self { key, shift: true, alt: false }
+    }
+
+    fn alt(key: Key) -> Self {
+        Self { key, shift: false, alt: true }
+    }
+
+    fn alt_shifted(key: Key) -> Self {
+        Self { key, shift: true, alt: true }
+    }
+}
+
+/// The keyboard layout the OS is set to, which decides what each physical key types.
+///
+/// rdev keys are physical positions named after the US layout, so on AZERTY typing `a`
+/// means pressing `Key::KeyQ`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KeyboardLayout {
+    Qwerty,
+    /// macOS "French" layout.
+    AzertyMac,
+    /// Windows/Linux French layout.
+    AzertyPc,
+}
+
+impl KeyboardLayout {
+    /// Modifier for the third level of each key.
+    pub fn alt_key(&self) -> Key {
+        match self {
+            KeyboardLayout::AzertyPc => Key::AltGr,
+            _ => Key::Alt,
+        }
+    }
+
+    /// Key presses that type `c`, going through dead keys for accents the layout has no
+    /// key for (`ê` is `^` then `e`). `None` if the layout can't type it.
+    pub fn strokes(&self, c: char) -> Option<Vec<KeyStroke>> {
+        if let Some((stroke, dead)) = self.direct(c) {
+            // A dead key on its own needs a space to come out.
+            return Some(if dead { vec![stroke, KeyStroke::plain(Key::Space)] } else { vec![stroke] });
+        }
+
+        let (accent, base) = decompose(c)?;
+        let (dead, true) = self.direct(accent)? else { return None };
+        let mut strokes = vec![dead];
+        strokes.extend(self.strokes(base)?);
+        Some(strokes)
+    }
+
+    /// The stroke typing `c` directly, and whether it is a dead key.
+    fn direct(&self, c: char) -> Option<(KeyStroke, bool)> {
+        if c.is_ascii_alphabetic() {
+            let key = self.letter(c.to_ascii_lowercase())?;
+            let stroke = if c.is_ascii_uppercase() { KeyStroke::shifted(key) } else { KeyStroke::plain(key) };
+            return Some((stroke, false));
+        }
+        match c {
+            ' ' => return Some((KeyStroke::plain(Key::Space), false)),
+            '\n' => return Some((KeyStroke::plain(Key::Return), false)),
+            '\t' => return Some((KeyStroke::plain(Key::Tab), false)),
+            _ => {}
+        }
+        match self {
+            KeyboardLayout::Qwerty => qwerty(c).map(|s| (s, false)),
+            KeyboardLayout::AzertyMac => azerty_mac(c),
+            KeyboardLayout::AzertyPc => azerty_pc(c),
+        }
+    }
+
+    fn letter(&self, c: char) -> Option<Key> {
+        let c = match (self, c) {
+            (KeyboardLayout::Qwerty, c) => c,
+            // AZERTY swaps a/q and z/w, and moves m next to l.
+            (_, 'a') => 'q',
+            (_, 'q') => 'a',
+            (_, 'z') => 'w',
+            (_, 'w') => 'z',
+            (_, 'm') => return Some(Key::SemiColon),
+            (_, c) => c,
+        };
+        Some(match c {
+            'a' => Key::KeyA,
+            'b' => Key::KeyB,
+            'c' => Key::KeyC,
+            'd' => Key::KeyD,
+            'e' => Key::KeyE,
+            'f' => Key::KeyF,
+            'g' => Key::KeyG,
+            'h' => Key::KeyH,
+            'i' => Key::KeyI,
+            'j' => Key::KeyJ,
+            'k' => Key::KeyK,
+            'l' => Key::KeyL,
+            'm' => Key::KeyM,
+            'n' => Key::KeyN,
+            'o' => Key::KeyO,
+            'p' => Key::KeyP,
+            'q' => Key::KeyQ,
+            'r' => Key::KeyR,
+            's' => Key::KeyS,
+            't' => Key::KeyT,
+            'u' => Key::KeyU,
+            'v' => Key::KeyV,
+            'w' => Key::KeyW,
+            'x' => Key::KeyX,
+            'y' => Key::KeyY,
+            'z' => Key::KeyZ,
+            _ => return None,
+        })
+    }
+}
+
+const DIGIT_KEYS: [Key; 10] = [
+    Key::Num0,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+];
+
+fn digit_key(c: char) -> Option<Key> {
+    c.to_digit(10).map(|d| DIGIT_KEYS[d as usize])
+}
+
+/// Splits accented letters typed with a dead key into `(dead key char, base letter)`.
+fn decompose(c: char) -> Option<(char, char)> {
+    Some(match c {
+        'â' => ('^', 'a'),
+        'ê' => ('^', 'e'),
+        'î' => ('^', 'i'),
+        'ô' => ('^', 'o'),
+        'û' => ('^', 'u'),
+        'Â' => ('^', 'A'),
+        'Ê' => ('^', 'E'),
+        'Î' => ('^', 'I'),
+        'Ô' => ('^', 'O'),
+        'Û' => ('^', 'U'),
+        'ä' => ('¨', 'a'),
+        'ë' => ('¨', 'e'),
+        'ï' => ('¨', 'i'),
+        'ö' => ('¨', 'o'),
+        'ü' => ('¨', 'u'),
+        'ÿ' => ('¨', 'y'),
+        'Ä' => ('¨', 'A'),
+        'Ë' => ('¨', 'E'),
+        'Ï' => ('¨', 'I'),
+        'Ö' => ('¨', 'O'),
+        'Ü' => ('¨', 'U'),
+        _ => return None,
+    })
+}
+
+fn qwerty(c: char) -> Option<KeyStroke> {
+    if let Some(key) = digit_key(c) {
+        return Some(KeyStroke::plain(key));
+    }
+    let (key, shift) = match c {
+        '!' => (Key::Num1, true),
+        '@' => (Key::Num2, true),
+        '#' => (Key::Num3, true),
+        '$' => (Key::Num4, true),
+        '%' => (Key::Num5, true),
+        '^' => (Key::Num6, true),
+        '&' => (Key::Num7, true),
+        '*' => (Key::Num8, true),
+        '(' => (Key::Num9, true),
+        ')' => (Key::Num0, true),
+        '-' => (Key::Minus, false),
+        '_' => (Key::Minus, true),
+        '=' => (Key::Equal, false),
+        '+' => (Key::Equal, true),
+        '[' => (Key::LeftBracket, false),
+        '{' => (Key::LeftBracket, true),
+        ']' => (Key::RightBracket, false),
+        '}' => (Key::RightBracket, true),
+        '\\' => (Key::BackSlash, false),
+        '|' => (Key::BackSlash, true),
+        ';' => (Key::SemiColon, false),
+        ':' => (Key::SemiColon, true),
+        '\'' => (Key::Quote, false),
+        '"' => (Key::Quote, true),
+        ',' => (Key::Comma, false),
+        '<' => (Key::Comma, true),
+        '.' => (Key::Dot, false),
+        '>' => (Key::Dot, true),
+        '/' => (Key::Slash, false),
+        '?' => (Key::Slash, true),
+        '`' => (Key::BackQuote, false),
+        '~' => (Key::BackQuote, true),
+        _ => return None,
+    };
+    Some(KeyStroke { key, shift, alt: false })
+}
+
+fn azerty_mac(c: char) -> Option<(KeyStroke, bool)> {
+    // Digits are on the shifted level of the top row.
+    if let Some(key) = digit_key(c) {
+        return Some((KeyStroke::shifted(key), false));
+    }
+    let stroke = match c {
+        '&' => KeyStroke::plain(Key::Num1),
+        'é' => KeyStroke::plain(Key::Num2),
+        '"' => KeyStroke::plain(Key::Num3),
+        '\'' => KeyStroke::plain(Key::Num4),
+        '(' => KeyStroke::plain(Key::Num5),
+        '§' => KeyStroke::plain(Key::Num6),
+        'è' => KeyStroke::plain(Key::Num7),
+        '!' => KeyStroke::plain(Key::Num8),
+        'ç' => KeyStroke::plain(Key::Num9),
+        'à' => KeyStroke::plain(Key::Num0),
+        ')' => KeyStroke::plain(Key::Minus),
+        '°' => KeyStroke::shifted(Key::Minus),
+        '-' => KeyStroke::plain(Key::Equal),
+        '_' => KeyStroke::shifted(Key::Equal),
+        '$' => KeyStroke::plain(Key::RightBracket),
+        '*' => KeyStroke::shifted(Key::RightBracket),
+        'ù' => KeyStroke::plain(Key::Quote),
+        '%' => KeyStroke::shifted(Key::Quote),
+        '£' => KeyStroke::shifted(Key::BackSlash),
+        '@' => KeyStroke::plain(Key::BackQuote),
+        '#' => KeyStroke::shifted(Key::BackQuote),
+        '<' => KeyStroke::plain(Key::IntlBackslash),
+        '>' => KeyStroke::shifted(Key::IntlBackslash),
+        ',' => KeyStroke::plain(Key::KeyM),
+        '?' => KeyStroke::shifted(Key::KeyM),
+        ';' => KeyStroke::plain(Key::Comma),
+        '.' => KeyStroke::shifted(Key::Comma),
+        ':' => KeyStroke::plain(Key::Dot),
+        '/' => KeyStroke::shifted(Key::Dot),
+        '=' => KeyStroke::plain(Key::Slash),
+        '+' => KeyStroke::shifted(Key::Slash),
+        '€' => KeyStroke::alt(Key::RightBracket),
+        '{' => KeyStroke::alt(Key::Num5),
+        '}' => KeyStroke::alt(Key::Minus),
+        '[' => KeyStroke::alt_shifted(Key::Num5),
+        ']' => KeyStroke::alt_shifted(Key::Minus),
+        '|' => KeyStroke::alt_shifted(Key::KeyL),
+        '\\' => KeyStroke::alt_shifted(Key::Dot),
+        '^' => return Some((KeyStroke::plain(Key::LeftBracket), true)),
+        '¨' => return Some((KeyStroke::shifted(Key::LeftBracket), true)),
+        '`' => return Some((KeyStroke::plain(Key::BackSlash), true)),
+        _ => return None,
+    };
+    Some((stroke, false))
+}
+
+fn azerty_pc(c: char) -> Option<(KeyStroke, bool)> {
+    if let Some(key) = digit_key(c) {
+        return Some((KeyStroke::shifted(key), false));
+    }
+    let stroke = match c {
+        '&' => KeyStroke::plain(Key::Num1),
+        'é' => KeyStroke::plain(Key::Num2),
+        '"' => KeyStroke::plain(Key::Num3),
+        '\'' => KeyStroke::plain(Key::Num4),
+        '(' => KeyStroke::plain(Key::Num5),
+        '-' => KeyStroke::plain(Key::Num6),
+        'è' => KeyStroke::plain(Key::Num7),
+        '_' => KeyStroke::plain(Key::Num8),
+        'ç' => KeyStroke::plain(Key::Num9),
+        'à' => KeyStroke::plain(Key::Num0),
+        ')' => KeyStroke::plain(Key::Minus),
+        '°' => KeyStroke::shifted(Key::Minus),
+        '=' => KeyStroke::plain(Key::Equal),
+        '+' => KeyStroke::shifted(Key::Equal),
+        '$' => KeyStroke::plain(Key::RightBracket),
+        '£' => KeyStroke::shifted(Key::RightBracket),
+        'ù' => KeyStroke::plain(Key::Quote),
+        '%' => KeyStroke::shifted(Key::Quote),
+        '*' => KeyStroke::plain(Key::BackSlash),
+        'µ' => KeyStroke::shifted(Key::BackSlash),
+        '²' => KeyStroke::plain(Key::BackQuote),
+        '<' => KeyStroke::plain(Key::IntlBackslash),
+        '>' => KeyStroke::shifted(Key::IntlBackslash),
+        ',' => KeyStroke::plain(Key::KeyM),
+        '?' => KeyStroke::shifted(Key::KeyM),
+        ';' => KeyStroke::plain(Key::Comma),
+        '.' => KeyStroke::shifted(Key::Comma),
+        ':' => KeyStroke::plain(Key::Dot),
+        '/' => KeyStroke::shifted(Key::Dot),
+        '!' => KeyStroke::plain(Key::Slash),
+        '§' => KeyStroke::shifted(Key::Slash),
+        '#' => KeyStroke::alt(Key::Num3),
+        '{' => KeyStroke::alt(Key::Num4),
+        '[' => KeyStroke::alt(Key::Num5),
+        '|' => KeyStroke::alt(Key::Num6),
+        '\\' => KeyStroke::alt(Key::Num8),
+        '@' => KeyStroke::alt(Key::Num0),
+        ']' => KeyStroke::alt(Key::Minus),
+        '}' => KeyStroke::alt(Key::Equal),
+        '€' => KeyStroke::alt(Key::KeyE),
+        '~' => return Some((KeyStroke::alt(Key::Num2), true)),
+        '`' => return Some((KeyStroke::alt(Key::Num7), true)),
+        '^' => return Some((KeyStroke::plain(Key::LeftBracket), true)),
+        '¨' => return Some((KeyStroke::shifted(Key::LeftBracket), true)),
+        _ => return None,
+    };
+    Some((stroke, false))
+}
+
+/// Something the game lets you do from the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GameAction {
+    /// Spell bar slot, 1 to 10.
+    Spell(u8),
+    Inventory,
+    Characteristics,
+    Spells,
+    Quests,
+    WorldMap,
+    Havenbag,
+    /// Opens the chat input, and sends the message when it is open.
+    Chat,
+    EndTurn,
+    CloseWindow,
+}
+
+/// A key with the modifiers held while it is pressed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyCombo {
+    #[serde(default)]
+    pub modifiers: Vec<Key>,
+    pub key: Key,
+}
+
+impl KeyCombo {
+    pub fn key(key: Key) -> Self {
+        Self { modifiers: Vec::new(), key }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Binding {
+    pub action: GameAction,
+    pub keys: KeyCombo,
+}
+
+/// Which physical keys trigger which game actions, and the layout used to type text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keymap {
+    pub layout: KeyboardLayout,
+    pub bindings: Vec<Binding>,
+}
+
+impl Keymap {
+    /// The game's default shortcuts on `layout`. Letter shortcuts follow the printed
+    /// letter, so they move with the layout; the spell bar is the top row whatever it types.
+    pub fn defaults(layout: KeyboardLayout) -> Self {
+        let letter = |c: char| KeyCombo::key(layout.letter(c).unwrap_or(Key::Unknown(0)));
+        let mut bindings: Vec<Binding> = (1..=10u8)
+            .map(|slot| Binding {
+                action: GameAction::Spell(slot),
+                keys: KeyCombo::key(DIGIT_KEYS[(slot % 10) as usize]),
+            })
+            .collect();
+        bindings.extend([
+            Binding { action: GameAction::Inventory, keys: letter('i') },
+            Binding { action: GameAction::Characteristics, keys: letter('c') },
+            Binding { action: GameAction::Spells, keys: letter('s') },
+            Binding { action: GameAction::Quests, keys: letter('q') },
+            Binding { action: GameAction::WorldMap, keys: letter('m') },
+            Binding { action: GameAction::Havenbag, keys: letter('h') },
+            Binding { action: GameAction::Chat, keys: KeyCombo::key(Key::Return) },
+            Binding { action: GameAction::EndTurn, keys: KeyCombo::key(Key::F1) },
+            Binding { action: GameAction::CloseWindow, keys: KeyCombo::key(Key::Escape) },
+        ]);
+        Self { layout, bindings }
+    }
+
+    /// Loads the keymap from `path`, using the AZERTY (macOS) defaults if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("Failed to parse keymap {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::defaults(KeyboardLayout::AzertyMac)),
+            Err(e) => Err(format!("Failed to read keymap {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn combo(&self, action: GameAction) -> Option<&KeyCombo> {
+        self.bindings.iter().find(|b| b.action == action).map(|b| &b.keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyboardLayout::{AzertyMac, AzertyPc, Qwerty};
+
+    fn plain(key: Key) -> KeyStroke {
+        KeyStroke::plain(key)
+    }
+
+    fn shifted(key: Key) -> KeyStroke {
+        KeyStroke::shifted(key)
+    }
+
+    #[test]
+    fn letters_follow_the_layout() {
+        assert_eq!(Qwerty.strokes('a'), Some(vec![plain(Key::KeyA)]));
+        assert_eq!(Qwerty.strokes('M'), Some(vec![shifted(Key::KeyM)]));
+        for layout in [AzertyMac, AzertyPc] {
+            assert_eq!(layout.strokes('a'), Some(vec![plain(Key::KeyQ)]));
+            assert_eq!(layout.strokes('Z'), Some(vec![shifted(Key::KeyW)]));
+            assert_eq!(layout.strokes('m'), Some(vec![plain(Key::SemiColon)]));
+            assert_eq!(layout.strokes('b'), Some(vec![plain(Key::KeyB)]));
+        }
+    }
+
+    #[test]
+    fn digits_need_shift_on_azerty_only() {
+        assert_eq!(Qwerty.strokes('1'), Some(vec![plain(Key::Num1)]));
+        assert_eq!(AzertyMac.strokes('1'), Some(vec![shifted(Key::Num1)]));
+        assert_eq!(AzertyPc.strokes('0'), Some(vec![shifted(Key::Num0)]));
+        // The unshifted top row types symbols instead
+        assert_eq!(AzertyMac.strokes('&'), Some(vec![plain(Key::Num1)]));
+        assert_eq!(AzertyPc.strokes('é'), Some(vec![plain(Key::Num2)]));
+    }
+
+    #[test]
+    fn punctuation_differs_between_azerty_variants() {
+        assert_eq!(Qwerty.strokes('!'), Some(vec![shifted(Key::Num1)]));
+        assert_eq!(AzertyMac.strokes('!'), Some(vec![plain(Key::Num8)]));
+        assert_eq!(AzertyPc.strokes('!'), Some(vec![plain(Key::Slash)]));
+        assert_eq!(AzertyMac.strokes('@'), Some(vec![plain(Key::BackQuote)]));
+        assert_eq!(AzertyPc.strokes('@'), Some(vec![KeyStroke::alt(Key::Num0)]));
+        assert_eq!(AzertyPc.alt_key(), Key::AltGr);
+        assert_eq!(AzertyMac.alt_key(), Key::Alt);
+    }
+
+    #[test]
+    fn accents_go_through_dead_keys() {
+        for layout in [AzertyMac, AzertyPc] {
+            assert_eq!(layout.strokes('ê'), Some(vec![plain(Key::LeftBracket), plain(Key::KeyE)]));
+            assert_eq!(layout.strokes('ë'), Some(vec![shifted(Key::LeftBracket), plain(Key::KeyE)]));
+            assert_eq!(layout.strokes('Â'), Some(vec![plain(Key::LeftBracket), shifted(Key::KeyQ)]));
+            // A lone dead key needs a space after it
+            assert_eq!(layout.strokes('^'), Some(vec![plain(Key::LeftBracket), plain(Key::Space)]));
+        }
+    }
+
+    #[test]
+    fn unsupported_characters_have_no_strokes() {
+        // US QWERTY has a `^` key, but it isn't a dead key
+        assert_eq!(Qwerty.strokes('ê'), None);
+        assert_eq!(Qwerty.strokes('€'), None);
+        for layout in [Qwerty, AzertyMac, AzertyPc] {
+            assert_eq!(layout.strokes('漢'), None);
+        }
+    }
+
+    #[test]
+    fn defaults_bind_letters_by_what_they_type() {
+        let azerty = Keymap::defaults(AzertyMac);
+        assert_eq!(azerty.combo(GameAction::Quests), Some(&KeyCombo::key(Key::KeyA)));
+        assert_eq!(azerty.combo(GameAction::Spell(10)), Some(&KeyCombo::key(Key::Num0)));
+        let qwerty = Keymap::defaults(Qwerty);
+        assert_eq!(qwerty.combo(GameAction::Quests), Some(&KeyCombo::key(Key::KeyQ)));
+    }
+}
@@ -0,0 +1,363 @@
+use crate::action_queue::{ActionQueue, ActionRequest, InputAction};
+use crate::coords::{CoordTransform, ScreenPoint};
+use crate::detector::ScreenKind;
+use crate::game_state::GameState;
+use crate::keymap::KeyCombo;
+use crate::safety::InputTarget;
+use crate::vision_engine::WindowGeometry;
+use crate::worker::CancelToken;
+use rdev::{Button, Event, EventType, Key};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub const MACRO_DIR: &str = "./macros";
+
+/// Pauses shorter than this between two recorded steps aren't kept.
+const MIN_WAIT: Duration = Duration::from_millis(200);
+/// A press and release further apart than this, in points, is a drag.
+const DRAG_THRESHOLD: f64 = 6.0;
+/// Two clicks on the same spot within this are a double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+/// How long a recorded screen checkpoint waits by default.
+const CHECKPOINT_TIMEOUT_MS: u64 = 10_000;
+const POLL: Duration = Duration::from_millis(50);
+
+/// One step of a macro. Positions are fractions of the window size, like in the world
+/// model, so a macro keeps working after the window is moved or resized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum MacroStep {
+    Click { x: f32, y: f32 },
+    RightClick { x: f32, y: f32 },
+    DoubleClick { x: f32, y: f32 },
+    Drag { from: (f32, f32), to: (f32, f32) },
+    Scroll { x: f32, y: f32, notches: i32 },
+    Key { keys: KeyCombo },
+    Wait { ms: u64 },
+    /// Checkpoint: holds until the game shows `screen`, failing the macro after `timeout_ms`.
+    WaitFor { screen: ScreenKind, timeout_ms: u64 },
+}
+
+/// A recorded routine, stored as JSON so it can be edited by hand.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+impl Macro {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read macro {}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Failed to parse macro {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("Failed to serialize macro: {}", e))?;
+        std::fs::write(path, json).map_err(|e| format!("Failed to write macro {}: {}", path.display(), e))
+    }
+
+    /// Macro files in `dir`, sorted by name.
+    pub fn list(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        paths.sort();
+        paths
+    }
+
+    /// Plays the macro into the target window, step by step, through the action queue.
+    /// Blocks until it's done; fails on the first step that fails or checkpoint that times out.
+    /// Cancelling `cancel` also cancels the step waiting in the queue.
+    pub fn play(
+        &self,
+        actions: &ActionQueue,
+        target: &InputTarget,
+        geometry: &Mutex<Option<WindowGeometry>>,
+        state: &watch::Receiver<GameState>,
+        cancel: &CancelToken,
+    ) -> Result<(), String> {
+        for (i, step) in self.steps.iter().enumerate() {
+            let fail = |err: String| format!("Macro {} failed at step {} ({:?}): {}", self.name, i + 1, step, err);
+            if cancel.is_cancelled() {
+                return Err(format!("Macro {} stopped at step {}.", self.name, i + 1));
+            }
+
+            // 1. Waits and checkpoints don't touch the queue
+            match step {
+                MacroStep::Wait { ms } => {
+                    if !cancel.sleep(Duration::from_millis(*ms)) {
+                        return Err(format!("Macro {} stopped at step {}.", self.name, i + 1));
+                    }
+                    continue;
+                }
+                MacroStep::WaitFor { screen, timeout_ms } => {
+                    wait_for_screen(state, *screen, Duration::from_millis(*timeout_ms), cancel).map_err(fail)?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            // 2. Everything else becomes input at the window's current position
+            let window = (*geometry.lock().unwrap()).ok_or_else(|| fail("window is gone".to_string()))?;
+            let transform = CoordTransform::new(window, window.capture_size());
+            let action = to_action(step, &transform);
+            let request = ActionRequest::new(&format!("macro {}", self.name), vec![InputAction::Focus(target.pid), action])
+                .target(target.clone());
+            actions
+                .submit(request)
+                .wait_or_cancel(cancel)
+                .into_result()
+                .map_err(|e| fail(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+fn wait_for_screen(
+    state: &watch::Receiver<GameState>,
+    screen: ScreenKind,
+    timeout: Duration,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    let started = Instant::now();
+    while state.borrow().screen() != screen {
+        if started.elapsed() >= timeout {
+            return Err(format!("screen is {:?}, expected {:?}", state.borrow().screen(), screen));
+        }
+        if !cancel.sleep(POLL) {
+            return Err("cancelled".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn to_action(step: &MacroStep, transform: &CoordTransform) -> InputAction {
+    let at = |pos: (f32, f32)| {
+        let p = transform.window_to_screen(transform.relative_to_window(pos));
+        (p.x, p.y)
+    };
+    match step {
+        MacroStep::Click { x, y } => {
+            let (x, y) = at((*x, *y));
+            InputAction::Click { x, y }
+        }
+        MacroStep::RightClick { x, y } => {
+            let (x, y) = at((*x, *y));
+            InputAction::RightClick { x, y }
+        }
+        MacroStep::DoubleClick { x, y } => {
+            let (x, y) = at((*x, *y));
+            InputAction::DoubleClick { x, y }
+        }
+        MacroStep::Drag { from, to } => InputAction::Drag { from: at(*from), to: at(*to) },
+        MacroStep::Scroll { x, y, notches } => {
+            let (x, y) = at((*x, *y));
+            InputAction::Scroll { x, y, notches: *notches }
+        }
+        MacroStep::Key { keys } => InputAction::Combo(keys.clone()),
+        MacroStep::Wait { ms } => InputAction::Wait(Duration::from_millis(*ms)),
+        // Handled by `play` before it gets here.
+        MacroStep::WaitFor { .. } => InputAction::Wait(Duration::ZERO),
+    }
+}
+
+fn is_modifier(key: Key) -> bool {
+    matches!(
+        key,
+        Key::ShiftLeft
+            | Key::ShiftRight
+            | Key::ControlLeft
+            | Key::ControlRight
+            | Key::Alt
+            | Key::AltGr
+            | Key::MetaLeft
+            | Key::MetaRight
+    )
+}
+
+/// Turns the user's raw input into macro steps. Only input aimed at the target window
+/// counts: clicks outside it are skipped, and so are keys typed while another window
+/// was last clicked.
+struct Recording {
+    geometry: Arc<Mutex<Option<WindowGeometry>>>,
+    steps: Vec<MacroStep>,
+    pointer: ScreenPoint,
+    pressed: Option<(Button, ScreenPoint)>,
+    /// Whether the last click landed in the target window.
+    focused: bool,
+    held: Vec<Key>,
+    last_step: Instant,
+    last_click: Option<((f32, f32), Instant)>,
+    screen: ScreenKind,
+}
+
+impl Recording {
+    /// `p` as a fraction of the window, if it lies inside.
+    fn relative(&self, p: ScreenPoint) -> Option<(f32, f32)> {
+        let window = (*self.geometry.lock().unwrap())?;
+        let transform = CoordTransform::new(window, window.capture_size());
+        let local = transform.screen_to_window(p);
+        transform
+            .contains(local)
+            .then(|| ((local.x / window.width) as f32, (local.y / window.height) as f32))
+    }
+
+    fn push(&mut self, step: MacroStep) {
+        let gap = self.last_step.elapsed();
+        if gap >= MIN_WAIT && !matches!(step, MacroStep::WaitFor { .. }) {
+            self.steps.push(MacroStep::Wait { ms: gap.as_millis() as u64 / 50 * 50 });
+        }
+        self.steps.push(step);
+        self.last_step = Instant::now();
+    }
+
+    fn handle(&mut self, event: &Event) {
+        match event.event_type {
+            EventType::MouseMove { x, y } => self.pointer = ScreenPoint { x, y },
+            EventType::ButtonPress(button) => {
+                self.focused = self.relative(self.pointer).is_some();
+                if self.focused {
+                    self.pressed = Some((button, self.pointer));
+                }
+            }
+            EventType::ButtonRelease(button) => {
+                let Some((pressed, start)) = self.pressed.take() else { return };
+                if pressed != button {
+                    return;
+                }
+                let (Some(from), Some(to)) = (self.relative(start), self.relative(self.pointer)) else { return };
+                let moved = (self.pointer.x - start.x).hypot(self.pointer.y - start.y);
+                match button {
+                    Button::Left if moved > DRAG_THRESHOLD => self.push(MacroStep::Drag { from, to }),
+                    Button::Left => self.click(from),
+                    Button::Right => self.push(MacroStep::RightClick { x: from.0, y: from.1 }),
+                    _ => {}
+                }
+            }
+            EventType::Wheel { delta_y, .. } if delta_y != 0 => {
+                let Some((x, y)) = self.relative(self.pointer) else { return };
+                let notches = delta_y as i32;
+                // One flick of the wheel arrives as several events.
+                if let Some(MacroStep::Scroll { x: sx, y: sy, notches: n }) = self.steps.last_mut()
+                    && (*sx, *sy) == (x, y)
+                    && self.last_step.elapsed() < MIN_WAIT
+                {
+                    *n += notches;
+                    self.last_step = Instant::now();
+                    return;
+                }
+                self.push(MacroStep::Scroll { x, y, notches });
+            }
+            EventType::KeyPress(key) if self.focused => {
+                if is_modifier(key) {
+                    if !self.held.contains(&key) {
+                        self.held.push(key);
+                    }
+                } else {
+                    let keys = KeyCombo { modifiers: self.held.clone(), key };
+                    self.push(MacroStep::Key { keys });
+                }
+            }
+            EventType::KeyRelease(key) => self.held.retain(|k| *k != key),
+            _ => {}
+        }
+    }
+
+    fn click(&mut self, pos: (f32, f32)) {
+        let now = Instant::now();
+        let close = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).abs() < 0.005 && (a.1 - b.1).abs() < 0.005;
+        if let Some((last, at)) = self.last_click
+            && close(last, pos)
+            && at.elapsed() < DOUBLE_CLICK
+            && matches!(self.steps.last(), Some(MacroStep::Click { .. }))
+        {
+            self.steps.pop();
+            self.steps.push(MacroStep::DoubleClick { x: pos.0, y: pos.1 });
+            self.last_click = None;
+            self.last_step = now;
+            return;
+        }
+        self.push(MacroStep::Click { x: pos.0, y: pos.1 });
+        self.last_click = Some((pos, now));
+    }
+
+    /// Adds a checkpoint whenever the game moves to another screen, so playback waits
+    /// for it instead of relying on the recorded delay.
+    fn check_screen(&mut self, state: &watch::Receiver<GameState>) {
+        let screen = state.borrow().screen();
+        if screen != self.screen && screen != ScreenKind::Unknown {
+            self.screen = screen;
+            self.push(MacroStep::WaitFor { screen, timeout_ms: CHECKPOINT_TIMEOUT_MS });
+        }
+    }
+}
+
+/// Records the user's input on one window until stopped.
+pub struct MacroRecorder {
+    stop: CancelToken,
+    thread: JoinHandle<Vec<MacroStep>>,
+}
+
+impl MacroRecorder {
+    pub fn start(
+        events: Receiver<Event>,
+        geometry: Arc<Mutex<Option<WindowGeometry>>>,
+        state: watch::Receiver<GameState>,
+    ) -> Self {
+        let stop = CancelToken::new();
+        let flag = stop.clone();
+        let thread = thread::spawn(move || {
+            let screen = state.borrow().screen();
+            let mut recording = Recording {
+                geometry,
+                steps: Vec::new(),
+                pointer: ScreenPoint { x: 0.0, y: 0.0 },
+                pressed: None,
+                // Recording starts from the bot's window.
+                focused: false,
+                held: Vec::new(),
+                last_step: Instant::now(),
+                last_click: None,
+                screen,
+            };
+            while !flag.is_cancelled() {
+                match events.recv_timeout(POLL) {
+                    Ok(event) => recording.handle(&event),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                recording.check_screen(&state);
+            }
+            recording.steps
+        });
+        Self { stop, thread }
+    }
+
+    pub fn stop(self, name: &str) -> Macro {
+        self.stop.cancel();
+        let steps = self.thread.join().unwrap_or_default();
+        Macro {
+            name: name.to_string(),
+            steps,
+        }
+    }
+}
+
+/// Where a new macro called `name` is saved.
+pub fn macro_path(name: &str) -> PathBuf {
+    Path::new(MACRO_DIR).join(format!("{}.json", name))
+}
+
@@ -1,17 +1,68 @@
+mod action_queue;
+mod activity_monitor;
+mod behavior;
 mod bot_engine;
+mod coords;
+mod detector;
+mod detectors;
+mod dry_run;
+mod expect;
+mod focus;
+mod game_state;
+mod stream_watchdog;
+mod task;
+mod tasks;
+mod team;
+mod input_backend;
 mod input_manager;
+mod keymap;
+mod macros;
+#[cfg(test)]
+mod replay;
+mod safety;
+mod scheduler;
+mod session;
 mod vision_engine;
+mod vision_pipeline;
+mod window_selector;
+mod worker;
+mod world_model;
 
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
 use eframe::egui;
+use action_queue::PauseReason;
+use behavior::{Behavior, BEHAVIOR_DIR};
 use bot_engine::{BotEngine, LogLevel, LogMessage};
+use coords::ScreenPoint;
+use dry_run::MARKER_LIFETIME;
+use macros::{Macro, MACRO_DIR};
+use session::{Session, SessionManager};
+use stream_watchdog::StreamHealth;
+use task::TaskState;
+use team::{Team, TeamCommand, TeamConfig, TeamHandle};
+use window_selector::WindowSelector;
+
+/// How long the team's threads get to stop when the app closes.
+const TEAM_STOP_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(PartialEq)]
 enum Tab {
     Vision,
+    Tasks,
     Logs,
 }
 
+#[derive(PartialEq)]
+enum Layout {
+    /// The active session, with its full details.
+    Single,
+    /// Every session in its own column.
+    SideBySide,
+}
+
 fn main() -> eframe::Result<()> {
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -29,67 +80,422 @@ fn main() -> eframe::Result<()> {
 }
 
 struct MyBotApp {
-    engine: BotEngine,
+    sessions: SessionManager,
+    /// Messages not tied to a session (startup, scans).
     logs: Vec<LogMessage>,
     log_receiver: Receiver<LogMessage>,
     current_tab: Tab,
-    texture: Option<egui::TextureHandle>,
+    layout: Layout,
+    /// Session shown in the single view.
+    active: Option<String>,
+    /// Session whose log the Logs tab shows; `None` is the system log.
+    log_source: Option<String>,
+    textures: HashMap<String, egui::TextureHandle>,
+    /// Window selector typed by the user; empty means the Dofus client.
+    selector_spec: String,
+    /// Running leader/follower coordinator, if any.
+    team: Option<TeamHandle>,
 }
 
 impl MyBotApp {
     fn new(tx: Sender<LogMessage>, rx: Receiver<LogMessage>) -> Self {
         Self {
-            engine: BotEngine::new(tx), 
+            sessions: SessionManager::new(tx),
             logs: Vec::new(),
             log_receiver: rx,
             current_tab: Tab::Vision,
-            texture: None,
+            layout: Layout::Single,
+            active: None,
+            log_source: None,
+            textures: HashMap::new(),
+            selector_spec: String::new(),
+            team: None,
+        }
+    }
+
+    fn active_session(&self) -> Option<&Session> {
+        self.active.as_deref().and_then(|name| self.sessions.get(name))
+    }
+
+    /// Starts a team led by the active session, or stops the running one.
+    fn team_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            match &mut self.team {
+                Some(team) => {
+                    ui.label(format!("Team led by {}. Turn order:", team.leader));
+                    for name in team.turn_order.clone() {
+                        if ui.small_button(format!("▲ {}", name)).on_hover_text("Play earlier in fights").clicked() {
+                            team.move_up(&name);
+                        }
+                    }
+                    if ui.button("Stop team").clicked()
+                        && let Some(team) = self.team.take()
+                    {
+                        team.halt();
+                    }
+                }
+                None => {
+                    let Some(leader) = self.active.clone() else { return };
+                    if ui.button(format!("Start team led by {}", leader)).clicked() {
+                        match Team::from_sessions(&self.sessions, &leader, TeamConfig::default(), self.sessions.log_sender()) {
+                            Ok(team) => self.team = Some(TeamHandle::start(team, &self.sessions)),
+                            Err(err) => self.sessions.log(&err, LogLevel::Warning),
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// During a team fight, a click on a follower's preview is played on that follower's
+    /// next turn.
+    fn queue_combat_click(&self, member: &str, pos: (f32, f32)) {
+        let Some(team) = &self.team else { return };
+        let in_combat = self.sessions.get(&team.leader).is_some_and(|s| s.engine.state().in_combat());
+        if member == team.leader || !in_combat {
+            return;
+        }
+        team.send(TeamCommand::CombatClick { member: member.to_string(), pos });
+        self.sessions.log(&format!("Queued a click for {} on its next turn.", member), LogLevel::Info);
+    }
+
+    /// Pulls the latest frame of each visible session into its texture.
+    fn update_textures(&mut self, ctx: &egui::Context) {
+        let visible: Vec<String> = match self.layout {
+            Layout::Single => self.active.iter().cloned().collect(),
+            Layout::SideBySide => self.sessions.names(),
+        };
+
+        for name in visible {
+            let Some(rgba_img) = self.sessions.get(&name).and_then(|s| s.engine.vision.capture_frame()) else {
+                continue;
+            };
+            let color_image = egui::ColorImage::from_rgba_unmultiplied(
+                [rgba_img.width() as usize, rgba_img.height() as usize],
+                rgba_img.as_flat_samples().as_slice(),
+            );
+
+            match self.textures.get_mut(&name) {
+                Some(tex) => tex.set(color_image, egui::TextureOptions::LINEAR),
+                None => {
+                    let tex = ctx.load_texture(format!("live_view_{}", name), color_image, egui::TextureOptions::LINEAR);
+                    self.textures.insert(name, tex);
+                }
+            }
+            // Request a repaint to keep the stream moving
+            ctx.request_repaint();
         }
     }
 }
 
+fn health_color(health: &StreamHealth) -> egui::Color32 {
+    match health {
+        StreamHealth::Active => egui::Color32::GREEN,
+        StreamHealth::Static => egui::Color32::LIGHT_GREEN,
+        StreamHealth::Hidden | StreamHealth::Probing => egui::Color32::YELLOW,
+        StreamHealth::NoTarget | StreamHealth::Dead { .. } => egui::Color32::RED,
+    }
+}
+
+/// What the user did in a session panel.
+#[derive(Default)]
+struct PanelResponse {
+    close: bool,
+    /// Where the live preview was clicked, as a fraction of its size.
+    preview_click: Option<(f32, f32)>,
+}
+
+/// One session's status, state and live preview. `detailed` adds the pipeline timings and
+/// the world model, which don't fit in a side-by-side column.
+fn session_panel(ui: &mut egui::Ui, session: &Session, texture: Option<&egui::TextureHandle>, detailed: bool) -> PanelResponse {
+    let engine = &session.engine;
+    let mut response = PanelResponse::default();
+
+    ui.horizontal(|ui| {
+        ui.strong(&session.name);
+        let health = engine.vision.health();
+        ui.colored_label(health_color(&health), health.to_string());
+        if ui.small_button("✖").on_hover_text("Close session").clicked() {
+            response.close = true;
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Window:");
+        ui.colored_label(egui::Color32::LIGHT_BLUE, engine.vision.target_window_name());
+        ui.label("| Resolution:");
+        ui.colored_label(egui::Color32::LIGHT_GREEN, engine.vision.window_resolution());
+    });
+
+    if let Some(report) = engine.last_report.lock().unwrap().as_ref() {
+        ui.colored_label(egui::Color32::GRAY, report.summary());
+    }
+    ui.colored_label(egui::Color32::GRAY, format!("Tasks: {}", engine.scheduler.summary()));
+
+    egui::CollapsingHeader::new("Game State")
+        .id_source(("game_state", &session.name))
+        .show(ui, |ui| {
+            egui::Grid::new(("game_state_grid", &session.name)).striped(true).show(ui, |ui| {
+                for (label, value) in engine.state().display_rows() {
+                    ui.label(label);
+                    ui.label(value);
+                    ui.end_row();
+                }
+
+                if detailed {
+                    let world = engine.world.lock().unwrap();
+                    let now = chrono::Utc::now().timestamp();
+                    ui.label("Known nodes");
+                    ui.label(format!(
+                        "{} ({} available now)",
+                        world.nodes().count(),
+                        world.available_nodes(now).len()
+                    ));
+                    ui.end_row();
+
+                    // Nodes on the current map, harvestable or not
+                    let mut harvest = None;
+                    if let Some(map) = engine.state().map.map(|m| m.value) {
+                        let available: Vec<u64> = world.available_on_map(map, now).iter().map(|n| n.id).collect();
+                        let busy = engine.harvest_busy();
+                        for node in world.map(map).map(|m| m.nodes.as_slice()).unwrap_or_default() {
+                            ui.label(format!("{} #{}", node.kind, node.id));
+                            if available.contains(&node.id) {
+                                if ui.add_enabled(!busy, egui::Button::new("Harvest")).clicked() {
+                                    harvest = Some(node.id);
+                                }
+                            } else {
+                                match world.respawns_in(node, now) {
+                                    Some(secs) => ui.label(format!("back in {}s", secs)),
+                                    None => ui.label("harvested"),
+                                };
+                            }
+                            ui.end_row();
+                        }
+                    }
+                    // The task locks the world model itself
+                    drop(world);
+                    if let Some(node_id) = harvest {
+                        engine.harvest(node_id);
+                    }
+                }
+            });
+        });
+
+    if detailed {
+        let stage_timings = engine.last_report.lock().unwrap().as_ref().map(|r| r.stage_timings.clone());
+        for (name, timings) in stage_timings.unwrap_or_default() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}:", name));
+                match &timings {
+                    Err(err) => ui.colored_label(egui::Color32::RED, err),
+                    Ok(summary) => ui.colored_label(egui::Color32::GRAY, summary),
+                };
+            });
+        }
+    }
+
+    ui.add_space(10.0);
+
+    // Action Buttons
+    ui.horizontal(|ui| {
+        let busy = engine.mission_proof_busy();
+        if ui.add_enabled(!busy, egui::Button::new("📸 Trigger Mission Proof")).clicked() {
+            engine.trigger_mission_proof();
+        }
+        let mut scheduled = engine.mission_proof_scheduled();
+        if ui.checkbox(&mut scheduled, "every 30 min").changed() {
+            engine.schedule_mission_proof(scheduled);
+        }
+        if busy {
+            ui.spinner();
+        }
+        let testing = engine.test_sequence_busy();
+        if ui.add_enabled(!testing, egui::Button::new("🧪 Test focus")).on_hover_text("Focus Dofus after 5s, then the bot").clicked() {
+            engine.run_test_sequence();
+        }
+        if testing {
+            ui.spinner();
+        }
+        if detailed && ui.button("📁 Open Mission Folder").clicked() {
+            let _ = std::process::Command::new("open")
+                .arg("./mission_logs")
+                .spawn();
+        }
+    });
+
+    // Macros
+    ui.horizontal(|ui| {
+        if engine.is_recording() {
+            if ui.button("⏹ Stop recording").clicked() {
+                engine.stop_recording();
+            }
+        } else if ui.button("⏺ Record macro").clicked() {
+            engine.start_recording();
+        }
+        if engine.is_playing() {
+            ui.spinner();
+            if ui.button("⏹ Stop macro").clicked() {
+                engine.stop_macro();
+            }
+        } else if detailed {
+            ui.menu_button("▶ Play macro", |ui| {
+                let paths = Macro::list(Path::new(MACRO_DIR));
+                if paths.is_empty() {
+                    ui.label("No macros yet.");
+                }
+                for path in paths {
+                    let name = path.file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                    if ui.button(name).clicked() {
+                        engine.play_macro(&path);
+                        ui.close_menu();
+                    }
+                }
+            });
+        }
+        if detailed {
+            ui.menu_button("🌳 Run behavior", |ui| {
+                let paths = Behavior::list(Path::new(BEHAVIOR_DIR));
+                if paths.is_empty() {
+                    ui.label("No behaviors yet.");
+                }
+                for path in paths {
+                    let name = path.file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                    if ui.button(name).clicked() {
+                        engine.run_behavior(&path);
+                        ui.close_menu();
+                    }
+                }
+            });
+        }
+    });
+
+    let behavior = engine.behavior_status();
+    if !behavior.is_empty() {
+        egui::CollapsingHeader::new("Behavior tree")
+            .id_source(("behavior", &session.name))
+            .show(ui, |ui| {
+                ui.label(egui::RichText::new(behavior).monospace());
+            });
+    }
+
+    ui.add_space(10.0);
+
+    // Live Preview
+    if let Some(tex) = texture {
+        let size = tex.size_vec2();
+        let max_size = ui.available_size();
+        let scale = (max_size.x / size.x).min(max_size.y / size.y).min(1.0);
+        let image = ui.add(egui::Image::new(tex).fit_to_exact_size(size * scale).sense(egui::Sense::click()));
+        if image.clicked()
+            && let Some(pos) = image.interact_pointer_pos()
+        {
+            let rel = (pos - image.rect.min) / image.rect.size();
+            response.preview_click = Some((rel.x, rel.y));
+        }
+        paint_intents(ui, engine, image.rect, size);
+    } else {
+        ui.label("Waiting for stream...");
+    }
+
+    response
+}
+
+/// Marks where dry-run actions would have clicked, fading out as they age.
+fn paint_intents(ui: &egui::Ui, engine: &BotEngine, rect: egui::Rect, frame_size: egui::Vec2) {
+    if !engine.dry_run.is_enabled() {
+        return;
+    }
+    let Some(transform) = engine.vision.transform() else { return };
+    let painter = ui.painter_at(rect);
+    for intent in engine.dry_run.recent() {
+        let fade = 1.0 - intent.at.elapsed().as_secs_f32() / MARKER_LIFETIME.as_secs_f32();
+        let color = egui::Color32::from_rgba_unmultiplied(255, 60, 60, (fade.clamp(0.0, 1.0) * 255.0) as u8);
+        for (x, y) in intent.action.points() {
+            let p = transform.screen_to_frame(ScreenPoint { x, y });
+            let pos = rect.min + egui::vec2(p.x as f32 / frame_size.x * rect.width(), p.y as f32 / frame_size.y * rect.height());
+            if rect.contains(pos) {
+                painter.circle_stroke(pos, 10.0, egui::Stroke::new(2.0, color));
+                painter.circle_filled(pos, 2.0, color);
+            }
+        }
+    }
+    ui.ctx().request_repaint();
+}
+
 impl eframe::App for MyBotApp {
+    /// Stops the team and every background thread before the window goes away.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Some(team) = self.team.take()
+            && !team.stop(TEAM_STOP_TIMEOUT)
+        {
+            self.sessions.log("Team still running at exit.", LogLevel::Warning);
+        }
+        self.sessions.shutdown();
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // 1. Drain logs from the channel
+        // 1. Drain logs from the channels
         while let Ok(msg) = self.log_receiver.try_recv() {
             self.logs.push(msg);
         }
+        self.sessions.drain_logs();
+        self.sessions.poll_scan();
+        if self.active_session().is_none() {
+            self.active = self.sessions.names().into_iter().next();
+        }
+        let paused = self.sessions.shared.actions.paused();
+        if paused == Some(PauseReason::EmergencyStop)
+            && let Some(team) = self.team.take()
+        {
+            team.halt();
+        }
 
-        // 2. Update Live Texture if in Vision Tab
+        // 2. Update Live Textures if in Vision Tab
         if self.current_tab == Tab::Vision {
-            if let Some(rgba_img) = self.engine.vision.capture_frame() {
-                let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                    [rgba_img.width() as usize, rgba_img.height() as usize],
-                    rgba_img.as_flat_samples().as_slice(),
-                );
-
-                if let Some(ref mut tex) = self.texture {
-                    tex.set(color_image, egui::TextureOptions::LINEAR);
-                } else {
-                    self.texture = Some(ctx.load_texture(
-                        "live_view",
-                        color_image,
-                        egui::TextureOptions::LINEAR,
-                    ));
-                }
-                // Request a repaint to keep the stream moving
-                ctx.request_repaint();
-            }
+            self.update_textures(ctx);
         }
 
         // 3. Top Navigation Bar
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.selectable_value(&mut self.current_tab, Tab::Vision, "Vision");
+                ui.selectable_value(&mut self.current_tab, Tab::Tasks, "Tasks");
                 ui.selectable_value(&mut self.current_tab, Tab::Logs, "Logs");
+                ui.separator();
+
+                for name in self.sessions.names() {
+                    if ui.selectable_label(self.active.as_ref() == Some(&name), &name).clicked() {
+                        self.active = Some(name);
+                    }
+                }
 
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    let stream_active = self.engine.vision.stream.is_some();
-                    if stream_active {
-                        ui.colored_label(egui::Color32::GREEN, "STREAM ACTIVE");
-                    } else {
-                        ui.colored_label(egui::Color32::RED, "STREAM INACTIVE");
+                    let dry_run = &self.sessions.shared.dry_run;
+                    let mut enabled = dry_run.is_enabled();
+                    if ui.checkbox(&mut enabled, "Dry run").changed() {
+                        dry_run.set_enabled(enabled);
                     }
+                    let health = self
+                        .active_session()
+                        .map(|s| s.engine.vision.health())
+                        .unwrap_or(StreamHealth::NoTarget);
+                    ui.colored_label(health_color(&health), health.to_string());
+                    match paused {
+                        Some(PauseReason::EmergencyStop) => {
+                            if ui.button("Resume input").clicked() {
+                                self.sessions.shared.actions.resume(PauseReason::EmergencyStop);
+                                self.sessions.log("Input resumed after emergency stop.", LogLevel::Info);
+                            }
+                            ui.colored_label(egui::Color32::RED, "EMERGENCY STOP");
+                        }
+                        Some(PauseReason::UserActivity) => {
+                            ui.colored_label(egui::Color32::YELLOW, "Paused: user active");
+                        }
+                        None => {}
+                    }
+                    ui.selectable_value(&mut self.layout, Layout::SideBySide, "Side by side");
+                    ui.selectable_value(&mut self.layout, Layout::Single, "Single");
                 });
             });
         });
@@ -100,53 +506,159 @@ impl eframe::App for MyBotApp {
                 Tab::Vision => {
                     ui.horizontal(|ui| {
                         ui.heading("Dofus Unity Vision");
-                        if ui.button("Scan for Dofus").clicked() {
-                            self.engine.scan_for_window();
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.selector_spec)
+                                .hint_text("app:dofus, character:Name, pid:123...")
+                                .desired_width(200.0),
+                        );
+                        let scanning = self.sessions.is_scanning();
+                        let label = if scanning { "Scanning..." } else { "Scan for Dofus" };
+                        if ui.add_enabled(!scanning, egui::Button::new(label)).clicked() {
+                            match WindowSelector::parse(&self.selector_spec) {
+                                Ok(selector) => self.sessions.scan(&selector),
+                                Err(err) => self.sessions.log(&err, LogLevel::Warning),
+                            }
                         }
                     });
+
+                    if self.sessions.candidates.len() > 1 {
+                        let mut opened = None;
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label("Windows:");
+                            for candidate in &self.sessions.candidates {
+                                let open = self.sessions.is_open(candidate);
+                                if ui.add_enabled(!open, egui::Button::new(candidate.label())).clicked() {
+                                    opened = Some(candidate.clone());
+                                }
+                            }
+                            if ui.button("Open all").clicked() {
+                                self.sessions.open_all();
+                            }
+                        });
+                        if let Some(candidate) = opened {
+                            self.active = Some(self.sessions.open(&candidate));
+                        }
+                    }
+                    if self.sessions.sessions().len() > 1 {
+                        self.team_controls(ui);
+                    }
                     ui.separator();
 
-                    ui.horizontal(|ui| {
-                        ui.label("Window:");
-                        ui.colored_label(egui::Color32::LIGHT_BLUE, &self.engine.vision.target_window_name);
-                        ui.label("| Resolution:");
-                        ui.colored_label(egui::Color32::LIGHT_GREEN, &self.engine.vision.window_resolution);
-                    });
+                    if self.sessions.sessions().is_empty() {
+                        ui.centered_and_justified(|ui| {
+                            ui.label("Waiting for stream... (Scan for Dofus to start)");
+                        });
+                        return;
+                    }
 
-                    ui.add_space(10.0);
+                    let mut responses = Vec::new();
+                    match self.layout {
+                        Layout::Single => {
+                            if let Some(session) = self.active_session() {
+                                let response = session_panel(ui, session, self.textures.get(&session.name), true);
+                                responses.push((session.name.clone(), response));
+                            }
+                        }
+                        Layout::SideBySide => {
+                            let sessions = self.sessions.sessions();
+                            ui.columns(sessions.len(), |columns| {
+                                for (ui, session) in columns.iter_mut().zip(sessions) {
+                                    let response = session_panel(ui, session, self.textures.get(&session.name), false);
+                                    responses.push((session.name.clone(), response));
+                                }
+                            });
+                        }
+                    }
 
-                    // Action Buttons
+                    let mut closed = None;
+                    for (name, response) in responses {
+                        if response.close {
+                            closed = Some(name.clone());
+                        }
+                        if let Some(pos) = response.preview_click {
+                            self.queue_combat_click(&name, pos);
+                        }
+                    }
+                    if let Some(name) = closed {
+                        // The team holds handles to every member, so it can't outlive one.
+                        if let Some(team) = self.team.take() {
+                            team.halt();
+                        }
+                        self.sessions.close(&name);
+                        self.textures.remove(&name);
+                    }
+                }
+                Tab::Tasks => {
+                    let tasks = &self.sessions.shared.tasks;
                     ui.horizontal(|ui| {
-                        if ui.button("📸 Trigger Mission Proof").clicked() {
-                            self.engine.trigger_mission_proof();
+                        ui.heading("Tasks");
+                        if ui.button("Clear finished").clicked() {
+                            tasks.clear_finished();
                         }
-                        if ui.button("📁 Open Mission Folder").clicked() {
-                            let _ = std::process::Command::new("open")
-                                .arg("./mission_logs")
-                                .spawn();
+                        if ui.button("Stop all").clicked() {
+                            tasks.cancel_all();
                         }
                     });
+                    ui.separator();
 
-                    ui.add_space(10.0);
-
-                    // Live Preview
-                    if let Some(ref tex) = self.texture {
-                        let size = tex.size_vec2();
-                        let max_size = ui.available_size();
-                        let scale = (max_size.x / size.x).min(max_size.y / size.y).min(1.0);
-                        ui.image(tex, size * scale);
-                    } else {
-                        ui.centered_and_justified(|ui| {
-                            ui.label("Waiting for stream... (Scan for Dofus to start)");
-                        });
-                    }
+                    egui::Grid::new("tasks_grid").striped(true).show(ui, |ui| {
+                        for info in tasks.list() {
+                            ui.label(format!("#{}", info.id));
+                            ui.strong(&info.name);
+                            ui.label(&info.owner);
+                            let color = match info.state {
+                                TaskState::Succeeded => egui::Color32::GREEN,
+                                TaskState::Failed(_) => egui::Color32::RED,
+                                TaskState::Paused => egui::Color32::YELLOW,
+                                _ => egui::Color32::LIGHT_BLUE,
+                            };
+                            ui.colored_label(color, info.state.to_string());
+                            match info.progress {
+                                Some(progress) => ui.add(egui::ProgressBar::new(progress).desired_width(120.0)),
+                                None => ui.label(""),
+                            };
+                            ui.label(&info.status);
+                            ui.horizontal(|ui| match info.state {
+                                TaskState::Running => {
+                                    if ui.small_button("Pause").clicked() {
+                                        tasks.pause(info.id);
+                                    }
+                                    if ui.small_button("Cancel").clicked() {
+                                        tasks.cancel(info.id);
+                                    }
+                                }
+                                TaskState::Paused => {
+                                    if ui.small_button("Resume").clicked() {
+                                        tasks.resume(info.id);
+                                    }
+                                    if ui.small_button("Cancel").clicked() {
+                                        tasks.cancel(info.id);
+                                    }
+                                }
+                                _ => {}
+                            });
+                            ui.end_row();
+                        }
+                    });
+                    ctx.request_repaint_after(Duration::from_millis(250));
                 }
                 Tab::Logs => {
-                    ui.heading("System Logs");
+                    ui.horizontal(|ui| {
+                        ui.heading("Logs");
+                        ui.selectable_value(&mut self.log_source, None, "System");
+                        for name in self.sessions.names() {
+                            ui.selectable_value(&mut self.log_source, Some(name.clone()), &name);
+                        }
+                    });
                     ui.separator();
 
+                    let source = self.log_source.as_deref().and_then(|name| self.sessions.get(name));
+                    let logs = match source {
+                        Some(session) => &session.logs,
+                        None => &self.logs,
+                    };
                     egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
-                        for log in self.logs.iter() {
+                        for log in logs.iter() {
                             ui.horizontal(|ui| {
                                 ui.label(format!("[{}]", log.timestamp));
                                 let color = match log.level {
@@ -0,0 +1,238 @@
+use crate::bot_engine::{send_log, LogLevel, LogMessage};
+use crate::detector::{DetectorRunner, WorkerPool};
+use crate::detectors;
+use crate::game_state::GameState;
+use crate::input_backend::InputError;
+use crate::input_manager::InputManager;
+use crate::team::TeamMember;
+use crate::vision_pipeline::{Frame, TemplateLibrary};
+use crate::worker::Worker;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::watch;
+
+const REPLAY_BUDGET: Duration = Duration::from_millis(50);
+
+/// Recorded frames played back in place of a capture stream.
+pub struct ReplaySource {
+    frames: Vec<PathBuf>,
+    interval: Duration,
+}
+
+impl ReplaySource {
+    /// Every PNG in `dir`, in file name order, one every `interval`.
+    pub fn from_dir(dir: &Path, interval: Duration) -> Result<Self, String> {
+        let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read replay dir {}: {}", dir.display(), e))?;
+        let mut frames: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "png"))
+            .collect();
+        frames.sort();
+        if frames.is_empty() {
+            return Err(format!("No frames in {}", dir.display()));
+        }
+        Ok(Self { frames, interval })
+    }
+
+    /// Plays the frames on a background thread. The channel closes after the last one.
+    pub fn play(self, log_tx: Sender<LogMessage>) -> Receiver<Frame> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (seq, path) in self.frames.iter().enumerate() {
+                let image = match image::open(path) {
+                    Ok(image) => image.to_rgba8(),
+                    Err(err) => {
+                        send_log(&log_tx, &format!("Skipping replay frame {}: {}", path.display(), err), LogLevel::Warning);
+                        continue;
+                    }
+                };
+                if tx.send(Frame::new(seq as u64 + 1, image)).is_err() {
+                    return;
+                }
+                thread::sleep(self.interval);
+            }
+        });
+        rx
+    }
+}
+
+/// A team member driven by a `ReplaySource`: the frames go through the real detectors,
+/// and clicks go to `input` at the matching pixel of the replayed frames, usually on a
+/// `RecordingBackend`.
+pub struct ReplayMember {
+    name: String,
+    state: watch::Receiver<GameState>,
+    frame_size: Arc<Mutex<(u32, u32)>>,
+    input: InputManager,
+    /// Stops processing the replay when the member is dropped.
+    _detectors: Worker,
+}
+
+impl ReplayMember {
+    pub fn start(
+        name: &str,
+        source: ReplaySource,
+        pool: Arc<WorkerPool>,
+        templates: &TemplateLibrary,
+        input: InputManager,
+        log_tx: Sender<LogMessage>,
+    ) -> Self {
+        let mut runner = DetectorRunner::new(pool, REPLAY_BUDGET);
+        for detector in detectors::default_detectors(templates) {
+            runner.add(detector);
+        }
+
+        let (state_tx, state_rx) = watch::channel(GameState::default());
+        let frame_size = Arc::new(Mutex::new((0, 0)));
+        let size = Arc::clone(&frame_size);
+        let detectors = runner.spawn(source.play(log_tx), move |report| {
+            *size.lock().unwrap() = report.frame_size;
+            state_tx.send_modify(|state| state.apply(&report));
+        });
+
+        Self {
+            name: name.to_string(),
+            state: state_rx,
+            frame_size,
+            input,
+            _detectors: detectors,
+        }
+    }
+
+    /// Receives the perception snapshot every time a frame has been processed.
+    pub fn subscribe_state(&self) -> watch::Receiver<GameState> {
+        self.state.clone()
+    }
+}
+
+impl TeamMember for ReplayMember {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn state(&self) -> GameState {
+        self.state.borrow().clone()
+    }
+
+    fn click_relative(&self, pos: (f32, f32)) -> Result<(), InputError> {
+        let (width, height) = *self.frame_size.lock().unwrap();
+        self.input.click_at(pos.0 as f64 * width as f64, pos.1 as f64 * height as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_backend::RecordingBackend;
+    use crate::team::{LeaderWatcher, Team, TeamConfig};
+    use image::{Rgba, RgbaImage};
+    use std::time::Instant;
+
+    const FRAME: (u32, u32) = (200, 100);
+
+    /// A 3x5 pixel font: `#` is lit.
+    fn glyph(ch: char) -> [&'static str; 5] {
+        match ch {
+            '1' => ["##", ".#", ".#", ".#", ".#"],
+            '2' => ["###", "..#", "###", "#..", "###"],
+            ',' => [".#", ".#", "##", "", ""],
+            _ => panic!("no glyph for {:?}", ch),
+        }
+    }
+
+    fn draw(img: &mut RgbaImage, x: u32, y: u32, ch: char) -> u32 {
+        let rows = glyph(ch);
+        for (dy, row) in rows.iter().enumerate() {
+            for (dx, c) in row.chars().enumerate() {
+                if c == '#' {
+                    img.put_pixel(x + dx as u32, y + dy as u32, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+        rows.iter().map(|r| r.len() as u32).max().unwrap()
+    }
+
+    fn glyphs() -> TemplateLibrary {
+        let mut library = TemplateLibrary::new();
+        for (name, ch) in [("glyph_1", '1'), ("glyph_2", '2'), ("glyph_comma", ',')] {
+            let rows = glyph(ch);
+            let height = rows.iter().filter(|r| !r.is_empty()).count() as u32;
+            let mut img = RgbaImage::from_pixel(rows[0].len() as u32, height, Rgba([0, 0, 0, 255]));
+            draw(&mut img, 0, 0, ch);
+            library.insert(name, img);
+        }
+        library
+    }
+
+    /// A black frame showing `coords` where the map detector reads them.
+    fn map_frame(coords: &str) -> RgbaImage {
+        let mut img = RgbaImage::from_pixel(FRAME.0, FRAME.1, Rgba([0, 0, 0, 255]));
+        let mut x = 2;
+        for ch in coords.chars() {
+            x += draw(&mut img, x, 1, ch) + 2;
+        }
+        img
+    }
+
+    fn write_frames(name: &str, frames: &[(&str, usize)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("replay_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut index = 0;
+        for (coords, count) in frames {
+            let img = map_frame(coords);
+            for _ in 0..*count {
+                img.save(dir.join(format!("{:04}.png", index))).unwrap();
+                index += 1;
+            }
+        }
+        dir
+    }
+
+    #[test]
+    fn follower_takes_the_exit_the_leader_took() {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let pool = Arc::new(WorkerPool::new(2));
+        let library = glyphs();
+        let interval = Duration::from_millis(20);
+        // Write every frame first, so both replays start together.
+        let (leader_dir, follower_dir) = (
+            write_frames("leader", &[("1,1", 15), ("2,1", 100)]),
+            write_frames("follower", &[("1,1", 150)]),
+        );
+        let member = |name: &str, dir: &Path, input: InputManager| {
+            let source = ReplaySource::from_dir(dir, interval).unwrap();
+            ReplayMember::start(name, source, Arc::clone(&pool), &library, input, log_tx.clone())
+        };
+
+        let leader = member("leader", &leader_dir, InputManager::with_backend(Arc::new(RecordingBackend::new())));
+        let backend = Arc::new(RecordingBackend::new());
+        let follower = member("follower", &follower_dir, InputManager::with_backend(backend.clone()));
+        let leader_state = leader.subscribe_state();
+        let mut team = Team::new(Box::new(leader), vec![Box::new(follower)], TeamConfig::default(), log_tx.clone());
+
+        // The user clicks the right-hand exit while the leader is on 1,1.
+        let mut watcher = LeaderWatcher::new(TeamConfig::default().ready_button);
+        let mut clicked = false;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while backend.clicks().is_empty() && Instant::now() < deadline {
+            let state = leader_state.borrow().clone();
+            for command in watcher.observe(&state) {
+                team.handle(command);
+            }
+            if !clicked && state.map.as_ref().is_some_and(|m| m.value == (1, 1)) {
+                watcher.clicked((0.75, 0.5));
+                clicked = true;
+            }
+            team.tick();
+            thread::sleep(interval);
+        }
+
+        let _ = std::fs::remove_dir_all(&leader_dir);
+        let _ = std::fs::remove_dir_all(&follower_dir);
+        assert_eq!(backend.clicks(), vec![(150.0, 50.0)]);
+    }
+}
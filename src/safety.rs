@@ -0,0 +1,149 @@
+use crate::action_queue::InputAction;
+use crate::input_backend::InputError;
+use crate::input_manager::InputManager;
+use crate::vision_engine::WindowGeometry;
+use std::sync::{Arc, Mutex};
+
+/// Default distance, in points, that clicks must keep from the window's edges.
+pub const DEFAULT_MARGIN: f64 = 5.0;
+
+/// The window a request is meant for. Bounds are read live, so a window moved after the
+/// request was queued is still checked against where it is now.
+#[derive(Clone)]
+pub struct InputTarget {
+    pub pid: i32,
+    geometry: Arc<Mutex<Option<WindowGeometry>>>,
+}
+
+impl InputTarget {
+    pub fn new(pid: i32, geometry: Arc<Mutex<Option<WindowGeometry>>>) -> Self {
+        Self { pid, geometry }
+    }
+}
+
+/// Last check before input leaves the queue: the target must be frontmost, and pointer
+/// input must land inside its bounds, `margin` points away from the edges. Anything else
+/// could end up in whatever window is in front instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SafetyGuard {
+    pub margin: f64,
+}
+
+impl Default for SafetyGuard {
+    fn default() -> Self {
+        Self { margin: DEFAULT_MARGIN }
+    }
+}
+
+impl SafetyGuard {
+    pub fn check(&self, input: &InputManager, target: &InputTarget, action: &InputAction) -> Result<(), InputError> {
+        // Focusing and waiting can't misfire.
+        if matches!(action, InputAction::Focus(_) | InputAction::Wait(_)) {
+            return Ok(());
+        }
+
+        // 1. The target must be the window receiving input
+        let frontmost = input.frontmost();
+        if frontmost != Some(target.pid) {
+            return Err(InputError::NotFrontmost { pid: target.pid, frontmost });
+        }
+
+        // 2. Every point the action touches must be well inside it
+        let points = action.points();
+        if points.is_empty() {
+            return Ok(());
+        }
+        let geometry = target.geometry.lock().unwrap().ok_or(InputError::TargetGone)?;
+        match points.into_iter().find(|&p| !self.inside(&geometry, p)) {
+            Some((x, y)) => Err(InputError::OutOfBounds { x, y }),
+            None => Ok(()),
+        }
+    }
+
+    fn inside(&self, window: &WindowGeometry, (x, y): (f64, f64)) -> bool {
+        x >= window.x + self.margin
+            && x <= window.x + window.width - self.margin
+            && y >= window.y + self.margin
+            && y <= window.y + window.height - self.margin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_backend::RecordingBackend;
+    use std::time::Duration;
+
+    const PID: i32 = 42;
+
+    /// A 200x100 window at (100, 50), and input whose frontmost app is `frontmost`.
+    fn setup(frontmost: Option<i32>) -> (InputManager, InputTarget) {
+        let backend = Arc::new(RecordingBackend::new());
+        let input = InputManager::with_backend(backend);
+        if let Some(pid) = frontmost {
+            input.focus_window(pid).unwrap();
+        }
+        let geometry = WindowGeometry { x: 100.0, y: 50.0, width: 200.0, height: 100.0 };
+        (input, InputTarget::new(PID, Arc::new(Mutex::new(Some(geometry)))))
+    }
+
+    fn click(x: f64, y: f64) -> InputAction {
+        InputAction::Click { x, y }
+    }
+
+    #[test]
+    fn input_is_refused_unless_the_target_is_frontmost() {
+        let guard = SafetyGuard::default();
+        for frontmost in [None, Some(7)] {
+            let (input, target) = setup(frontmost);
+            assert_eq!(
+                guard.check(&input, &target, &click(150.0, 100.0)),
+                Err(InputError::NotFrontmost { pid: PID, frontmost })
+            );
+            assert!(guard.check(&input, &target, &InputAction::Key(rdev::Key::KeyA)).is_err());
+            // Focusing is how the target gets to the front in the first place
+            assert_eq!(guard.check(&input, &target, &InputAction::Focus(PID)), Ok(()));
+        }
+
+        let (input, target) = setup(Some(PID));
+        assert_eq!(guard.check(&input, &target, &click(150.0, 100.0)), Ok(()));
+    }
+
+    #[test]
+    fn points_must_keep_the_margin_from_the_edges() {
+        let guard = SafetyGuard::default();
+        let (input, target) = setup(Some(PID));
+        for (x, y) in [(105.0, 55.0), (295.0, 145.0), (105.0, 145.0)] {
+            assert_eq!(guard.check(&input, &target, &click(x, y)), Ok(()), "({}, {})", x, y);
+        }
+        for (x, y) in [(104.9, 100.0), (295.1, 100.0), (150.0, 54.9), (150.0, 145.1), (20.0, 20.0)] {
+            assert_eq!(guard.check(&input, &target, &click(x, y)), Err(InputError::OutOfBounds { x, y }));
+        }
+    }
+
+    #[test]
+    fn both_ends_of_a_drag_are_checked() {
+        let guard = SafetyGuard::default();
+        let (input, target) = setup(Some(PID));
+        let drag = |from, to| InputAction::Drag { from, to };
+
+        assert_eq!(guard.check(&input, &target, &drag((120.0, 60.0), (280.0, 140.0))), Ok(()));
+        assert_eq!(
+            guard.check(&input, &target, &drag((120.0, 60.0), (400.0, 140.0))),
+            Err(InputError::OutOfBounds { x: 400.0, y: 140.0 })
+        );
+        assert_eq!(
+            guard.check(&input, &target, &drag((0.0, 0.0), (280.0, 140.0))),
+            Err(InputError::OutOfBounds { x: 0.0, y: 0.0 })
+        );
+    }
+
+    #[test]
+    fn a_closed_window_refuses_pointer_input() {
+        let guard = SafetyGuard::default();
+        let (input, _) = setup(Some(PID));
+        let gone = InputTarget::new(PID, Arc::new(Mutex::new(None)));
+        assert_eq!(guard.check(&input, &gone, &click(150.0, 100.0)), Err(InputError::TargetGone));
+        assert_eq!(guard.check(&input, &gone, &InputAction::Wait(Duration::ZERO)), Ok(()));
+    }
+}
@@ -0,0 +1,485 @@
+use crate::bot_engine::{send_log, LogLevel, LogMessage};
+use crate::game_state::GameState;
+use crate::task::{Task, TaskId, TaskRegistry};
+use crate::worker::Worker;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// How often the scheduler looks at triggers, schedules and its tasks.
+const SCHEDULER_TICK: Duration = Duration::from_millis(250);
+
+/// Higher priorities preempt lower ones; equal ones wait their turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TaskPriority {
+    /// Farming loops and other filler.
+    Background,
+    /// Things the user asked for.
+    Normal,
+    /// Reactions to the game: a fight started, a whisper, a full inventory.
+    Reactive,
+    Critical,
+}
+
+pub type TaskFactory = Box<dyn Fn() -> Box<dyn Task> + Send>;
+pub type Condition = Box<dyn Fn(&GameState) -> bool + Send>;
+
+/// Common conditions for triggers.
+pub mod conditions {
+    use super::Condition;
+
+    pub fn combat_started() -> Condition {
+        Box::new(|state| state.in_combat())
+    }
+
+    /// A game window (a whisper tab, a trade request...) was detected as open.
+    pub fn window_open(name: &str) -> Condition {
+        let name = name.to_string();
+        Box::new(move |state| state.is_window_open(&name))
+    }
+
+    /// Pods at or above `fraction` of the maximum.
+    pub fn inventory_full(fraction: f32) -> Condition {
+        Box::new(move |state| {
+            state
+                .pods
+                .as_ref()
+                .is_some_and(|p| p.value.1 > 0 && p.value.0 as f32 >= p.value.1 as f32 * fraction)
+        })
+    }
+}
+
+/// Starts a task each time `condition` becomes true.
+struct Trigger {
+    name: String,
+    priority: TaskPriority,
+    condition: Condition,
+    factory: TaskFactory,
+    /// Whether the condition held last time, so it fires on the rising edge only.
+    held: bool,
+}
+
+/// Starts a task every `interval`.
+struct Recurring {
+    name: String,
+    priority: TaskPriority,
+    interval: Duration,
+    next: Instant,
+    factory: TaskFactory,
+}
+
+struct Queued {
+    priority: TaskPriority,
+    task: Box<dyn Task>,
+    reason: String,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    queue: Vec<Queued>,
+    foreground: Option<(TaskId, TaskPriority)>,
+    /// Preempted tasks, paused, most recent last.
+    suspended: Vec<(TaskId, TaskPriority)>,
+    triggers: Vec<Trigger>,
+    recurring: Vec<Recurring>,
+}
+
+/// Runs one foreground task at a time for one client.
+///
+/// A task with a higher priority than the foreground one pauses it and takes over; the
+/// paused task resumes once nothing more urgent is left. Every decision goes to the
+/// client's log with its reason. A task already queued or running under the same name
+/// isn't queued again. Cheap to clone; clones share the same scheduler.
+#[derive(Clone)]
+pub struct Scheduler {
+    owner: String,
+    state: Arc<Mutex<SchedulerState>>,
+    tasks: TaskRegistry,
+    worker: Arc<Mutex<Option<Worker>>>,
+    log_tx: Sender<LogMessage>,
+}
+
+impl Scheduler {
+    pub fn start(owner: &str, tasks: TaskRegistry, game: watch::Receiver<GameState>, log_tx: Sender<LogMessage>) -> Self {
+        let scheduler = Self {
+            owner: owner.to_string(),
+            state: Arc::new(Mutex::new(SchedulerState::default())),
+            tasks,
+            worker: Arc::new(Mutex::new(None)),
+            log_tx,
+        };
+        let ticker = scheduler.clone();
+        let worker = Worker::spawn(&format!("scheduler-{}", owner), move |token| {
+            while !token.is_cancelled() {
+                ticker.tick(&game.borrow());
+                token.sleep(SCHEDULER_TICK);
+            }
+        });
+        *scheduler.worker.lock().unwrap() = Some(worker);
+        scheduler
+    }
+
+    /// Queues `task`; it runs once nothing more urgent is running. Returns false if a task
+    /// with the same name is already queued or running.
+    pub fn submit(&self, priority: TaskPriority, task: Box<dyn Task>, reason: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if self.is_busy_in(&state, task.name()) {
+            self.log(&format!("Ignoring {}: already queued or running", task.name()), LogLevel::Warning);
+            return false;
+        }
+        self.log(&format!("Queued {} ({:?}): {}", task.name(), priority, reason), LogLevel::Info);
+        state.queue.push(Queued {
+            priority,
+            task,
+            reason: reason.to_string(),
+        });
+        true
+    }
+
+    /// Whether a task called `name` is queued, running or suspended.
+    pub fn is_busy(&self, name: &str) -> bool {
+        self.is_busy_in(&self.state.lock().unwrap(), name)
+    }
+
+    fn is_busy_in(&self, state: &SchedulerState, name: &str) -> bool {
+        let running = |id: TaskId| self.tasks.info(id).is_some_and(|info| info.name == name && !info.state.is_finished());
+        state.queue.iter().any(|q| q.task.name() == name)
+            || state.foreground.is_some_and(|(id, _)| running(id))
+            || state.suspended.iter().any(|(id, _)| running(*id))
+    }
+
+    /// Stops scheduling, drops what is still queued and waits up to `timeout` for the
+    /// scheduler's thread. Running tasks are left to the registry.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let stopped = self.worker.lock().unwrap().take().is_none_or(|mut worker| worker.stop(timeout));
+        let mut state = self.state.lock().unwrap();
+        if !state.queue.is_empty() {
+            self.log(&format!("Dropping {} queued task(s): shutting down", state.queue.len()), LogLevel::Info);
+        }
+        state.queue.clear();
+        state.triggers.clear();
+        state.recurring.clear();
+        stopped
+    }
+
+    /// Queues a task from `factory` every time `condition` starts to hold.
+    pub fn on(&self, name: &str, priority: TaskPriority, condition: Condition, factory: TaskFactory) {
+        self.state.lock().unwrap().triggers.push(Trigger {
+            name: name.to_string(),
+            priority,
+            condition,
+            factory,
+            held: false,
+        });
+    }
+
+    /// Queues a task from `factory` every `interval`, the first one right away.
+    pub fn every(&self, name: &str, interval: Duration, priority: TaskPriority, factory: TaskFactory) {
+        self.state.lock().unwrap().recurring.push(Recurring {
+            name: name.to_string(),
+            priority,
+            interval,
+            next: Instant::now(),
+            factory,
+        });
+    }
+
+    /// Stops the recurring schedule called `name`. Returns false if there was none.
+    pub fn cancel_every(&self, name: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let before = state.recurring.len();
+        state.recurring.retain(|r| r.name != name);
+        before != state.recurring.len()
+    }
+
+    /// Whether a recurring schedule called `name` is registered.
+    pub fn is_scheduled(&self, name: &str) -> bool {
+        self.state.lock().unwrap().recurring.iter().any(|r| r.name == name)
+    }
+
+    fn log(&self, message: &str, level: LogLevel) {
+        send_log(&self.log_tx, &format!("Scheduler: {}", message), level);
+    }
+
+    fn is_finished(&self, id: TaskId) -> bool {
+        self.tasks.info(id).is_none_or(|info| info.state.is_finished())
+    }
+
+    fn tick(&self, game: &GameState) {
+        let mut state = self.state.lock().unwrap();
+
+        // 1. Fire triggers whose condition just started to hold
+        let mut fired = Vec::new();
+        for trigger in state.triggers.iter_mut() {
+            let holds = (trigger.condition)(game);
+            if holds && !trigger.held {
+                fired.push((trigger.priority, (trigger.factory)(), format!("trigger {}", trigger.name)));
+            }
+            trigger.held = holds;
+        }
+
+        // 2. Queue recurring tasks that are due
+        let now = Instant::now();
+        for recurring in state.recurring.iter_mut().filter(|r| r.next <= now) {
+            recurring.next = now + recurring.interval;
+            fired.push((recurring.priority, (recurring.factory)(), format!("every {:?} ({})", recurring.interval, recurring.name)));
+        }
+        for (priority, task, reason) in fired {
+            if self.is_busy_in(&state, task.name()) {
+                self.log(&format!("Skipping {} ({}): still queued or running", task.name(), reason), LogLevel::Info);
+                continue;
+            }
+            self.log(&format!("Queued {} ({:?}): {}", task.name(), priority, reason), LogLevel::Info);
+            state.queue.push(Queued { priority, task, reason });
+        }
+
+        // 3. Forget tasks that ended, including ones cancelled while suspended
+        if let Some((id, _)) = state.foreground
+            && self.is_finished(id)
+        {
+            state.foreground = None;
+        }
+        state.suspended.retain(|(id, _)| !self.is_finished(*id));
+
+        // 4. Pick what should be in the foreground
+        let next_queued = state
+            .queue
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, q)| (q.priority, std::cmp::Reverse(*i)))
+            .map(|(i, q)| (i, q.priority));
+        match (state.foreground, next_queued) {
+            // A more urgent task takes over
+            (Some((id, current)), Some((index, priority))) if priority > current => {
+                let queued = state.queue.remove(index);
+                self.log(
+                    &format!("Pausing task #{} ({:?}) for {} ({:?}): {}", id, current, queued.task.name(), priority, queued.reason),
+                    LogLevel::Warning,
+                );
+                self.tasks.pause(id);
+                state.suspended.push((id, current));
+                state.foreground = Some((self.run(queued), priority));
+            }
+            (Some(_), _) => {}
+            // Free: resume a preempted task unless something queued is more urgent
+            (None, next) => {
+                let resumable = state.suspended.last().copied();
+                match (resumable, next) {
+                    (Some((id, suspended)), next) if next.is_none_or(|(_, p)| p <= suspended) => {
+                        state.suspended.pop();
+                        self.log(&format!("Resuming task #{} ({:?}): nothing more urgent left", id, suspended), LogLevel::Info);
+                        self.tasks.resume(id);
+                        state.foreground = Some((id, suspended));
+                    }
+                    (_, Some((index, priority))) => {
+                        let queued = state.queue.remove(index);
+                        self.log(&format!("Starting {} ({:?}): {}", queued.task.name(), priority, queued.reason), LogLevel::Info);
+                        state.foreground = Some((self.run(queued), priority));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn run(&self, queued: Queued) -> TaskId {
+        self.tasks.spawn(&self.owner, self.log_tx.clone(), queued.task)
+    }
+
+    /// The task in the foreground and the ones waiting under it, for display.
+    pub fn summary(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let foreground = match state.foreground {
+            Some((id, priority)) => format!("#{} ({:?})", id, priority),
+            None => "idle".to_string(),
+        };
+        format!("{}, {} suspended, {} queued", foreground, state.suspended.len(), state.queue.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{TaskContext, TaskState, Tick};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Keeps ticking until `done` is set.
+    struct Hold {
+        name: &'static str,
+        done: Arc<AtomicBool>,
+    }
+
+    impl Task for Hold {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn tick(&mut self, _ctx: &TaskContext) -> Tick {
+            if self.done.load(Ordering::SeqCst) { Tick::Done } else { Tick::Continue }
+        }
+
+        fn tick_interval(&self) -> Duration {
+            Duration::from_millis(5)
+        }
+    }
+
+    /// A scheduler without its ticking thread, so tests call `tick` themselves.
+    fn scheduler() -> Scheduler {
+        let (log_tx, _log_rx) = mpsc::channel();
+        Scheduler {
+            owner: "test".to_string(),
+            state: Arc::new(Mutex::new(SchedulerState::default())),
+            tasks: TaskRegistry::default(),
+            worker: Arc::new(Mutex::new(None)),
+            log_tx,
+        }
+    }
+
+    fn hold(name: &'static str) -> (Box<dyn Task>, Arc<AtomicBool>) {
+        let done = Arc::new(AtomicBool::new(false));
+        (Box::new(Hold { name, done: Arc::clone(&done) }), done)
+    }
+
+    /// Name of the foreground task.
+    fn foreground(scheduler: &Scheduler) -> Option<String> {
+        let (id, _) = scheduler.state.lock().unwrap().foreground?;
+        scheduler.tasks.info(id).map(|info| info.name)
+    }
+
+    fn suspended(scheduler: &Scheduler) -> Vec<String> {
+        let state = scheduler.state.lock().unwrap();
+        state.suspended.iter().filter_map(|(id, _)| scheduler.tasks.info(*id)).map(|info| info.name).collect()
+    }
+
+    fn wait_for_state(scheduler: &Scheduler, name: &str, state: TaskState) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !scheduler.tasks.list().iter().any(|info| info.name == name && info.state == state) {
+            assert!(Instant::now() < deadline, "timed out waiting for {} to be {}", name, state);
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    #[test]
+    fn preempted_tasks_resume_most_recent_first() {
+        let scheduler = scheduler();
+        let game = GameState::default();
+        let (farm, _) = hold("farm");
+        let (errand, errand_done) = hold("errand");
+        let (fight, fight_done) = hold("fight");
+
+        scheduler.submit(TaskPriority::Background, farm, "test");
+        scheduler.tick(&game);
+        scheduler.submit(TaskPriority::Normal, errand, "test");
+        scheduler.tick(&game);
+        scheduler.submit(TaskPriority::Critical, fight, "test");
+        scheduler.tick(&game);
+        assert_eq!(foreground(&scheduler).as_deref(), Some("fight"));
+        assert_eq!(suspended(&scheduler), ["farm", "errand"]);
+        wait_for_state(&scheduler, "farm", TaskState::Paused);
+        wait_for_state(&scheduler, "errand", TaskState::Paused);
+
+        fight_done.store(true, Ordering::SeqCst);
+        wait_for_state(&scheduler, "fight", TaskState::Succeeded);
+        scheduler.tick(&game);
+        assert_eq!(foreground(&scheduler).as_deref(), Some("errand"));
+        wait_for_state(&scheduler, "errand", TaskState::Running);
+
+        errand_done.store(true, Ordering::SeqCst);
+        wait_for_state(&scheduler, "errand", TaskState::Succeeded);
+        scheduler.tick(&game);
+        assert_eq!(foreground(&scheduler).as_deref(), Some("farm"));
+        assert!(suspended(&scheduler).is_empty());
+        wait_for_state(&scheduler, "farm", TaskState::Running);
+        scheduler.tasks.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn equal_priority_waits_its_turn() {
+        let scheduler = scheduler();
+        let game = GameState::default();
+        let (first, first_done) = hold("first");
+        let (second, _) = hold("second");
+
+        scheduler.submit(TaskPriority::Normal, first, "test");
+        scheduler.tick(&game);
+        scheduler.submit(TaskPriority::Normal, second, "test");
+        scheduler.tick(&game);
+        assert_eq!(foreground(&scheduler).as_deref(), Some("first"));
+        assert!(suspended(&scheduler).is_empty());
+        assert!(scheduler.is_busy("second"));
+
+        first_done.store(true, Ordering::SeqCst);
+        wait_for_state(&scheduler, "first", TaskState::Succeeded);
+        scheduler.tick(&game);
+        assert_eq!(foreground(&scheduler).as_deref(), Some("second"));
+        scheduler.tasks.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn triggers_fire_on_the_rising_edge_only() {
+        let scheduler = scheduler();
+        let game = GameState::default();
+        let holds = Arc::new(AtomicBool::new(false));
+        let finished = Arc::new(AtomicBool::new(true));
+        let fired = Arc::new(Mutex::new(0));
+        let (condition, counter, done) = (Arc::clone(&holds), Arc::clone(&fired), Arc::clone(&finished));
+        scheduler.on(
+            "alarm",
+            TaskPriority::Reactive,
+            Box::new(move |_| condition.load(Ordering::SeqCst)),
+            Box::new(move || {
+                *counter.lock().unwrap() += 1;
+                Box::new(Hold { name: "alarm", done: Arc::clone(&done) })
+            }),
+        );
+        let fired = || *fired.lock().unwrap();
+
+        scheduler.tick(&game);
+        assert_eq!(fired(), 0);
+
+        holds.store(true, Ordering::SeqCst);
+        scheduler.tick(&game);
+        assert_eq!(fired(), 1);
+        wait_for_state(&scheduler, "alarm", TaskState::Succeeded);
+        scheduler.tick(&game);
+        scheduler.tick(&game);
+        assert_eq!(fired(), 1);
+        assert_eq!(foreground(&scheduler), None);
+
+        holds.store(false, Ordering::SeqCst);
+        scheduler.tick(&game);
+        holds.store(true, Ordering::SeqCst);
+        scheduler.tick(&game);
+        assert_eq!(fired(), 2);
+        scheduler.tasks.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn recurring_tasks_run_when_due_until_cancelled() {
+        let scheduler = scheduler();
+        let game = GameState::default();
+        let done = Arc::new(AtomicBool::new(true));
+        let finished = Arc::clone(&done);
+        scheduler.every(
+            "proof",
+            Duration::from_secs(3600),
+            TaskPriority::Normal,
+            Box::new(move || Box::new(Hold { name: "proof", done: Arc::clone(&finished) })),
+        );
+        assert!(scheduler.is_scheduled("proof"));
+
+        scheduler.tick(&game);
+        assert_eq!(scheduler.tasks.list().len(), 1);
+        wait_for_state(&scheduler, "proof", TaskState::Succeeded);
+        scheduler.tick(&game);
+        assert_eq!(scheduler.tasks.list().len(), 1);
+
+        assert!(scheduler.cancel_every("proof"));
+        assert!(!scheduler.is_scheduled("proof"));
+        assert!(!scheduler.cancel_every("proof"));
+        scheduler.tasks.shutdown(Duration::from_secs(1));
+    }
+}
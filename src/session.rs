@@ -0,0 +1,308 @@
+use crate::action_queue::{ActionQueue, DEFAULT_SPACING};
+use crate::activity_monitor::{self, ActivityConfig, ActivityMonitor};
+use crate::bot_engine::{send_log, BotEngine, LogLevel, LogMessage};
+use crate::detector::WorkerPool;
+use crate::dry_run::DryRun;
+use crate::input_backend::RdevBackend;
+use crate::input_manager::InputManager;
+use crate::keymap::{KeyboardLayout, Keymap};
+use crate::safety::SafetyGuard;
+use crate::task::{TaskId, TaskRegistry};
+use crate::tasks::{ScanResult, WindowScan};
+use crate::vision_pipeline::TemplateLibrary;
+use crate::window_selector::{WindowCandidate, WindowSelector};
+use crate::world_model::WorldModel;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WORLD_MODEL_PATH: &str = "./data/world.json";
+const WORLD_RECOVERY_PATH: &str = "./data/world.recovered.json";
+const KEYMAP_PATH: &str = "./config/keymap.json";
+/// Oldest lines are dropped past this, so a session left running for days doesn't grow forever.
+const MAX_LOG_LINES: usize = 5000;
+/// How long each background thread gets to stop when the app closes.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Loaded once and handed to every session: the templates, the detector worker pool,
+/// the keymap, the input queue (there is only one mouse) and the world model (all
+/// accounts play on the same server, so they share what they learn).
+pub struct SharedResources {
+    pub templates: Arc<TemplateLibrary>,
+    pub pool: Arc<WorkerPool>,
+    pub actions: ActionQueue,
+    pub keymap: Arc<Keymap>,
+    pub world: Arc<Mutex<WorldModel>>,
+    pub monitor: ActivityMonitor,
+    pub dry_run: Arc<DryRun>,
+    pub tasks: TaskRegistry,
+}
+
+impl SharedResources {
+    pub fn load(log_tx: &Sender<LogMessage>) -> Self {
+        let world = match WorldModel::load(Path::new(WORLD_MODEL_PATH)) {
+            Ok(world) => {
+                send_log(log_tx, &format!("Loaded world model: {} known nodes.", world.nodes().count()), LogLevel::Info);
+                world
+            }
+            Err(err) => {
+                // Keep the unreadable file for inspection instead of overwriting it.
+                send_log(log_tx, &format!("{}. Starting a new one in {}.", err, WORLD_RECOVERY_PATH), LogLevel::Warning);
+                WorldModel::new(Path::new(WORLD_RECOVERY_PATH))
+            }
+        };
+
+        let templates = match TemplateLibrary::load_dir(Path::new("./templates")) {
+            Ok(library) if library.is_empty() => {
+                send_log(log_tx, "No templates in ./templates: the HUD and resource detectors will find nothing.", LogLevel::Warning);
+                library
+            }
+            Ok(library) => {
+                send_log(log_tx, &format!("Loaded {} templates.", library.len()), LogLevel::Info);
+                library
+            }
+            Err(err) => {
+                send_log(log_tx, &err, LogLevel::Warning);
+                TemplateLibrary::new()
+            }
+        };
+
+        let keymap = Keymap::load(Path::new(KEYMAP_PATH)).unwrap_or_else(|err| {
+            send_log(log_tx, &format!("{}. Using the default AZERTY keymap.", err), LogLevel::Warning);
+            Keymap::defaults(KeyboardLayout::AzertyMac)
+        });
+
+        let pool = WorkerPool::with_default_size();
+        send_log(log_tx, &format!("Running detectors on {} worker threads.", pool.size()), LogLevel::Info);
+
+        let backend = RdevBackend::new();
+        let injected = backend.injections();
+        let dry_run = Arc::new(DryRun::new(log_tx.clone()));
+        let actions = ActionQueue::new(
+            InputManager::with_backend(Arc::new(backend)).with_dry_run(Arc::clone(&dry_run)),
+            DEFAULT_SPACING,
+            SafetyGuard::default(),
+            log_tx.clone(),
+        );
+        let tasks = TaskRegistry::default();
+        let monitor = activity_monitor::start(
+            ActivityConfig::default(),
+            injected,
+            actions.clone(),
+            tasks.clone(),
+            log_tx.clone(),
+        );
+
+        Self {
+            templates: Arc::new(templates),
+            pool: Arc::new(pool),
+            actions,
+            keymap: Arc::new(keymap),
+            world: Arc::new(Mutex::new(world)),
+            monitor,
+            dry_run,
+            tasks,
+        }
+    }
+}
+
+/// One Dofus client, identified by its character name.
+pub struct Session {
+    pub name: String,
+    pub engine: BotEngine,
+    pub logs: Vec<LogMessage>,
+    log_rx: Receiver<LogMessage>,
+}
+
+impl Session {
+    fn new(name: String, shared: &SharedResources) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            engine: BotEngine::new(&name, tx, shared),
+            name,
+            logs: Vec::new(),
+            log_rx: rx,
+        }
+    }
+
+    /// Moves the messages sent since the last call into `logs`.
+    pub fn drain_logs(&mut self) {
+        while let Ok(msg) = self.log_rx.try_recv() {
+            self.logs.push(msg);
+        }
+        if self.logs.len() > MAX_LOG_LINES {
+            let excess = self.logs.len() - MAX_LOG_LINES;
+            self.logs.drain(..excess);
+        }
+    }
+}
+
+/// Runs one `Session` per Dofus window, all sharing the same `SharedResources`.
+pub struct SessionManager {
+    pub shared: SharedResources,
+    sessions: Vec<Session>,
+    /// Windows found by the last scan.
+    pub candidates: Vec<WindowCandidate>,
+    /// Scan task in progress and where it leaves what it found.
+    scan: Option<(TaskId, ScanResult)>,
+    log_tx: Sender<LogMessage>,
+}
+
+impl SessionManager {
+    pub fn new(log_tx: Sender<LogMessage>) -> Self {
+        Self {
+            shared: SharedResources::load(&log_tx),
+            sessions: Vec::new(),
+            candidates: Vec::new(),
+            scan: None,
+            log_tx,
+        }
+    }
+
+    pub fn log(&self, message: &str, level: LogLevel) {
+        send_log(&self.log_tx, message, level);
+    }
+
+    /// Sender for the system log, for components working across sessions.
+    pub fn log_sender(&self) -> Sender<LogMessage> {
+        self.log_tx.clone()
+    }
+
+    /// Starts a task listing the windows matching `selector`; `poll_scan` picks up the
+    /// result.
+    pub fn scan(&mut self, selector: &WindowSelector) {
+        if self.is_scanning() {
+            return;
+        }
+        self.log(&format!("Scanning for Dofus windows ({})...", selector), LogLevel::Info);
+        let found = Arc::new(Mutex::new(None));
+        let task = WindowScan::new(selector.clone(), Arc::clone(&found));
+        let id = self.shared.tasks.spawn("all sessions", self.log_tx.clone(), Box::new(task));
+        self.scan = Some((id, found));
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    /// Handles a finished scan: a single match is opened straight away; with several,
+    /// the caller picks which ones to open.
+    pub fn poll_scan(&mut self) {
+        let Some((id, found)) = &self.scan else { return };
+        let finished = self.shared.tasks.info(*id).is_none_or(|info| info.state.is_finished());
+        if !finished {
+            return;
+        }
+        let found = found.lock().unwrap().take();
+        self.scan = None;
+        // A failed scan was already logged by the task.
+        let Some(candidates) = found else { return };
+        self.candidates = candidates;
+
+        match self.candidates.len() {
+            0 => self.log("Dofus window not found.", LogLevel::Warning),
+            1 => {
+                let candidate = self.candidates[0].clone();
+                self.open(&candidate);
+            }
+            n => self.log(&format!("Found {} matching windows, pick the ones to run.", n), LogLevel::Info),
+        }
+    }
+
+    /// Starts a session on `candidate`'s window and returns its name. If a session already
+    /// exists for that character (the client was restarted), it is pointed at the new window.
+    pub fn open(&mut self, candidate: &WindowCandidate) -> String {
+        let name = session_name(candidate);
+        let index = match self.sessions.iter().position(|s| s.name == name) {
+            Some(index) => index,
+            None => {
+                self.log(&format!("Opening session '{}'.", name), LogLevel::Info);
+                self.sessions.push(Session::new(name.clone(), &self.shared));
+                self.sessions.len() - 1
+            }
+        };
+
+        let session = &mut self.sessions[index];
+        session.engine.select_window(candidate);
+        name
+    }
+
+    /// Opens a session for every candidate from the last scan.
+    pub fn open_all(&mut self) {
+        for candidate in self.candidates.clone() {
+            self.open(&candidate);
+        }
+    }
+
+    /// Stops the session's stream and detectors.
+    pub fn close(&mut self, name: &str) {
+        if let Some(index) = self.sessions.iter().position(|s| s.name == name) {
+            self.sessions.remove(index);
+            self.log(&format!("Closed session '{}'.", name), LogLevel::Info);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Session> {
+        self.sessions.iter().find(|s| s.name == name)
+    }
+
+    /// Whether some session is already capturing this window.
+    pub fn is_open(&self, candidate: &WindowCandidate) -> bool {
+        self.sessions
+            .iter()
+            .any(|s| s.engine.vision.target_window().is_some_and(|t| t.window_id == candidate.window_id))
+    }
+
+    pub fn sessions(&self) -> &[Session] {
+        &self.sessions
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sessions.iter().map(|s| s.name.clone()).collect()
+    }
+
+    pub fn drain_logs(&mut self) {
+        for session in &mut self.sessions {
+            session.drain_logs();
+        }
+    }
+
+    /// Closes the shared input queue, then stops the background work of every session,
+    /// the tasks and the idle timer, and saves the world model. The queue goes first so
+    /// nothing is sent while the rest winds down and tasks waiting on input are released.
+    /// Threads that don't stop within `SHUTDOWN_TIMEOUT` are left to end with the process.
+    pub fn shutdown(&mut self) {
+        self.log("Shutting down...", LogLevel::Info);
+        let mut stuck = Vec::new();
+        if !self.shared.actions.shutdown(SHUTDOWN_TIMEOUT) {
+            stuck.push("action-queue".to_string());
+        }
+        for session in &mut self.sessions {
+            stuck.extend(session.engine.shutdown(SHUTDOWN_TIMEOUT));
+        }
+        stuck.extend(self.shared.tasks.shutdown(SHUTDOWN_TIMEOUT).into_iter().map(|name| format!("task {}", name)));
+        if !self.shared.monitor.shutdown(SHUTDOWN_TIMEOUT) {
+            stuck.push("idle-resume".to_string());
+        }
+
+        // Sighting times don't mark the model dirty, so save whether or not it is.
+        if let Err(err) = self.shared.world.lock().unwrap().save() {
+            self.log(&err, LogLevel::Error);
+        }
+
+        if stuck.is_empty() {
+            self.log("All background work stopped.", LogLevel::Info);
+        } else {
+            self.log(&format!("Still running at exit: {}", stuck.join(", ")), LogLevel::Warning);
+        }
+    }
+}
+
+/// Sessions are keyed by character; windows whose title doesn't name one fall back to the title.
+fn session_name(candidate: &WindowCandidate) -> String {
+    candidate
+        .character
+        .clone()
+        .unwrap_or_else(|| format!("{} ({})", candidate.title, candidate.window_id))
+}
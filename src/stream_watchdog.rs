@@ -0,0 +1,288 @@
+use crate::vision_engine::{open_stream, find_window, StreamHandler, TargetWindow, VisionEvent, WindowGeometry};
+use screencapturekit::{shareable_content::SCShareableContent, stream::SCStream};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the watchdog wakes up to check the window and the frames.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// No frame for this long and we start asking whether the stream is still alive.
+const STALL_AFTER: Duration = Duration::from_secs(3);
+/// How long a restarted stream gets to deliver its first frame.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often a static screen is re-probed to make sure the stream didn't die in the meantime.
+const STATIC_REPROBE: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamHealth {
+    /// No window selected yet.
+    NoTarget,
+    /// Frames are arriving.
+    Active,
+    /// The stream is alive (a restart produced a frame) but the picture isn't changing.
+    Static,
+    /// The window exists but is minimized or off-screen.
+    Hidden,
+    /// No recent frame; restarted the stream to find out whether it is dead or just static.
+    Probing,
+    /// The window is gone or the stream won't produce frames. Retrying with backoff.
+    Dead { attempts: u32 },
+}
+
+impl fmt::Display for StreamHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamHealth::NoTarget => write!(f, "STREAM INACTIVE"),
+            StreamHealth::Active => write!(f, "STREAM ACTIVE"),
+            StreamHealth::Static => write!(f, "STREAM STATIC"),
+            StreamHealth::Hidden => write!(f, "WINDOW HIDDEN"),
+            StreamHealth::Probing => write!(f, "STREAM STALLED"),
+            StreamHealth::Dead { attempts } => write!(f, "STREAM DEAD (retry {})", attempts),
+        }
+    }
+}
+
+/// State shared between the engine, the capture handler and the watchdog thread.
+pub struct WatchdogShared {
+    pub stop: Arc<AtomicBool>,
+    pub handler: StreamHandler,
+    pub stream: Arc<Mutex<Option<SCStream>>>,
+    pub target: Arc<Mutex<Option<TargetWindow>>>,
+    pub geometry: Arc<Mutex<Option<WindowGeometry>>>,
+    pub health: Arc<Mutex<StreamHealth>>,
+    pub last_frame_at: Arc<Mutex<Option<Instant>>>,
+    pub frame_seq: Arc<AtomicU64>,
+    pub event_subscribers: Arc<Mutex<Vec<Sender<VisionEvent>>>>,
+}
+
+/// Background thread keeping the capture stream alive.
+///
+/// ScreenCaptureKit only delivers frames when the picture changes, so silence alone can't
+/// tell a dead stream from a static screen. When frames stop, the watchdog restarts the
+/// stream: a live window always yields a first frame on a fresh stream, a dead one doesn't.
+/// It also follows the window's geometry, rebuilding the stream when the size changes,
+/// and re-discovers the window if the client comes back with a new window id.
+pub struct StreamWatchdog {
+    shared: WatchdogShared,
+    started: Instant,
+    probe: Option<(Instant, u64)>,
+    last_probe: Option<Instant>,
+    static_seq: u64,
+    failures: u32,
+    next_retry: Instant,
+}
+
+impl StreamWatchdog {
+    pub fn new(shared: WatchdogShared) -> Self {
+        Self {
+            shared,
+            started: Instant::now(),
+            probe: None,
+            last_probe: None,
+            static_seq: 0,
+            failures: 0,
+            next_retry: Instant::now(),
+        }
+    }
+
+    pub fn run(mut self) {
+        while !self.shared.stop.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        let Some(target) = self.shared.target.lock().unwrap().clone() else {
+            self.set_health(StreamHealth::NoTarget, "no window selected");
+            return;
+        };
+
+        let Ok(content) = SCShareableContent::get() else {
+            return;
+        };
+        let window = content.windows().into_iter().find(|w| w.window_id() == target.window_id);
+
+        let Some(window) = window else {
+            self.recover(&target, "window closed");
+            return;
+        };
+
+        if !window.is_on_screen() {
+            self.set_health(StreamHealth::Hidden, "window minimized or off-screen");
+            return;
+        }
+
+        self.check_geometry(WindowGeometry::of(&window));
+        self.check_frames();
+    }
+
+    fn check_frames(&mut self) {
+        let seq = self.shared.frame_seq.load(Ordering::SeqCst);
+        let since_frame = self
+            .shared
+            .last_frame_at
+            .lock()
+            .unwrap()
+            .map(|t| t.elapsed())
+            .unwrap_or(self.started.elapsed());
+
+        // 1. A probe is running: did the fresh stream produce a frame?
+        if let Some((started, probe_seq)) = self.probe {
+            if seq > probe_seq {
+                self.probe = None;
+                self.failures = 0;
+                self.static_seq = seq;
+                self.set_health(StreamHealth::Static, "restarted stream delivered a frame, picture unchanged");
+            } else if started.elapsed() >= PROBE_TIMEOUT {
+                self.probe = None;
+                self.fail("restarted stream delivered no frame");
+            }
+            return;
+        }
+
+        // 2. Waiting out the backoff after a failure
+        if let StreamHealth::Dead { .. } = self.health() {
+            if Instant::now() >= self.next_retry {
+                self.start_probe("retrying stream");
+            }
+            return;
+        }
+
+        // 3. Frames flowing. Right after a probe a couple of frames trickle in, so a
+        //    static screen only counts as active again once the sequence really moves.
+        if since_frame < STALL_AFTER {
+            if self.health() != StreamHealth::Static || seq > self.static_seq + 2 {
+                self.set_health(StreamHealth::Active, "frames arriving");
+            }
+            return;
+        }
+
+        let due = match self.health() {
+            StreamHealth::Static => self.last_probe.is_none_or(|t| t.elapsed() >= STATIC_REPROBE),
+            _ => true,
+        };
+        if due {
+            self.start_probe("no frame for a while");
+        }
+    }
+
+    fn start_probe(&mut self, reason: &str) {
+        let seq = self.shared.frame_seq.load(Ordering::SeqCst);
+        let now = Instant::now();
+        self.probe = Some((now, seq));
+        self.last_probe = Some(now);
+        self.set_health(StreamHealth::Probing, reason);
+        if let Err(err) = self.restart_stream() {
+            self.probe = None;
+            self.fail(&err);
+        }
+    }
+
+    fn fail(&mut self, reason: &str) {
+        self.failures += 1;
+        let backoff = Duration::from_secs(1u64 << self.failures.min(5)).min(MAX_BACKOFF);
+        self.next_retry = Instant::now() + backoff;
+        self.set_health(
+            StreamHealth::Dead { attempts: self.failures },
+            &format!("{}; retrying in {}s", reason, backoff.as_secs()),
+        );
+    }
+
+    /// The window id is gone: look for the client again (it may have restarted) and
+    /// point the stream at whatever we find.
+    fn recover(&mut self, old: &TargetWindow, reason: &str) {
+        if !matches!(self.health(), StreamHealth::Dead { .. }) {
+            self.probe = None;
+            self.fail(reason);
+            return;
+        }
+        if Instant::now() < self.next_retry {
+            return;
+        }
+
+        match find_window(|w| old.matches_replacement(w)) {
+            Some((new, geometry)) => {
+                *self.shared.target.lock().unwrap() = Some(new.clone());
+                *self.shared.geometry.lock().unwrap() = Some(geometry);
+                self.emit(VisionEvent::TargetReplaced { old: old.clone(), new: new.clone() });
+                self.start_probe("found the window again");
+            }
+            None => self.fail(&format!("{}, no replacement found", reason)),
+        }
+    }
+
+    fn restart_stream(&self) -> Result<(), String> {
+        let target = self.shared.target.lock().unwrap().clone().ok_or("no target window")?;
+        let geometry = self.shared.geometry.lock().unwrap().ok_or("unknown window size")?;
+        let (w, h) = geometry.capture_size();
+
+        let mut stream = self.shared.stream.lock().unwrap();
+        if let Some(mut s) = stream.take() {
+            let _ = s.stop_capture();
+        }
+        *stream = Some(open_stream(target.window_id, w, h, self.shared.handler.clone())?);
+        Ok(())
+    }
+
+    fn check_geometry(&mut self, new: WindowGeometry) {
+        let old = match self.shared.geometry.lock().unwrap().replace(new) {
+            Some(old) if old != new => old,
+            _ => return,
+        };
+
+        if old.capture_size() != new.capture_size()
+            && let Err(err) = self.restart_stream()
+        {
+            self.fail(&err);
+        }
+
+        self.emit(VisionEvent::WindowGeometryChanged { old, new });
+    }
+
+    fn health(&self) -> StreamHealth {
+        self.shared.health.lock().unwrap().clone()
+    }
+
+    fn set_health(&self, new: StreamHealth, reason: &str) {
+        set_health(&self.shared.health, &self.shared.event_subscribers, new, reason);
+    }
+
+    fn emit(&self, event: VisionEvent) {
+        emit(&self.shared.event_subscribers, event);
+    }
+}
+
+/// Moves the stream to `new`, telling subscribers with a `HealthChanged` event if that's a change.
+pub fn set_health(
+    health: &Mutex<StreamHealth>,
+    subscribers: &Mutex<Vec<Sender<VisionEvent>>>,
+    new: StreamHealth,
+    reason: &str,
+) {
+    let old = {
+        let mut health = health.lock().unwrap();
+        if *health == new {
+            return;
+        }
+        std::mem::replace(&mut *health, new.clone())
+    };
+    emit(
+        subscribers,
+        VisionEvent::HealthChanged {
+            old,
+            new,
+            reason: reason.to_string(),
+        },
+    );
+}
+
+fn emit(subscribers: &Mutex<Vec<Sender<VisionEvent>>>, event: VisionEvent) {
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,499 @@
+use crate::bot_engine::{send_log, LogLevel, LogMessage};
+use crate::worker::{join_until, CancelToken};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Default pause between two ticks.
+pub const DEFAULT_TICK: Duration = Duration::from_millis(100);
+
+pub type TaskId = u64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskState {
+    Pending,
+    Running,
+    Paused,
+    Succeeded,
+    /// Includes being cancelled.
+    Failed(String),
+}
+
+impl TaskState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskState::Succeeded | TaskState::Failed(_))
+    }
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskState::Pending => write!(f, "Pending"),
+            TaskState::Running => write!(f, "Running"),
+            TaskState::Paused => write!(f, "Paused"),
+            TaskState::Succeeded => write!(f, "Succeeded"),
+            TaskState::Failed(reason) => write!(f, "Failed: {}", reason),
+        }
+    }
+}
+
+/// What a tick decided.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tick {
+    Continue,
+    Done,
+    Failed(String),
+}
+
+/// A unit of bot work with a lifecycle. The registry drives it from its own thread:
+/// `start` once, then `tick` until it returns `Done` or `Failed`, with `pause`, `resume`
+/// and `cancel` called in between as the user asks. Ticks should return quickly; long
+/// waits are better spread over several ticks, so pausing and cancelling stay responsive.
+pub trait Task: Send {
+    fn name(&self) -> &str;
+
+    fn start(&mut self, _ctx: &TaskContext) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn tick(&mut self, ctx: &TaskContext) -> Tick;
+
+    fn pause(&mut self, _ctx: &TaskContext) {}
+
+    fn resume(&mut self, _ctx: &TaskContext) {}
+
+    /// Last chance to drop queued input or other work in flight.
+    fn cancel(&mut self, _ctx: &TaskContext) {}
+
+    fn tick_interval(&self) -> Duration {
+        DEFAULT_TICK
+    }
+}
+
+/// State, progress and status line of a task, as shown to the user.
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    /// Session the task acts for.
+    pub owner: String,
+    pub state: TaskState,
+    /// `0.0..=1.0`, for tasks that know how far along they are.
+    pub progress: Option<f32>,
+    pub status: String,
+}
+
+/// Handed to every task call: logging, progress reporting and cancellation.
+pub struct TaskContext {
+    info: Arc<Mutex<TaskInfo>>,
+    token: CancelToken,
+    log_tx: Sender<LogMessage>,
+}
+
+impl TaskContext {
+    pub fn log(&self, message: &str, level: LogLevel) {
+        send_log(&self.log_tx, message, level);
+    }
+
+    /// Reports how far along the task is and what it's doing.
+    pub fn progress(&self, fraction: Option<f32>, status: &str) {
+        let mut info = self.info.lock().unwrap();
+        info.progress = fraction.map(|f| f.clamp(0.0, 1.0));
+        info.status = status.to_string();
+    }
+
+    /// Whether the task was cancelled, for ticks that loop or wait.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    fn set_state(&self, state: TaskState) {
+        self.info.lock().unwrap().state = state;
+    }
+
+    /// A context outside any registry, for driving a task or tree by hand in tests.
+    #[cfg(test)]
+    pub fn detached(name: &str, log_tx: Sender<LogMessage>) -> Self {
+        let info = TaskInfo {
+            id: 0,
+            name: name.to_string(),
+            owner: "test".to_string(),
+            state: TaskState::Running,
+            progress: None,
+            status: String::new(),
+        };
+        Self {
+            info: Arc::new(Mutex::new(info)),
+            token: CancelToken::new(),
+            log_tx,
+        }
+    }
+}
+
+enum Command {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+struct Entry {
+    info: Arc<Mutex<TaskInfo>>,
+    commands: Sender<Command>,
+    token: CancelToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Entry {
+    fn cancel(&self) {
+        self.token.cancel();
+        let _ = self.commands.send(Command::Cancel);
+    }
+}
+
+/// Every task started in this process. Cheap to clone; clones share the registry.
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+    entries: Arc<Mutex<Vec<Entry>>>,
+    next_id: Arc<Mutex<TaskId>>,
+}
+
+impl TaskRegistry {
+    /// Starts `task` on its own thread, acting for session `owner` and logging to `log_tx`.
+    pub fn spawn(&self, owner: &str, log_tx: Sender<LogMessage>, task: Box<dyn Task>) -> TaskId {
+        let id = {
+            let mut next = self.next_id.lock().unwrap();
+            *next += 1;
+            *next
+        };
+        let info = Arc::new(Mutex::new(TaskInfo {
+            id,
+            name: task.name().to_string(),
+            owner: owner.to_string(),
+            state: TaskState::Pending,
+            progress: None,
+            status: String::new(),
+        }));
+        let (tx, rx) = mpsc::channel();
+        let token = CancelToken::new();
+        let ctx = TaskContext {
+            info: Arc::clone(&info),
+            token: token.clone(),
+            log_tx,
+        };
+        let thread = thread::spawn(move || drive(task, ctx, rx));
+        self.entries.lock().unwrap().push(Entry {
+            info,
+            commands: tx,
+            token,
+            thread: Some(thread),
+        });
+        id
+    }
+
+    pub fn pause(&self, id: TaskId) {
+        self.send(id, Command::Pause);
+    }
+
+    pub fn resume(&self, id: TaskId) {
+        self.send(id, Command::Resume);
+    }
+
+    pub fn cancel(&self, id: TaskId) {
+        let entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter().find(|e| e.info.lock().unwrap().id == id) {
+            entry.cancel();
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        for entry in self.entries.lock().unwrap().iter() {
+            entry.cancel();
+        }
+    }
+
+    /// Cancels every task and waits up to `timeout` for their threads. Returns the names
+    /// of the tasks still running after that.
+    pub fn shutdown(&self, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let running: Vec<(String, JoinHandle<()>)> = {
+            let mut entries = self.entries.lock().unwrap();
+            entries
+                .iter_mut()
+                .filter_map(|e| {
+                    e.cancel();
+                    let name = e.info.lock().unwrap().name.clone();
+                    e.thread.take().map(|thread| (name, thread))
+                })
+                .collect()
+        };
+        running
+            .into_iter()
+            .filter_map(|(name, thread)| (!join_until(thread, deadline)).then_some(name))
+            .collect()
+    }
+
+    /// Snapshot of every task, oldest first.
+    pub fn list(&self) -> Vec<TaskInfo> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.info.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Snapshot of one task, if it's still known.
+    pub fn info(&self, id: TaskId) -> Option<TaskInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().map(|e| e.info.lock().unwrap().clone()).find(|info| info.id == id)
+    }
+
+    /// Forgets tasks that have succeeded or failed.
+    pub fn clear_finished(&self) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|e| !e.info.lock().unwrap().state.is_finished());
+    }
+
+    fn send(&self, id: TaskId, command: Command) {
+        let entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter().find(|e| e.info.lock().unwrap().id == id) {
+            let _ = entry.commands.send(command);
+        }
+    }
+}
+
+/// Runs one task through its lifecycle.
+fn drive(mut task: Box<dyn Task>, ctx: TaskContext, commands: Receiver<Command>) {
+    let finish = |state: TaskState| {
+        let level = if state == TaskState::Succeeded { LogLevel::Success } else { LogLevel::Warning };
+        let name = ctx.info.lock().unwrap().name.clone();
+        ctx.log(&format!("Task {}: {}", name, state), level);
+        ctx.set_state(state);
+    };
+
+    // 1. Start
+    ctx.set_state(TaskState::Running);
+    if let Err(err) = task.start(&ctx) {
+        return finish(TaskState::Failed(err));
+    }
+
+    let mut paused = false;
+    loop {
+        // 2. Apply what the user asked for. A paused task just waits for the next command;
+        //    a dropped registry counts as cancelling.
+        let command = if ctx.is_cancelled() {
+            Some(Command::Cancel)
+        } else if paused {
+            Some(commands.recv().unwrap_or(Command::Cancel))
+        } else {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => Some(Command::Cancel),
+            }
+        };
+        match command {
+            Some(Command::Pause) if !paused => {
+                task.pause(&ctx);
+                paused = true;
+                ctx.set_state(TaskState::Paused);
+                continue;
+            }
+            Some(Command::Resume) if paused => {
+                task.resume(&ctx);
+                paused = false;
+                ctx.set_state(TaskState::Running);
+            }
+            Some(Command::Cancel) => {
+                task.cancel(&ctx);
+                return finish(TaskState::Failed("cancelled".to_string()));
+            }
+            _ => {}
+        }
+        if paused {
+            continue;
+        }
+
+        // 3. Tick
+        match task.tick(&ctx) {
+            Tick::Continue => {
+                ctx.token.sleep(task.tick_interval());
+            }
+            Tick::Done => return finish(TaskState::Succeeded),
+            Tick::Failed(reason) => return finish(TaskState::Failed(reason)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A task that logs its lifecycle calls and keeps ticking until `outcome` is set.
+    #[derive(Clone, Default)]
+    struct Scripted {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        ticks: Arc<AtomicU32>,
+        start_error: Option<String>,
+        outcome: Arc<Mutex<Option<Tick>>>,
+        /// How long each tick blocks, ignoring cancellation.
+        tick_time: Duration,
+    }
+
+    impl Scripted {
+        fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                ..Self::default()
+            }
+        }
+
+        fn calls(&self) -> Vec<&'static str> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn finish(&self, tick: Tick) {
+            *self.outcome.lock().unwrap() = Some(tick);
+        }
+    }
+
+    impl Task for Scripted {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn start(&mut self, _ctx: &TaskContext) -> Result<(), String> {
+            self.calls.lock().unwrap().push("start");
+            self.start_error.clone().map_or(Ok(()), Err)
+        }
+
+        fn tick(&mut self, _ctx: &TaskContext) -> Tick {
+            self.ticks.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(self.tick_time);
+            self.outcome.lock().unwrap().clone().unwrap_or(Tick::Continue)
+        }
+
+        fn pause(&mut self, _ctx: &TaskContext) {
+            self.calls.lock().unwrap().push("pause");
+        }
+
+        fn resume(&mut self, _ctx: &TaskContext) {
+            self.calls.lock().unwrap().push("resume");
+        }
+
+        fn cancel(&mut self, _ctx: &TaskContext) {
+            self.calls.lock().unwrap().push("cancel");
+        }
+
+        fn tick_interval(&self) -> Duration {
+            Duration::from_millis(5)
+        }
+    }
+
+    /// Drives `task` on a thread with a detached context, as the registry would.
+    fn drive_detached(task: Scripted) -> (Arc<Mutex<TaskInfo>>, Sender<Command>, JoinHandle<()>) {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let ctx = TaskContext::detached(task.name, log_tx);
+        ctx.set_state(TaskState::Pending);
+        let info = Arc::clone(&ctx.info);
+        assert_eq!(info.lock().unwrap().state, TaskState::Pending);
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || drive(Box::new(task), ctx, rx));
+        (info, tx, thread)
+    }
+
+    fn wait_for(what: &str, done: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    fn state(info: &Arc<Mutex<TaskInfo>>) -> TaskState {
+        info.lock().unwrap().state.clone()
+    }
+
+    #[test]
+    fn pausing_stops_the_ticks_until_resumed() {
+        let task = Scripted::new("scripted");
+        let (info, commands, thread) = drive_detached(task.clone());
+        wait_for("a tick", || task.ticks.load(Ordering::SeqCst) > 0);
+        assert_eq!(state(&info), TaskState::Running);
+
+        commands.send(Command::Pause).unwrap();
+        wait_for("the pause", || state(&info) == TaskState::Paused);
+        let ticks = task.ticks.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(task.ticks.load(Ordering::SeqCst), ticks);
+
+        commands.send(Command::Resume).unwrap();
+        wait_for("a tick after resuming", || task.ticks.load(Ordering::SeqCst) > ticks);
+        assert_eq!(state(&info), TaskState::Running);
+
+        task.finish(Tick::Done);
+        thread.join().unwrap();
+        assert_eq!(state(&info), TaskState::Succeeded);
+        assert_eq!(task.calls(), ["start", "pause", "resume"]);
+    }
+
+    #[test]
+    fn cancelling_a_paused_task_wakes_it() {
+        let task = Scripted::new("scripted");
+        let (info, commands, thread) = drive_detached(task.clone());
+        commands.send(Command::Pause).unwrap();
+        wait_for("the pause", || state(&info) == TaskState::Paused);
+
+        commands.send(Command::Cancel).unwrap();
+        thread.join().unwrap();
+        assert_eq!(state(&info), TaskState::Failed("cancelled".to_string()));
+        assert_eq!(task.calls(), ["start", "pause", "cancel"]);
+    }
+
+    #[test]
+    fn failing_to_start_fails_without_ticking() {
+        let task = Scripted {
+            start_error: Some("no window".to_string()),
+            ..Scripted::new("scripted")
+        };
+        let (info, _commands, thread) = drive_detached(task.clone());
+        thread.join().unwrap();
+        assert_eq!(state(&info), TaskState::Failed("no window".to_string()));
+        assert_eq!(task.ticks.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn a_failed_tick_fails_the_task() {
+        let task = Scripted::new("scripted");
+        task.finish(Tick::Failed("lost the fight".to_string()));
+        let (info, _commands, thread) = drive_detached(task.clone());
+        thread.join().unwrap();
+        assert_eq!(state(&info), TaskState::Failed("lost the fight".to_string()));
+        assert_eq!(task.ticks.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn shutdown_reports_tasks_stuck_in_a_tick() {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let registry = TaskRegistry::default();
+        let stuck = Scripted {
+            tick_time: Duration::from_millis(500),
+            ..Scripted::new("stuck")
+        };
+        let quick = Scripted::new("quick");
+        registry.spawn("test", log_tx.clone(), Box::new(stuck.clone()));
+        registry.spawn("test", log_tx, Box::new(quick.clone()));
+        wait_for("both tasks ticking", || {
+            stuck.ticks.load(Ordering::SeqCst) > 0 && quick.ticks.load(Ordering::SeqCst) > 0
+        });
+
+        assert_eq!(registry.shutdown(Duration::from_millis(100)), ["stuck"]);
+        assert_eq!(quick.calls(), ["start", "cancel"]);
+    }
+}
@@ -0,0 +1,434 @@
+use crate::action_queue::{ActionQueue, ActionRequest, ActionTicket, InputAction};
+use crate::bot_engine::LogLevel;
+use crate::expect::{ExpectError, Expectation, Verifier};
+use crate::game_state::GameState;
+use crate::scheduler::Condition;
+use crate::task::{Task, TaskContext, Tick};
+use crate::vision_pipeline::Frame;
+use crate::window_selector::{self, WindowCandidate, WindowSelector};
+use crate::worker::Worker;
+use crate::world_model::{ResourceNode, WorldModel};
+use chrono::{Local, Utc};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// How an `ActionTicket` stands after polling it once.
+enum Polled {
+    Waiting(ActionTicket),
+    Done,
+    Failed(String),
+}
+
+fn poll(ticket: ActionTicket) -> Polled {
+    match ticket.try_outcome() {
+        None => Polled::Waiting(ticket),
+        Some(outcome) => match outcome.into_result() {
+            Ok(()) => Polled::Done,
+            Err(err) => Polled::Failed(err.to_string()),
+        },
+    }
+}
+
+/// How long Mission Proof waits for a frame from after Dofus came forward.
+const PROOF_TIMEOUT: Duration = Duration::from_secs(2);
+
+enum ProofStep {
+    Start,
+    Focusing(Worker, Receiver<Result<Frame, ExpectError>>),
+    Capture(Frame),
+    FocusBack,
+    FocusingBack(ActionTicket),
+}
+
+/// Brings Dofus forward, saves the first frame captured after that to `./mission_logs`
+/// and returns focus to the bot.
+pub struct MissionProof {
+    dofus_pid: i32,
+    bot_pid: i32,
+    verifier: Verifier,
+    actions: ActionQueue,
+    step: Option<ProofStep>,
+}
+
+impl MissionProof {
+    pub const NAME: &str = "Mission Proof";
+
+    pub fn new(dofus_pid: i32, verifier: Verifier, actions: ActionQueue) -> Self {
+        Self {
+            dofus_pid,
+            bot_pid: std::process::id() as i32,
+            verifier,
+            actions,
+            step: Some(ProofStep::Start),
+        }
+    }
+
+    fn save_frame(frame: &Frame) -> Result<String, String> {
+        let dir = "./mission_logs";
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+        let path = format!("{}/proof_{}.png", dir, Local::now().format("%Y%m%d_%H%M%S"));
+        frame.image.save(&path).map_err(|e| format!("Failed to save image: {}", e))?;
+        Ok(path)
+    }
+}
+
+impl Task for MissionProof {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn tick(&mut self, ctx: &TaskContext) -> Tick {
+        let Some(step) = self.step.take() else { return Tick::Done };
+        match step {
+            // 1. Focus Dofus and wait for a frame captured after it came forward
+            ProofStep::Start => {
+                ctx.progress(Some(0.0), "Focusing Dofus");
+                let (tx, rx) = mpsc::channel();
+                let (verifier, pid) = (self.verifier.clone(), self.dofus_pid);
+                let worker = Worker::spawn("mission-proof", move |token| {
+                    let focus = || Ok(ActionRequest::new("mission proof", vec![InputAction::Focus(pid)]));
+                    let _ = tx.send(verifier.act_and_expect(focus, &Expectation::NewFrame, PROOF_TIMEOUT, 1, &token));
+                });
+                self.step = Some(ProofStep::Focusing(worker, rx));
+            }
+            ProofStep::Focusing(worker, rx) => match rx.try_recv() {
+                Err(TryRecvError::Empty) => self.step = Some(ProofStep::Focusing(worker, rx)),
+                Ok(Ok(frame)) => self.step = Some(ProofStep::Capture(frame)),
+                Ok(Err(err)) => return Tick::Failed(err.to_string()),
+                Err(TryRecvError::Disconnected) => return Tick::Failed("focus check stopped".to_string()),
+            },
+
+            // 2. Save that frame
+            ProofStep::Capture(frame) => {
+                ctx.progress(Some(0.5), "Saving frame");
+                match Self::save_frame(&frame) {
+                    Ok(path) => ctx.log(&format!("Mission Proof saved: {}", path), LogLevel::Success),
+                    Err(err) => return Tick::Failed(err),
+                }
+                self.step = Some(ProofStep::FocusBack);
+            }
+
+            // 3. Focus back to the bot
+            ProofStep::FocusBack => {
+                ctx.progress(Some(0.75), "Focusing bot");
+                let back = ActionRequest::new("focus bot", vec![InputAction::Focus(self.bot_pid)]);
+                self.step = Some(ProofStep::FocusingBack(self.actions.submit(back)));
+            }
+            ProofStep::FocusingBack(ticket) => match poll(ticket) {
+                Polled::Waiting(ticket) => self.step = Some(ProofStep::FocusingBack(ticket)),
+                Polled::Done => return Tick::Done,
+                // The proof is saved; not getting focus back isn't worth failing over.
+                Polled::Failed(err) => {
+                    ctx.log(&err, LogLevel::Warning);
+                    return Tick::Done;
+                }
+            },
+        }
+        Tick::Continue
+    }
+
+    /// Takes back the focus request in flight; the step that sent it sends it again once
+    /// the task resumes, so the proof frame is still one from after Dofus came forward.
+    fn pause(&mut self, ctx: &TaskContext) {
+        self.cancel(ctx);
+        self.step = match self.step.take() {
+            Some(ProofStep::Focusing(..)) => Some(ProofStep::Start),
+            Some(ProofStep::FocusingBack(_)) => Some(ProofStep::FocusBack),
+            step => step,
+        };
+    }
+
+    fn cancel(&mut self, _ctx: &TaskContext) {
+        match &self.step {
+            // Cancelling the worker's token cancels its focus request too.
+            Some(ProofStep::Focusing(worker, _)) => worker.cancel(),
+            Some(ProofStep::FocusingBack(ticket)) => ticket.cancel(),
+            _ => {}
+        }
+    }
+}
+
+/// One focus request of `FocusTest`.
+#[derive(Clone, Copy)]
+struct FocusStep {
+    label: &'static str,
+    pid: i32,
+    /// From the start of the test, or from resuming it.
+    delay: Duration,
+    message: &'static str,
+}
+
+/// The focus dance used to check input works: Dofus after 5s, the bot 5s later.
+pub struct FocusTest {
+    dofus_pid: i32,
+    actions: ActionQueue,
+    /// Submitted steps, with when they're due.
+    pending: Vec<(FocusStep, Instant, ActionTicket)>,
+    /// Steps taken back by `pause`, with the delay they had left.
+    held: Vec<FocusStep>,
+    total: usize,
+}
+
+impl FocusTest {
+    pub const NAME: &str = "Focus test";
+
+    pub fn new(dofus_pid: i32, actions: &ActionQueue) -> Self {
+        Self {
+            dofus_pid,
+            actions: actions.clone(),
+            pending: Vec::new(),
+            held: Vec::new(),
+            total: 0,
+        }
+    }
+
+    fn submit(&mut self, steps: Vec<FocusStep>) {
+        for step in steps {
+            let request = ActionRequest::new(step.label, vec![InputAction::Focus(step.pid)]).after(step.delay);
+            self.pending.push((step, Instant::now() + step.delay, self.actions.submit(request)));
+        }
+    }
+}
+
+impl Task for FocusTest {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    /// Submits both focus requests only once the scheduler starts the task, so the
+    /// delays count from then rather than from when it was queued.
+    fn start(&mut self, ctx: &TaskContext) -> Result<(), String> {
+        let bot_pid = std::process::id() as i32;
+        self.submit(vec![
+            FocusStep {
+                label: "test: focus dofus",
+                pid: self.dofus_pid,
+                delay: Duration::from_secs(5),
+                message: "Test: Dofus focused. Focusing Bot in 5s...",
+            },
+            FocusStep {
+                label: "test: focus bot",
+                pid: bot_pid,
+                delay: Duration::from_secs(10),
+                message: "Test: Bot focused. Sequence complete.",
+            },
+        ]);
+        self.total = self.pending.len();
+        ctx.progress(Some(0.0), "Focusing Dofus in 5s...");
+        Ok(())
+    }
+
+    fn tick(&mut self, ctx: &TaskContext) -> Tick {
+        if self.pending.is_empty() {
+            return Tick::Done;
+        }
+        let (step, due, ticket) = self.pending.remove(0);
+        match poll(ticket) {
+            Polled::Waiting(ticket) => self.pending.insert(0, (step, due, ticket)),
+            Polled::Done => {
+                let done = self.total - self.pending.len();
+                ctx.progress(Some(done as f32 / self.total as f32), step.message);
+                ctx.log(step.message, LogLevel::Success);
+            }
+            Polled::Failed(err) => {
+                self.cancel(ctx);
+                return Tick::Failed(err);
+            }
+        }
+        Tick::Continue
+    }
+
+    /// Takes the requests back from the queue, keeping what was left of their delays.
+    fn pause(&mut self, _ctx: &TaskContext) {
+        let now = Instant::now();
+        for (step, due, ticket) in self.pending.drain(..) {
+            ticket.cancel();
+            self.held.push(FocusStep {
+                delay: due.saturating_duration_since(now),
+                ..step
+            });
+        }
+    }
+
+    fn resume(&mut self, _ctx: &TaskContext) {
+        let held = std::mem::take(&mut self.held);
+        self.submit(held);
+    }
+
+    fn cancel(&mut self, _ctx: &TaskContext) {
+        for (_, _, ticket) in &self.pending {
+            ticket.cancel();
+        }
+    }
+}
+
+/// Holds the foreground while `condition` holds, so lower-priority work stays paused
+/// until the user has dealt with the game: a fight the bot doesn't play, a whisper to
+/// answer, a full inventory to empty.
+pub struct HoldWhile {
+    name: String,
+    message: String,
+    state: watch::Receiver<GameState>,
+    condition: Condition,
+}
+
+impl HoldWhile {
+    pub fn new(name: &str, message: &str, state: watch::Receiver<GameState>, condition: Condition) -> Self {
+        Self {
+            name: name.to_string(),
+            message: message.to_string(),
+            state,
+            condition,
+        }
+    }
+}
+
+impl Task for HoldWhile {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn start(&mut self, ctx: &TaskContext) -> Result<(), String> {
+        ctx.log(&self.message, LogLevel::Warning);
+        ctx.progress(None, &self.message);
+        Ok(())
+    }
+
+    fn tick(&mut self, _ctx: &TaskContext) -> Tick {
+        if (self.condition)(&self.state.borrow()) {
+            Tick::Continue
+        } else {
+            Tick::Done
+        }
+    }
+}
+
+/// How long a clicked node gets to disappear before the harvest counts as failed.
+const HARVEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+enum HarvestStep {
+    Start(ActionRequest),
+    Clicking(ActionTicket),
+    Waiting(Instant),
+}
+
+/// Clicks a known resource node and waits for it to disappear, which the world model
+/// records as a harvest so it can learn when the node comes back.
+pub struct Harvest {
+    node: ResourceNode,
+    world: Arc<Mutex<WorldModel>>,
+    actions: ActionQueue,
+    step: Option<HarvestStep>,
+    /// When the node was clicked, in unix seconds.
+    clicked: i64,
+}
+
+impl Harvest {
+    pub const NAME: &str = "Harvest";
+
+    /// `click` is the request clicking the node on screen.
+    pub fn new(node: ResourceNode, click: ActionRequest, world: Arc<Mutex<WorldModel>>, actions: ActionQueue) -> Self {
+        Self {
+            node,
+            world,
+            actions,
+            step: Some(HarvestStep::Start(click)),
+            clicked: 0,
+        }
+    }
+}
+
+impl Task for Harvest {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn tick(&mut self, ctx: &TaskContext) -> Tick {
+        let Some(step) = self.step.take() else { return Tick::Done };
+        match step {
+            // 1. Skip nodes that haven't respawned yet, then click
+            HarvestStep::Start(click) => {
+                let now = Utc::now().timestamp();
+                let mut world = self.world.lock().unwrap();
+                let Some(node) = world.node(self.node.id) else {
+                    return Tick::Failed(format!("Unknown resource node {}", self.node.id));
+                };
+                if !world.is_available(node, now) {
+                    return Tick::Failed(match world.respawns_in(node, now) {
+                        Some(secs) => format!("{} #{} respawns in {}s", node.kind, node.id, secs),
+                        None => format!("{} #{} hasn't respawned yet", node.kind, node.id),
+                    });
+                }
+                world.expect_harvest(self.node.id, now);
+                self.clicked = now;
+                ctx.progress(Some(0.0), &format!("Clicking {} #{}", self.node.kind, self.node.id));
+                self.step = Some(HarvestStep::Clicking(self.actions.submit(click)));
+            }
+            HarvestStep::Clicking(ticket) => match poll(ticket) {
+                Polled::Waiting(ticket) => self.step = Some(HarvestStep::Clicking(ticket)),
+                Polled::Done => {
+                    ctx.progress(Some(0.5), "Waiting for the node to disappear");
+                    self.step = Some(HarvestStep::Waiting(Instant::now() + HARVEST_TIMEOUT));
+                }
+                Polled::Failed(err) => return Tick::Failed(err),
+            },
+
+            // 2. The world model notices the node is gone and records the harvest
+            HarvestStep::Waiting(deadline) => {
+                let harvested = self.world.lock().unwrap().node(self.node.id).and_then(|n| n.last_harvested);
+                if harvested.is_some_and(|at| at >= self.clicked) {
+                    ctx.log(&format!("Harvested {} #{}", self.node.kind, self.node.id), LogLevel::Success);
+                    return Tick::Done;
+                }
+                if Instant::now() >= deadline {
+                    return Tick::Failed(format!("{} #{} is still there", self.node.kind, self.node.id));
+                }
+                self.step = Some(HarvestStep::Waiting(deadline));
+            }
+        }
+        Tick::Continue
+    }
+
+    fn cancel(&mut self, _ctx: &TaskContext) {
+        if let Some(HarvestStep::Clicking(ticket)) = &self.step {
+            ticket.cancel();
+        }
+    }
+}
+
+/// Where a `WindowScan` leaves the windows it found.
+pub type ScanResult = Arc<Mutex<Option<Vec<WindowCandidate>>>>;
+
+/// Lists the windows matching a selector, off the UI thread. The result is left in
+/// `found` for the session manager to pick up.
+pub struct WindowScan {
+    selector: WindowSelector,
+    found: ScanResult,
+}
+
+impl WindowScan {
+    pub const NAME: &str = "Window scan";
+
+    pub fn new(selector: WindowSelector, found: ScanResult) -> Self {
+        Self { selector, found }
+    }
+}
+
+impl Task for WindowScan {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn tick(&mut self, ctx: &TaskContext) -> Tick {
+        ctx.progress(None, &format!("Scanning for {}", self.selector));
+        match window_selector::scan_windows(&self.selector) {
+            Ok(candidates) => {
+                *self.found.lock().unwrap() = Some(candidates);
+                Tick::Done
+            }
+            Err(err) => Tick::Failed(err),
+        }
+    }
+}
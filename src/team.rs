@@ -0,0 +1,753 @@
+use crate::action_queue::ActionQueue;
+use crate::bot_engine::{click_in_window, send_log, LogLevel, LogMessage};
+use crate::coords::{CoordTransform, ScreenPoint};
+use crate::game_state::GameState;
+use crate::input_backend::InputError;
+use crate::keymap::GameAction;
+use crate::session::{Session, SessionManager};
+use crate::vision_engine::{TargetWindow, WindowGeometry};
+use crate::worker::{join_until, CancelToken, Worker};
+use rdev::{Button, Event, EventType, Key};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// How often the coordinator re-checks the members' states.
+const TICK_INTERVAL: Duration = Duration::from_millis(200);
+/// Map and fight observations older than this are ignored.
+const FRESH: Duration = Duration::from_secs(2);
+
+/// A client taking part in the team. Implemented by live sessions and by replays.
+pub trait TeamMember: Send {
+    fn name(&self) -> &str;
+    fn state(&self) -> GameState;
+    /// Focuses the client and clicks at `pos`, given as a fraction of the window size.
+    fn click_relative(&self, pos: (f32, f32)) -> Result<(), InputError>;
+}
+
+/// A live session seen through the shared handles of its engine, so the coordinator
+/// thread doesn't need to own the `BotEngine`.
+pub struct EngineMember {
+    name: String,
+    state: watch::Receiver<GameState>,
+    target: Arc<Mutex<Option<TargetWindow>>>,
+    geometry: Arc<Mutex<Option<WindowGeometry>>>,
+    actions: ActionQueue,
+}
+
+impl EngineMember {
+    pub fn of(session: &Session) -> Self {
+        Self {
+            name: session.name.clone(),
+            state: session.engine.subscribe_state(),
+            target: Arc::clone(&session.engine.vision.target),
+            geometry: Arc::clone(&session.engine.vision.geometry),
+            actions: session.engine.actions.clone(),
+        }
+    }
+}
+
+impl TeamMember for EngineMember {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn state(&self) -> GameState {
+        self.state.borrow().clone()
+    }
+
+    fn click_relative(&self, pos: (f32, f32)) -> Result<(), InputError> {
+        click_in_window(&self.actions, &self.target, &self.geometry, pos)
+    }
+}
+
+/// Something the leader just did that the followers should mirror.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LeaderAction {
+    /// Clicked a map exit on map `from`; followers there click the same spot once the
+    /// leader has arrived.
+    ChangeMap { from: (i32, i32), exit: (f32, f32) },
+    /// Started or joined a fight by clicking `target`; followers click it once the leader is in.
+    JoinFight { target: (f32, f32) },
+    /// Pressed ready during placement.
+    ReadyUp,
+}
+
+#[derive(Debug)]
+pub enum TeamCommand {
+    LeaderActed(LeaderAction),
+    /// Combat turn order by character name, as read from the timeline.
+    SetTurnOrder(Vec<String>),
+    /// The character whose turn it is has passed.
+    TurnEnded,
+    /// A click for `member` to play on its next turn.
+    CombatClick { member: String, pos: (f32, f32) },
+}
+
+#[derive(Debug, Clone)]
+pub struct TeamConfig {
+    /// Ready button, relative to the window.
+    pub ready_button: (f32, f32),
+    /// Time for a click to show its effect (new map, fight joined) before it is retried.
+    pub confirm_timeout: Duration,
+    pub max_attempts: u32,
+    /// Orders that never become possible (leader never arrived, fight never started) are dropped after this.
+    pub order_timeout: Duration,
+    /// Gap between two followers readying up, so the server sees them in turn order.
+    pub ready_spacing: Duration,
+}
+
+impl Default for TeamConfig {
+    fn default() -> Self {
+        Self {
+            ready_button: (0.86, 0.93),
+            confirm_timeout: Duration::from_secs(5),
+            max_attempts: 3,
+            order_timeout: Duration::from_secs(60),
+            ready_spacing: Duration::from_millis(400),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum OrderKind {
+    FollowMap { from: (i32, i32), exit: (f32, f32) },
+    JoinFight { target: (f32, f32) },
+    ReadyUp,
+    CombatClick { pos: (f32, f32) },
+}
+
+#[derive(Debug, Clone)]
+struct Order {
+    member: String,
+    kind: OrderKind,
+    issued_at: Instant,
+    clicked_at: Option<Instant>,
+    attempts: u32,
+}
+
+/// Makes followers mirror the leader's map changes, fight joins and ready-ups.
+///
+/// Orders are only carried out once the leader's state shows the action took effect, and
+/// are confirmed from the follower's own state, retrying a click that didn't work. In
+/// combat, per-member clicks wait for that member's turn.
+pub struct Team {
+    leader: Box<dyn TeamMember>,
+    followers: Vec<Box<dyn TeamMember>>,
+    turn_order: Vec<String>,
+    turn: usize,
+    leader_in_combat: bool,
+    last_ready: Option<Instant>,
+    orders: Vec<Order>,
+    config: TeamConfig,
+    log_tx: Sender<LogMessage>,
+}
+
+impl Team {
+    pub fn new(leader: Box<dyn TeamMember>, followers: Vec<Box<dyn TeamMember>>, config: TeamConfig, log_tx: Sender<LogMessage>) -> Self {
+        let turn_order = std::iter::once(leader.name().to_string())
+            .chain(followers.iter().map(|f| f.name().to_string()))
+            .collect();
+        Self {
+            leader,
+            followers,
+            turn_order,
+            turn: 0,
+            leader_in_combat: false,
+            last_ready: None,
+            orders: Vec::new(),
+            config,
+            log_tx,
+        }
+    }
+
+    /// A team led by `leader` with every other open session following.
+    pub fn from_sessions(manager: &SessionManager, leader: &str, config: TeamConfig, log_tx: Sender<LogMessage>) -> Result<Self, String> {
+        let leader = manager
+            .get(leader)
+            .map(EngineMember::of)
+            .ok_or_else(|| format!("No session named '{}'.", leader))?;
+        let followers: Vec<Box<dyn TeamMember>> = manager
+            .sessions()
+            .iter()
+            .filter(|s| s.name != leader.name)
+            .map(|s| Box::new(EngineMember::of(s)) as Box<dyn TeamMember>)
+            .collect();
+        if followers.is_empty() {
+            return Err("A team needs at least one follower session.".to_string());
+        }
+        Ok(Self::new(Box::new(leader), followers, config, log_tx))
+    }
+
+    pub fn leader_name(&self) -> &str {
+        self.leader.name()
+    }
+
+    /// Whose turn it is in the current fight.
+    pub fn current_turn(&self) -> Option<&str> {
+        self.leader_in_combat
+            .then(|| self.turn_order.get(self.turn).map(String::as_str))
+            .flatten()
+    }
+
+    pub fn handle(&mut self, command: TeamCommand) {
+        match command {
+            TeamCommand::LeaderActed(action) => self.leader_acted(action),
+            TeamCommand::SetTurnOrder(order) => {
+                self.turn_order = order;
+                self.turn = 0;
+            }
+            TeamCommand::TurnEnded => {
+                if !self.turn_order.is_empty() {
+                    self.turn = (self.turn + 1) % self.turn_order.len();
+                }
+            }
+            TeamCommand::CombatClick { member, pos } => self.push(member, OrderKind::CombatClick { pos }),
+        }
+    }
+
+    pub fn leader_acted(&mut self, action: LeaderAction) {
+        let names: Vec<String> = self.followers_in_turn_order();
+        match action {
+            LeaderAction::ChangeMap { from, exit } => {
+                for name in names {
+                    self.push(name, OrderKind::FollowMap { from, exit });
+                }
+            }
+            LeaderAction::JoinFight { target } => {
+                for name in names {
+                    self.push(name, OrderKind::JoinFight { target });
+                }
+            }
+            LeaderAction::ReadyUp => {
+                for name in names {
+                    self.push(name, OrderKind::ReadyUp);
+                }
+            }
+        }
+    }
+
+    /// Carries out whatever orders have become possible. Each follower works through its
+    /// own orders in sequence; clicks are sent one at a time since each one steals focus.
+    pub fn tick(&mut self) {
+        let leader = self.leader.state();
+        self.track_combat(&leader);
+
+        let mut index = 0;
+        while index < self.orders.len() {
+            // Only the oldest order of each member is live.
+            let member = self.orders[index].member.clone();
+            if self.orders[..index].iter().any(|o| o.member == member) {
+                index += 1;
+                continue;
+            }
+            let Some(follower) = self.followers.iter().find(|f| f.name() == member) else {
+                self.orders.remove(index);
+                continue;
+            };
+            let state = follower.state();
+            let order = self.orders[index].clone();
+
+            // 1. Confirmed by the follower's state
+            if is_done(&order, &leader, &state) {
+                self.orders.remove(index);
+                continue;
+            }
+
+            // 2. Clicked but nothing happened yet
+            if let Some(clicked_at) = order.clicked_at {
+                if clicked_at.elapsed() < self.config.confirm_timeout {
+                    index += 1;
+                    continue;
+                }
+                if order.attempts >= self.config.max_attempts {
+                    self.log(&format!("{}: gave up on {:?} after {} attempts.", member, order.kind, order.attempts), LogLevel::Warning);
+                    self.orders.remove(index);
+                    continue;
+                }
+            }
+
+            // 3. Waiting for the leader, the fight or this member's turn
+            if !self.can_act(&order, &leader, &state) {
+                if order.issued_at.elapsed() >= self.config.order_timeout {
+                    self.log(&format!("{}: dropped {:?}, it never became possible.", member, order.kind), LogLevel::Warning);
+                    self.orders.remove(index);
+                } else {
+                    index += 1;
+                }
+                continue;
+            }
+
+            // 4. Click
+            let pos = match order.kind {
+                OrderKind::FollowMap { exit, .. } => exit,
+                OrderKind::JoinFight { target } => target,
+                OrderKind::ReadyUp => self.config.ready_button,
+                OrderKind::CombatClick { pos } => pos,
+            };
+            // A retryable failure counts as an attempt and is retried after the confirm
+            // timeout, like a click that had no effect.
+            if let Err(err) = follower.click_relative(pos) {
+                if !err.is_retryable() {
+                    self.log(&format!("{}: dropped {:?}: {}", member, order.kind, err), LogLevel::Error);
+                    self.orders.remove(index);
+                    continue;
+                }
+                self.log(&format!("{}: {}", member, err), LogLevel::Warning);
+            }
+            if matches!(order.kind, OrderKind::ReadyUp) {
+                self.last_ready = Some(Instant::now());
+            }
+            let order = &mut self.orders[index];
+            order.clicked_at = Some(Instant::now());
+            order.attempts += 1;
+            index += 1;
+        }
+    }
+
+    /// Runs the coordinator on its own thread until the command channel is closed.
+    pub fn spawn(mut self, commands: Receiver<TeamCommand>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            self.log(&format!("Team started, led by {}.", self.leader.name()), LogLevel::Info);
+            loop {
+                match commands.recv_timeout(TICK_INTERVAL) {
+                    Ok(command) => self.handle(command),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                self.tick();
+            }
+            self.log("Team stopped.", LogLevel::Info);
+        })
+    }
+
+    fn track_combat(&mut self, leader: &GameState) {
+        let in_combat = leader.in_combat();
+        if in_combat != self.leader_in_combat {
+            self.leader_in_combat = in_combat;
+            self.turn = 0;
+            if !in_combat {
+                // Combat clicks left over from the fight are meaningless now.
+                self.orders.retain(|o| !matches!(o.kind, OrderKind::CombatClick { .. }));
+            }
+        }
+    }
+
+    fn can_act(&self, order: &Order, leader: &GameState, state: &GameState) -> bool {
+        match order.kind {
+            OrderKind::FollowMap { from, .. } => {
+                let leader_moved = fresh_map(leader).is_some_and(|m| m != from);
+                leader_moved && fresh_map(state) == Some(from)
+            }
+            OrderKind::JoinFight { .. } => leader.in_combat(),
+            OrderKind::ReadyUp => {
+                // Ready up one member at a time, in turn order.
+                let first = self.followers_in_turn_order().into_iter().find(|name| {
+                    self.orders
+                        .iter()
+                        .any(|o| &o.member == name && matches!(o.kind, OrderKind::ReadyUp) && o.clicked_at.is_none())
+                });
+                first.as_deref() == Some(order.member.as_str())
+                    && self.last_ready.is_none_or(|t| t.elapsed() >= self.config.ready_spacing)
+            }
+            OrderKind::CombatClick { .. } => self.current_turn() == Some(order.member.as_str()),
+        }
+    }
+
+    fn followers_in_turn_order(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .turn_order
+            .iter()
+            .filter(|name| self.followers.iter().any(|f| f.name() == name.as_str()))
+            .cloned()
+            .collect();
+        for follower in &self.followers {
+            if !names.iter().any(|n| n == follower.name()) {
+                names.push(follower.name().to_string());
+            }
+        }
+        names
+    }
+
+    fn push(&mut self, member: String, kind: OrderKind) {
+        self.orders.push(Order {
+            member,
+            kind,
+            issued_at: Instant::now(),
+            clicked_at: None,
+            attempts: 0,
+        });
+    }
+
+    fn log(&self, message: &str, level: LogLevel) {
+        send_log(&self.log_tx, message, level);
+    }
+}
+
+fn fresh_map(state: &GameState) -> Option<(i32, i32)> {
+    state.map.as_ref().filter(|m| m.is_fresh(FRESH)).map(|m| m.value)
+}
+
+fn is_done(order: &Order, leader: &GameState, state: &GameState) -> bool {
+    match order.kind {
+        OrderKind::FollowMap { from, .. } => {
+            let target = fresh_map(leader).filter(|m| *m != from);
+            target.is_some() && fresh_map(state) == target
+        }
+        OrderKind::JoinFight { .. } => state.in_combat(),
+        OrderKind::ReadyUp | OrderKind::CombatClick { .. } => order.clicked_at.is_some(),
+    }
+}
+
+/// Turns what the leader does into team commands: the user's clicks in the leader's
+/// window say where, and the leader's state then shows what they did. A click followed
+/// by a new map is a map exit, one followed by a fight is a fight join.
+pub struct LeaderWatcher {
+    ready_button: (f32, f32),
+    last_click: Option<(f32, f32)>,
+    map: Option<(i32, i32)>,
+    in_combat: bool,
+}
+
+impl LeaderWatcher {
+    pub fn new(ready_button: (f32, f32)) -> Self {
+        Self {
+            ready_button,
+            last_click: None,
+            map: None,
+            in_combat: false,
+        }
+    }
+
+    /// The user clicked the leader's window at `pos`, as a fraction of its size. The
+    /// ready button during a fight is acted on at once.
+    pub fn clicked(&mut self, pos: (f32, f32)) -> Option<TeamCommand> {
+        let near = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).abs() < 0.03 && (a.1 - b.1).abs() < 0.03;
+        if self.in_combat && near(pos, self.ready_button) {
+            return Some(TeamCommand::LeaderActed(LeaderAction::ReadyUp));
+        }
+        self.last_click = Some(pos);
+        None
+    }
+
+    /// The end-turn key was pressed in one of the team's windows.
+    pub fn turn_ended(&self) -> Option<TeamCommand> {
+        self.in_combat.then_some(TeamCommand::TurnEnded)
+    }
+
+    /// Compares the leader's state with the last one seen.
+    pub fn observe(&mut self, leader: &GameState) -> Vec<TeamCommand> {
+        let mut commands = Vec::new();
+        let in_combat = leader.in_combat();
+
+        if let Some(map) = fresh_map(leader) {
+            if let Some(from) = self.map
+                && from != map
+                && !in_combat
+                && let Some(exit) = self.last_click.take()
+            {
+                commands.push(TeamCommand::LeaderActed(LeaderAction::ChangeMap { from, exit }));
+            }
+            self.map = Some(map);
+        }
+
+        if in_combat && !self.in_combat
+            && let Some(target) = self.last_click.take()
+        {
+            commands.push(TeamCommand::LeaderActed(LeaderAction::JoinFight { target }));
+        }
+        self.in_combat = in_combat;
+        commands
+    }
+}
+
+/// `p` as a fraction of the window, if it lies inside.
+fn relative_to(geometry: &Mutex<Option<WindowGeometry>>, p: ScreenPoint) -> Option<(f32, f32)> {
+    let window = (*geometry.lock().unwrap())?;
+    let transform = CoordTransform::new(window, window.capture_size());
+    let local = transform.screen_to_window(p);
+    transform
+        .contains(local)
+        .then(|| ((local.x / window.width) as f32, (local.y / window.height) as f32))
+}
+
+/// Feeds a `LeaderWatcher` from the user's input and the leader's state, sending what it
+/// sees to the coordinator. `windows` are the team's windows, the leader's first.
+fn watch_leader(
+    mut watcher: LeaderWatcher,
+    events: Receiver<Event>,
+    windows: Vec<Arc<Mutex<Option<WindowGeometry>>>>,
+    leader: watch::Receiver<GameState>,
+    end_turn: Option<Key>,
+    commands: Sender<TeamCommand>,
+    token: CancelToken,
+) {
+    let mut pointer = ScreenPoint { x: 0.0, y: 0.0 };
+    // Whether the last click landed in one of the team's windows, so keys typed
+    // elsewhere don't count.
+    let mut focused = false;
+    while !token.is_cancelled() {
+        let mut seen = Vec::new();
+        match events.recv_timeout(TICK_INTERVAL) {
+            Ok(event) => match event.event_type {
+                EventType::MouseMove { x, y } => pointer = ScreenPoint { x, y },
+                EventType::ButtonPress(Button::Left) => {
+                    focused = windows.iter().any(|w| relative_to(w, pointer).is_some());
+                    if let Some(pos) = relative_to(&windows[0], pointer) {
+                        seen.extend(watcher.clicked(pos));
+                    }
+                }
+                EventType::KeyPress(key) if focused && Some(key) == end_turn => seen.extend(watcher.turn_ended()),
+                _ => {}
+            },
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        seen.extend(watcher.observe(&leader.borrow()));
+        for command in seen {
+            if commands.send(command).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// A running team: the command channel to its coordinator thread, and the watcher
+/// turning the leader's actions into commands.
+pub struct TeamHandle {
+    pub leader: String,
+    /// Combat turn order as last sent to the coordinator.
+    pub turn_order: Vec<String>,
+    commands: Sender<TeamCommand>,
+    thread: JoinHandle<()>,
+    watcher: Worker,
+}
+
+impl TeamHandle {
+    /// Starts `team`, watching the user's input on the sessions of `manager`.
+    pub fn start(team: Team, manager: &SessionManager) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let leader = team.leader_name().to_string();
+        let turn_order = team.turn_order.clone();
+
+        let session = |name: &str| manager.get(name).expect("team member without a session");
+        let windows = turn_order.iter().map(|name| Arc::clone(&session(name).engine.vision.geometry)).collect();
+        let end_turn = manager.shared.keymap.combo(GameAction::EndTurn).map(|combo| combo.key);
+        let (watcher, events, state, commands) = (
+            LeaderWatcher::new(team.config.ready_button),
+            manager.shared.monitor.subscribe(),
+            session(&leader).engine.subscribe_state(),
+            tx.clone(),
+        );
+        let watcher = Worker::spawn("team-watcher", move |token| {
+            watch_leader(watcher, events, windows, state, end_turn, commands, token)
+        });
+
+        Self {
+            leader,
+            turn_order,
+            commands: tx,
+            thread: team.spawn(rx),
+            watcher,
+        }
+    }
+
+    pub fn send(&self, command: TeamCommand) {
+        let _ = self.commands.send(command);
+    }
+
+    /// Moves `name` one place earlier in the combat turn order.
+    pub fn move_up(&mut self, name: &str) {
+        if let Some(index) = self.turn_order.iter().position(|n| n == name)
+            && index > 0
+        {
+            self.turn_order.swap(index - 1, index);
+            self.send(TeamCommand::SetTurnOrder(self.turn_order.clone()));
+        }
+    }
+
+    /// Stops the watcher and closes the command channel without waiting; the coordinator
+    /// exits on its next tick. For the UI thread.
+    pub fn halt(self) {
+        self.watcher.cancel();
+        drop(self.commands);
+    }
+
+    /// Stops the watcher, closes the command channel and waits up to `timeout` for both
+    /// threads. Returns false, leaving them detached, if either is still running.
+    pub fn stop(mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        self.watcher.cancel();
+        drop(self.commands);
+        let coordinator = join_until(self.thread, deadline);
+        let watcher = self.watcher.stop(deadline.saturating_duration_since(Instant::now()));
+        coordinator && watcher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_state::Observed;
+    use crate::input_backend::RecordingBackend;
+    use crate::input_manager::InputManager;
+
+    fn observed<T>(value: T) -> Option<Observed<T>> {
+        Some(Observed {
+            value,
+            confidence: 1.0,
+            seen_at: Instant::now(),
+            frame_seq: 0,
+        })
+    }
+
+    fn state(map: (i32, i32), in_combat: bool) -> GameState {
+        GameState {
+            map: observed(map),
+            in_combat: observed(in_combat),
+            ..GameState::default()
+        }
+    }
+
+    /// Always shows `state`, and clicks on a `RecordingBackend` as if its window were
+    /// 100x100 at the origin.
+    struct Stub {
+        name: String,
+        state: GameState,
+        input: InputManager,
+    }
+
+    impl TeamMember for Stub {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn state(&self) -> GameState {
+            self.state.clone()
+        }
+
+        fn click_relative(&self, pos: (f32, f32)) -> Result<(), InputError> {
+            self.input.click_at(pos.0 as f64 * 100.0, pos.1 as f64 * 100.0)
+        }
+    }
+
+    fn stub(name: &str, state: GameState) -> (Box<dyn TeamMember>, Arc<RecordingBackend>) {
+        let backend = Arc::new(RecordingBackend::new());
+        let member = Stub {
+            name: name.to_string(),
+            state,
+            input: InputManager::with_backend(backend.clone()),
+        };
+        (Box::new(member), backend)
+    }
+
+    #[test]
+    fn watcher_turns_a_click_and_a_new_map_into_a_map_change() {
+        let mut watcher = LeaderWatcher::new((0.86, 0.93));
+        assert!(watcher.observe(&state((1, 1), false)).is_empty());
+        assert!(watcher.clicked((0.9, 0.5)).is_none());
+        assert!(watcher.observe(&state((1, 1), false)).is_empty());
+
+        let commands = watcher.observe(&state((2, 1), false));
+        assert!(matches!(
+            commands.as_slice(),
+            [TeamCommand::LeaderActed(LeaderAction::ChangeMap { from: (1, 1), exit: (0.9, 0.5) })]
+        ));
+        // The click was used up: a later map change without one isn't mirrored.
+        assert!(watcher.observe(&state((3, 1), false)).is_empty());
+    }
+
+    #[test]
+    fn watcher_sees_fight_joins_ready_and_turn_ends() {
+        let mut watcher = LeaderWatcher::new((0.86, 0.93));
+        watcher.observe(&state((1, 1), false));
+        assert!(watcher.turn_ended().is_none());
+
+        watcher.clicked((0.4, 0.4));
+        let commands = watcher.observe(&state((1, 1), true));
+        assert!(matches!(
+            commands.as_slice(),
+            [TeamCommand::LeaderActed(LeaderAction::JoinFight { target: (0.4, 0.4) })]
+        ));
+        assert!(matches!(watcher.clicked((0.86, 0.93)), Some(TeamCommand::LeaderActed(LeaderAction::ReadyUp))));
+        assert!(matches!(watcher.turn_ended(), Some(TeamCommand::TurnEnded)));
+    }
+
+    #[test]
+    fn combat_clicks_wait_for_the_members_turn() {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let (leader, _) = stub("leader", state((1, 1), true));
+        let (a, a_input) = stub("a", state((1, 1), true));
+        let (b, b_input) = stub("b", state((1, 1), true));
+        let mut team = Team::new(leader, vec![a, b], TeamConfig::default(), log_tx);
+
+        team.handle(TeamCommand::SetTurnOrder(vec!["leader".into(), "b".into(), "a".into()]));
+        team.handle(TeamCommand::CombatClick { member: "a".into(), pos: (0.25, 0.25) });
+        team.handle(TeamCommand::CombatClick { member: "b".into(), pos: (0.5, 0.5) });
+        team.tick();
+        assert_eq!(team.current_turn(), Some("leader"));
+        assert!(a_input.clicks().is_empty() && b_input.clicks().is_empty());
+
+        team.handle(TeamCommand::TurnEnded);
+        team.tick();
+        assert_eq!(b_input.clicks(), vec![(50.0, 50.0)]);
+        assert!(a_input.clicks().is_empty());
+
+        team.handle(TeamCommand::TurnEnded);
+        team.tick();
+        assert_eq!(a_input.clicks(), vec![(25.0, 25.0)]);
+    }
+
+    fn fight_team(config: TeamConfig) -> (Team, Arc<RecordingBackend>) {
+        let (log_tx, _log_rx) = mpsc::channel();
+        let (leader, _) = stub("leader", state((1, 1), true));
+        let (follower, input) = stub("follower", state((1, 1), false));
+        let mut team = Team::new(leader, vec![follower], config, log_tx);
+        team.handle(TeamCommand::LeaderActed(LeaderAction::JoinFight { target: (0.5, 0.25) }));
+        (team, input)
+    }
+
+    #[test]
+    fn retryable_click_failure_is_tried_again() {
+        let (mut team, input) = fight_team(TeamConfig {
+            confirm_timeout: Duration::ZERO,
+            ..TeamConfig::default()
+        });
+        input.fail_next(InputError::Backend("event tap dropped".into()));
+        team.tick();
+        assert!(input.clicks().is_empty());
+
+        team.tick();
+        assert_eq!(input.clicks(), vec![(50.0, 25.0)]);
+    }
+
+    #[test]
+    fn retries_stop_after_max_attempts() {
+        let (mut team, input) = fight_team(TeamConfig {
+            confirm_timeout: Duration::ZERO,
+            max_attempts: 2,
+            ..TeamConfig::default()
+        });
+        input.fail_next(InputError::FocusFailed { pid: 7, frontmost: None });
+        input.fail_next(InputError::FocusFailed { pid: 7, frontmost: None });
+        for _ in 0..4 {
+            team.tick();
+        }
+        // A third attempt would have gone through.
+        assert!(input.clicks().is_empty());
+    }
+
+    #[test]
+    fn permanent_click_failure_drops_the_order() {
+        let (mut team, input) = fight_team(TeamConfig {
+            confirm_timeout: Duration::ZERO,
+            ..TeamConfig::default()
+        });
+        input.fail_next(InputError::PermissionDenied);
+        team.tick();
+        team.tick();
+        assert!(input.clicks().is_empty());
+    }
+}
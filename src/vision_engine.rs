@@ -1,53 +1,231 @@
 use screencapturekit::{
     cm_sample_buffer::CMSampleBuffer,
     content_filter::{InitParams, SCContentFilter},
-    shareable_content::SCShareableContent,
+    shareable_content::{SCShareableContent, SCWindow},
     stream::{SCStream, SCStreamConfiguration, SCStreamOutput, SCStreamOutputType},
 };
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 use image::RgbaImage;
+use crate::coords::CoordTransform;
+use crate::stream_watchdog::{self, StreamHealth, StreamWatchdog, WatchdogShared};
+use crate::vision_pipeline::Frame;
+use crate::window_selector::{parse_character_name, WindowCandidate};
+
+/// Window frame in global screen points, as reported by ScreenCaptureKit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl WindowGeometry {
+    pub fn of(window: &SCWindow) -> Self {
+        let frame = window.frame();
+        Self {
+            x: frame.origin.x,
+            y: frame.origin.y,
+            width: frame.size.width,
+            height: frame.size.height,
+        }
+    }
+
+    /// Stream dimensions for this geometry.
+    pub fn capture_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+}
+
+/// The window being captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetWindow {
+    pub window_id: u32,
+    pub pid: i32,
+    pub title: String,
+    pub character: Option<String>,
+}
+
+impl TargetWindow {
+    pub fn of(window: &SCWindow) -> Self {
+        let title = window.title();
+        Self {
+            window_id: window.window_id(),
+            pid: window.owning_application().process_id(),
+            character: parse_character_name(&title),
+            title,
+        }
+    }
+
+    /// Whether `window` is this client come back under a new window id: same character,
+    /// same title, or another Dofus window of the same process. Any other Dofus window
+    /// could belong to a different account, so it doesn't count.
+    pub fn matches_replacement(&self, window: &SCWindow) -> bool {
+        let title = window.title();
+        if self.character.is_some() && parse_character_name(&title) == self.character {
+            return true;
+        }
+        title == self.title || (window.owning_application().process_id() == self.pid && title.contains("Dofus"))
+    }
+}
+
+impl From<&WindowCandidate> for TargetWindow {
+    fn from(candidate: &WindowCandidate) -> Self {
+        Self {
+            window_id: candidate.window_id,
+            pid: candidate.pid,
+            title: candidate.title.clone(),
+            character: candidate.character.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum VisionEvent {
+    /// The target window moved or was resized. The stream has already been reconfigured
+    /// when this is sent.
+    WindowGeometryChanged { old: WindowGeometry, new: WindowGeometry },
+    HealthChanged { old: StreamHealth, new: StreamHealth, reason: String },
+    /// The client came back under a new window id and the stream now follows it.
+    TargetReplaced { old: TargetWindow, new: TargetWindow },
+}
 
 pub struct VisionEngine {
-    pub target_window_name: String,
-    pub target_window_pid: Option<i32>,
-    pub target_window_id: Option<u32>,
-    pub window_resolution: String,
+    pub target: Arc<Mutex<Option<TargetWindow>>>,
     pub latest_frame: Arc<Mutex<Vec<u8>>>,
     pub frame_size: Arc<Mutex<(u32, u32)>>,
-    pub stream: Option<SCStream>,
+    pub frame_seq: Arc<AtomicU64>,
+    pub geometry: Arc<Mutex<Option<WindowGeometry>>>,
+    pub health: Arc<Mutex<StreamHealth>>,
+    last_frame_at: Arc<Mutex<Option<Instant>>>,
+    frame_subscribers: Arc<Mutex<Vec<Sender<Frame>>>>,
+    event_subscribers: Arc<Mutex<Vec<Sender<VisionEvent>>>>,
+    stream: Arc<Mutex<Option<SCStream>>>,
+    watchdog: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+}
+
+#[derive(Clone)]
+pub struct StreamHandler {
+    latest_frame: Arc<Mutex<Vec<u8>>>,
+    frame_size: Arc<Mutex<(u32, u32)>>,
+    last_frame_at: Arc<Mutex<Option<Instant>>>,
+    frame_seq: Arc<AtomicU64>,
+    frame_subscribers: Arc<Mutex<Vec<Sender<Frame>>>>,
 }
 
-struct StreamHandler {
+/// The latest frame and new-frame subscriptions of a `VisionEngine`, for threads that
+/// need nothing else from it. Cheap to clone.
+#[derive(Clone)]
+pub struct FrameFeed {
     latest_frame: Arc<Mutex<Vec<u8>>>,
     frame_size: Arc<Mutex<(u32, u32)>>,
+    frame_seq: Arc<AtomicU64>,
+    frame_subscribers: Arc<Mutex<Vec<Sender<Frame>>>>,
+}
+
+impl FrameFeed {
+    /// Sequence number of the latest frame.
+    pub fn seq(&self) -> u64 {
+        self.frame_seq.load(Ordering::SeqCst)
+    }
+
+    /// Latest frame together with its sequence number.
+    pub fn latest(&self) -> Option<Frame> {
+        let seq = self.seq();
+        let data = self.latest_frame.lock().ok()?.clone();
+        let size = *self.frame_size.lock().ok()?;
+        if data.is_empty() || size.0 == 0 || size.1 == 0 {
+            return None;
+        }
+        RgbaImage::from_raw(size.0, size.1, data).map(|img| Frame::new(seq, img))
+    }
+
+    /// Returns a channel that receives every new frame as it is captured.
+    pub fn subscribe(&self) -> Receiver<Frame> {
+        let (tx, rx) = mpsc::channel();
+        if let Ok(mut subscribers) = self.frame_subscribers.lock() {
+            subscribers.push(tx);
+        }
+        rx
+    }
+}
+
+#[cfg(test)]
+impl FrameFeed {
+    /// A feed with no capture behind it, fed by `publish`.
+    pub fn detached() -> Self {
+        Self {
+            latest_frame: Arc::new(Mutex::new(Vec::new())),
+            frame_size: Arc::new(Mutex::new((0, 0))),
+            frame_seq: Arc::new(AtomicU64::new(0)),
+            frame_subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Makes `image` the latest frame and sends it to the subscribers, as a capture would.
+    pub fn publish(&self, image: RgbaImage) {
+        let seq = self.seq() + 1;
+        let frame = Frame::new(seq, image);
+        self.frame_subscribers.lock().unwrap().retain(|tx| tx.send(frame.clone()).is_ok());
+        *self.latest_frame.lock().unwrap() = frame.image.as_raw().clone();
+        *self.frame_size.lock().unwrap() = frame.image.dimensions();
+        self.frame_seq.store(seq, Ordering::SeqCst);
+    }
+}
+
+impl StreamHandler {
+    /// Hands the new frame to every frame subscriber. Nothing heavier runs here, so the
+    /// capture thread is never held up.
+    fn publish_frame(&self, seq: u64, width: u32, height: u32, rgba: &[u8]) {
+        let Ok(mut subscribers) = self.frame_subscribers.lock() else { return };
+        if subscribers.is_empty() {
+            return;
+        }
+        if let Some(img) = RgbaImage::from_raw(width, height, rgba.to_vec()) {
+            let frame = Frame::new(seq, img);
+            subscribers.retain(|tx| tx.send(frame.clone()).is_ok());
+        }
+    }
 }
 
 impl SCStreamOutput for StreamHandler {
     fn did_output_sample_buffer(&self, sample_buffer: CMSampleBuffer, of_type: SCStreamOutputType) {
-        if let SCStreamOutputType::Screen = of_type {
-            if let Ok(pixel_buffer) = sample_buffer.image_buffer() {
-                let width = pixel_buffer.width() as u32;
-                let height = pixel_buffer.height() as u32;
-
-                let data = pixel_buffer.as_slice();
-                if data.is_empty() { return; }
-
-                // High-performance BGRA to RGBA conversion
-                let mut rgba = Vec::with_capacity(data.len());
-                for bgra in data.chunks_exact(4) {
-                    rgba.push(bgra[2]); // R
-                    rgba.push(bgra[1]); // G
-                    rgba.push(bgra[0]); // B
-                    rgba.push(bgra[3]); // A
-                }
-
-                if let Ok(mut latest) = self.latest_frame.lock() {
-                    *latest = rgba;
-                }
-                if let Ok(mut size) = self.frame_size.lock() {
-                    *size = (width, height);
-                }
+        if let SCStreamOutputType::Screen = of_type
+            && let Ok(pixel_buffer) = sample_buffer.image_buffer()
+        {
+            let width = pixel_buffer.width() as u32;
+            let height = pixel_buffer.height() as u32;
+
+            let data = pixel_buffer.as_slice();
+            if data.is_empty() { return; }
+
+            // High-performance BGRA to RGBA conversion
+            let mut rgba = Vec::with_capacity(data.len());
+            for bgra in data.chunks_exact(4) {
+                rgba.push(bgra[2]); // R
+                rgba.push(bgra[1]); // G
+                rgba.push(bgra[0]); // B
+                rgba.push(bgra[3]); // A
+            }
+
+            let seq = self.frame_seq.load(Ordering::SeqCst) + 1;
+            self.publish_frame(seq, width, height, &rgba);
+
+            if let Ok(mut latest) = self.latest_frame.lock() {
+                *latest = rgba;
+            }
+            if let Ok(mut size) = self.frame_size.lock() {
+                *size = (width, height);
             }
+            if let Ok(mut at) = self.last_frame_at.lock() {
+                *at = Some(Instant::now());
+            }
+            // Publish the sequence number last so it never runs ahead of the pixels.
+            self.frame_seq.store(seq, Ordering::SeqCst);
         }
     }
 }
@@ -55,69 +233,128 @@ impl SCStreamOutput for StreamHandler {
 impl VisionEngine {
     pub fn new() -> Self {
         Self {
-            target_window_name: "Not Scanned".to_owned(),
-            target_window_pid: None,
-            target_window_id: None,
-            window_resolution: "0x0".to_owned(),
+            target: Arc::new(Mutex::new(None)),
             latest_frame: Arc::new(Mutex::new(Vec::new())),
             frame_size: Arc::new(Mutex::new((0, 0))),
-            stream: None,
+            frame_seq: Arc::new(AtomicU64::new(0)),
+            geometry: Arc::new(Mutex::new(None)),
+            health: Arc::new(Mutex::new(StreamHealth::NoTarget)),
+            last_frame_at: Arc::new(Mutex::new(None)),
+            frame_subscribers: Arc::new(Mutex::new(Vec::new())),
+            event_subscribers: Arc::new(Mutex::new(Vec::new())),
+            stream: Arc::new(Mutex::new(None)),
+            watchdog: None,
+        }
+    }
+
+    fn handler(&self) -> StreamHandler {
+        StreamHandler {
+            latest_frame: Arc::clone(&self.latest_frame),
+            frame_size: Arc::clone(&self.frame_size),
+            last_frame_at: Arc::clone(&self.last_frame_at),
+            frame_seq: Arc::clone(&self.frame_seq),
+            frame_subscribers: Arc::clone(&self.frame_subscribers),
         }
     }
 
-    pub fn find_dofus_window(&mut self) -> Result<String, String> {
-        if let Ok(content) = SCShareableContent::get() {
-            let dofus_window = content.windows().into_iter().find(|w| {
-                w.title().contains("Dofus")
-            });
+    pub fn frame_feed(&self) -> FrameFeed {
+        FrameFeed {
+            latest_frame: Arc::clone(&self.latest_frame),
+            frame_size: Arc::clone(&self.frame_size),
+            frame_seq: Arc::clone(&self.frame_seq),
+            frame_subscribers: Arc::clone(&self.frame_subscribers),
+        }
+    }
 
-            if let Some(window) = dofus_window {
-                self.target_window_name = window.title().to_string();
-                self.target_window_pid = Some(window.owning_application().process_id());
-                self.target_window_id = Some(window.window_id());
-                self.window_resolution = format!("{}x{}", window.width(), window.height());
+    pub fn health(&self) -> StreamHealth {
+        self.health.lock().unwrap().clone()
+    }
 
-                let window_id = window.window_id();
-                let w = window.width() as u32;
-                let h = window.height() as u32;
+    pub fn target_window(&self) -> Option<TargetWindow> {
+        self.target.lock().unwrap().clone()
+    }
 
-                self.start_streaming(window_id, w, h);
+    pub fn target_window_pid(&self) -> Option<i32> {
+        self.target_window().map(|t| t.pid)
+    }
 
-                Ok(format!("Found window: {} (PID: {})", self.target_window_name, self.target_window_pid.unwrap()))
-            } else {
-                self.target_window_name = "Dofus not found".to_string();
-                self.target_window_pid = None;
-                self.target_window_id = None;
-                Err("Dofus window not found.".to_string())
-            }
-        } else {
-            Err("Failed to get shareable content.".to_string())
+    pub fn target_window_name(&self) -> String {
+        match self.target_window() {
+            Some(t) => t.title,
+            None => "Not Scanned".to_owned(),
         }
     }
 
-    pub fn start_streaming(&mut self, window_id: u32, width: u32, height: u32) {
-        if let Some(mut s) = self.stream.take() {
-            let _ = s.stop_capture();
+    /// Size of the target window in points, e.g. `1280x800`.
+    pub fn window_resolution(&self) -> String {
+        match *self.geometry.lock().unwrap() {
+            Some(g) => format!("{}x{}", g.width, g.height),
+            None => "0x0".to_owned(),
         }
+    }
 
-        if let Ok(content) = SCShareableContent::get() {
-            if let Some(window) = content.windows().into_iter().find(|w| w.window_id() == window_id) {
-                let filter = SCContentFilter::new(InitParams::Window(window));
-                let mut config = SCStreamConfiguration::default();
-                config.width = width;
-                config.height = height;
-                config.shows_cursor = false;
+    /// Makes `candidate` the capture target and starts streaming it.
+    pub fn select_window(&mut self, candidate: &WindowCandidate) -> Result<String, String> {
+        let target = TargetWindow::from(candidate);
+        let msg = format!("Selected window: {} (PID: {})", target.title, target.pid);
+        *self.target.lock().unwrap() = Some(target);
+        *self.geometry.lock().unwrap() = Some(candidate.geometry);
+        self.start_streaming()?;
+        Ok(msg)
+    }
 
-                let handler = StreamHandler {
-                    latest_frame: Arc::clone(&self.latest_frame),
-                    frame_size: Arc::clone(&self.frame_size),
-                };
+    /// Starts capturing the target window, along with the watchdog that keeps the stream
+    /// alive and sized to the window.
+    pub fn start_streaming(&mut self) -> Result<(), String> {
+        self.stop_streaming();
 
-                let mut stream = SCStream::new(filter, config, handler);
-                let _ = stream.start_capture();
-                self.stream = Some(stream);
-            }
+        let target = self.target_window().ok_or("No target window.")?;
+        let geometry = self.geometry.lock().unwrap().ok_or("Unknown window size.")?;
+        let (w, h) = geometry.capture_size();
+
+        // Even if the first attempt fails, the watchdog below keeps retrying.
+        let result = open_stream(target.window_id, w, h, self.handler())
+            .map(|stream| *self.stream.lock().unwrap() = Some(stream));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let watchdog = StreamWatchdog::new(WatchdogShared {
+            stop: Arc::clone(&stop),
+            handler: self.handler(),
+            stream: Arc::clone(&self.stream),
+            target: Arc::clone(&self.target),
+            geometry: Arc::clone(&self.geometry),
+            health: Arc::clone(&self.health),
+            last_frame_at: Arc::clone(&self.last_frame_at),
+            frame_seq: Arc::clone(&self.frame_seq),
+            event_subscribers: Arc::clone(&self.event_subscribers),
+        });
+        let handle = thread::Builder::new()
+            .name("stream-watchdog".to_string())
+            .spawn(move || watchdog.run())
+            .expect("failed to spawn stream watchdog");
+        self.watchdog = Some((stop, handle));
+
+        result
+    }
+
+    pub fn stop_streaming(&mut self) {
+        if let Some((stop, handle)) = self.watchdog.take() {
+            stop.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+        }
+        if let Some(mut s) = self.stream.lock().unwrap().take() {
+            let _ = s.stop_capture();
+        }
+        stream_watchdog::set_health(&self.health, &self.event_subscribers, StreamHealth::NoTarget, "capture stopped");
+    }
+
+    /// Returns a channel that receives stream and window events.
+    pub fn subscribe_events(&self) -> Receiver<VisionEvent> {
+        let (tx, rx) = mpsc::channel();
+        if let Ok(mut subscribers) = self.event_subscribers.lock() {
+            subscribers.push(tx);
         }
+        rx
     }
 
     pub fn capture_frame(&self) -> Option<RgbaImage> {
@@ -130,12 +367,54 @@ impl VisionEngine {
 
         RgbaImage::from_raw(size.0, size.1, data)
     }
+
+    /// Mapping between the current frames and the window on screen.
+    pub fn transform(&self) -> Option<CoordTransform> {
+        let geometry = (*self.geometry.lock().ok()?)?;
+        let size = *self.frame_size.lock().ok()?;
+        let size = if size.0 == 0 || size.1 == 0 { geometry.capture_size() } else { size };
+        Some(CoordTransform::new(geometry, size))
+    }
+
+    /// Returns a channel that receives every new frame as it is captured.
+    pub fn subscribe_frames(&self) -> Receiver<Frame> {
+        self.frame_feed().subscribe()
+    }
 }
 
 impl Drop for VisionEngine {
     fn drop(&mut self) {
-        if let Some(mut s) = self.stream.take() {
-            let _ = s.stop_capture();
-        }
+        self.stop_streaming();
     }
 }
+
+/// Creates and starts a capture stream for the window.
+pub fn open_stream(window_id: u32, width: u32, height: u32, handler: StreamHandler) -> Result<SCStream, String> {
+    let content = SCShareableContent::get().map_err(|_| "Failed to get shareable content.".to_string())?;
+    let window = content
+        .windows()
+        .into_iter()
+        .find(|w| w.window_id() == window_id)
+        .ok_or_else(|| format!("Window {} no longer exists.", window_id))?;
+
+    let filter = SCContentFilter::new(InitParams::Window(window));
+    let config = SCStreamConfiguration {
+        width,
+        height,
+        shows_cursor: false,
+        ..Default::default()
+    };
+
+    let mut stream = SCStream::new(filter, config, handler);
+    stream
+        .start_capture()
+        .map_err(|e| format!("Failed to start capture: {:?}", e))?;
+    Ok(stream)
+}
+
+/// First window accepted by `predicate`.
+pub fn find_window(predicate: impl Fn(&SCWindow) -> bool) -> Option<(TargetWindow, WindowGeometry)> {
+    let content = SCShareableContent::get().ok()?;
+    let window = content.windows().into_iter().find(|w| predicate(w))?;
+    Some((TargetWindow::of(&window), WindowGeometry::of(&window)))
+}
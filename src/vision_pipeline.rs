@@ -0,0 +1,752 @@
+use image::{imageops, GrayImage, Luma, RgbaImage};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A captured frame tagged with the sequence number assigned by the capture stream.
+#[derive(Clone)]
+pub struct Frame {
+    pub seq: u64,
+    pub image: Arc<RgbaImage>,
+    pub captured_at: Instant,
+}
+
+impl Frame {
+    pub fn new(seq: u64, image: RgbaImage) -> Self {
+        Self {
+            seq,
+            image: Arc::new(image),
+            captured_at: Instant::now(),
+        }
+    }
+}
+
+/// Rectangle in frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Clamps the rectangle so it fits inside an image of the given size.
+    pub fn clamp_to(&self, width: u32, height: u32) -> Roi {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Roi {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+
+    pub fn center(&self) -> (u32, u32) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+}
+
+/// Connected blob of mask pixels, with its bounding box in frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Component {
+    pub bounds: Roi,
+    pub area: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateMatch {
+    pub template: String,
+    pub bounds: Roi,
+    pub score: f32,
+}
+
+/// Intermediate results handed from one stage to the next.
+#[derive(Clone, Default)]
+pub struct StageData {
+    /// Region of the frame the current image/mask covers. `None` means the whole frame.
+    pub roi: Option<Roi>,
+    pub image: Option<Arc<RgbaImage>>,
+    pub mask: Option<GrayImage>,
+    pub components: Vec<Component>,
+    pub matches: Vec<TemplateMatch>,
+    pub text: Option<String>,
+    /// How sure the last scoring stage was about its result, in `0.0..=1.0`.
+    pub confidence: Option<f32>,
+}
+
+impl StageData {
+    /// Offset of the working image inside the frame.
+    pub fn origin(&self) -> (u32, u32) {
+        self.roi.map(|r| (r.x, r.y)).unwrap_or((0, 0))
+    }
+}
+
+pub trait Stage: Send {
+    fn name(&self) -> &str;
+    fn run(&self, frame: &Frame, data: StageData) -> Result<StageData, String>;
+}
+
+#[derive(Debug, Clone)]
+pub struct StageTiming {
+    pub stage: String,
+    pub elapsed: Duration,
+}
+
+/// Outcome of running a pipeline on one frame.
+#[derive(Clone)]
+pub struct PipelineRun {
+    pub seq: u64,
+    pub output: StageData,
+    pub timings: Vec<StageTiming>,
+    pub error: Option<String>,
+}
+
+impl PipelineRun {
+    pub fn total_time(&self) -> Duration {
+        self.timings.iter().map(|t| t.elapsed).sum()
+    }
+
+    /// One-line summary such as `crop 0.1ms > threshold 0.8ms (total 0.9ms)`.
+    pub fn timing_summary(&self) -> String {
+        let stages: Vec<String> = self
+            .timings
+            .iter()
+            .map(|t| format!("{} {:.1}ms", t.stage, t.elapsed.as_secs_f64() * 1000.0))
+            .collect();
+        format!(
+            "{} (total {:.1}ms)",
+            stages.join(" > "),
+            self.total_time().as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Chain of stages run in order on a frame.
+///
+/// The result of the last run is cached by frame sequence number, so asking for the same
+/// frame twice (e.g. from two detectors) only does the work once.
+pub struct VisionPipeline {
+    pub name: String,
+    stages: Vec<Box<dyn Stage>>,
+    last_run: Option<PipelineRun>,
+}
+
+impl VisionPipeline {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            stages: Vec::new(),
+            last_run: None,
+        }
+    }
+
+    pub fn stage(mut self, stage: impl Stage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn run(&mut self, frame: &Frame) -> &PipelineRun {
+        let cached = matches!(&self.last_run, Some(run) if run.seq == frame.seq);
+        if !cached {
+            self.last_run = Some(self.run_uncached(frame));
+        }
+        self.last_run.as_ref().unwrap()
+    }
+
+    /// Runs every stage without touching the cache. Handy for static fixtures.
+    pub fn run_uncached(&self, frame: &Frame) -> PipelineRun {
+        let mut data = StageData {
+            image: Some(Arc::clone(&frame.image)),
+            ..Default::default()
+        };
+        let mut timings = Vec::with_capacity(self.stages.len());
+        let mut error = None;
+
+        for stage in &self.stages {
+            let started = Instant::now();
+            let result = stage.run(frame, data.clone());
+            timings.push(StageTiming {
+                stage: stage.name().to_string(),
+                elapsed: started.elapsed(),
+            });
+
+            match result {
+                Ok(next) => data = next,
+                Err(e) => {
+                    error = Some(format!("{}: {}", stage.name(), e));
+                    break;
+                }
+            }
+        }
+
+        PipelineRun {
+            seq: frame.seq,
+            output: data,
+            timings,
+            error,
+        }
+    }
+
+    /// Convenience wrapper for running against a single `RgbaImage`.
+    #[cfg(test)]
+    pub fn run_image(&self, image: RgbaImage) -> PipelineRun {
+        self.run_uncached(&Frame::new(0, image))
+    }
+
+    pub fn last_run(&self) -> Option<&PipelineRun> {
+        self.last_run.as_ref()
+    }
+}
+
+/// Named templates loaded from PNG files, shared between pipelines.
+#[derive(Default)]
+pub struct TemplateLibrary {
+    templates: HashMap<String, Arc<RgbaImage>>,
+}
+
+impl TemplateLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every `*.png` in `dir`, keyed by file stem.
+    pub fn load_dir(dir: &Path) -> Result<Self, String> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read template folder {}: {}", dir.display(), e))?;
+
+        let mut library = Self::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("png") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let img = image::open(&path)
+                .map_err(|e| format!("Failed to load template {}: {}", path.display(), e))?;
+            library.insert(name, img.to_rgba8());
+        }
+        Ok(library)
+    }
+
+    pub fn insert(&mut self, name: &str, template: RgbaImage) {
+        self.templates.insert(name.to_string(), Arc::new(template));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<RgbaImage>> {
+        self.templates.get(name).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<RgbaImage>)> {
+        self.templates.iter().map(|(name, img)| (name.as_str(), img))
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+}
+
+/// Restricts the working image to a region of the frame.
+pub struct CropRoi(pub Roi);
+
+impl Stage for CropRoi {
+    fn name(&self) -> &str {
+        "crop"
+    }
+
+    fn run(&self, frame: &Frame, mut data: StageData) -> Result<StageData, String> {
+        let roi = self.0.clamp_to(frame.image.width(), frame.image.height());
+        if roi.width == 0 || roi.height == 0 {
+            return Err(format!("ROI {:?} is outside the frame", self.0));
+        }
+        let cropped = imageops::crop_imm(frame.image.as_ref(), roi.x, roi.y, roi.width, roi.height).to_image();
+        data.roi = Some(roi);
+        data.image = Some(Arc::new(cropped));
+        Ok(data)
+    }
+}
+
+/// Like [`CropRoi`], but with the region given as fractions of the frame size, so the
+/// same pipeline works whatever the window resolution.
+pub struct CropRelative {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl CropRelative {
+    pub fn to_roi(&self, frame_width: u32, frame_height: u32) -> Roi {
+        let fw = frame_width as f32;
+        let fh = frame_height as f32;
+        Roi::new(
+            (self.x * fw) as u32,
+            (self.y * fh) as u32,
+            (self.width * fw).ceil() as u32,
+            (self.height * fh).ceil() as u32,
+        )
+    }
+}
+
+impl Stage for CropRelative {
+    fn name(&self) -> &str {
+        "crop"
+    }
+
+    fn run(&self, frame: &Frame, data: StageData) -> Result<StageData, String> {
+        CropRoi(self.to_roi(frame.image.width(), frame.image.height())).run(frame, data)
+    }
+}
+
+/// Builds a mask of the pixels whose RGB values fall inside `[min, max]`.
+pub struct ColorThreshold {
+    pub min: [u8; 3],
+    pub max: [u8; 3],
+}
+
+impl Stage for ColorThreshold {
+    fn name(&self) -> &str {
+        "threshold"
+    }
+
+    fn run(&self, _frame: &Frame, mut data: StageData) -> Result<StageData, String> {
+        let image = data.image.as_ref().ok_or("no image to threshold")?;
+        let mask = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            let p = image.get_pixel(x, y).0;
+            let inside = (0..3).all(|c| p[c] >= self.min[c] && p[c] <= self.max[c]);
+            Luma([if inside { 255 } else { 0 }])
+        });
+        data.mask = Some(mask);
+        Ok(data)
+    }
+}
+
+/// Groups mask pixels into 4-connected components, dropping those smaller than `min_area`.
+pub struct ConnectedComponents {
+    pub min_area: u32,
+}
+
+impl Stage for ConnectedComponents {
+    fn name(&self) -> &str {
+        "components"
+    }
+
+    fn run(&self, _frame: &Frame, mut data: StageData) -> Result<StageData, String> {
+        let mask = data.mask.as_ref().ok_or("no mask to label")?;
+        let (ox, oy) = data.origin();
+        let (w, h) = mask.dimensions();
+        let mut visited = vec![false; (w * h) as usize];
+        let mut components = Vec::new();
+        let mut stack = Vec::new();
+
+        for start_y in 0..h {
+            for start_x in 0..w {
+                let idx = (start_y * w + start_x) as usize;
+                if visited[idx] || mask.get_pixel(start_x, start_y).0[0] == 0 {
+                    continue;
+                }
+
+                visited[idx] = true;
+                stack.push((start_x, start_y));
+                let (mut min_x, mut min_y, mut max_x, mut max_y) = (start_x, start_y, start_x, start_y);
+                let mut area = 0;
+
+                while let Some((x, y)) = stack.pop() {
+                    area += 1;
+                    min_x = min_x.min(x);
+                    min_y = min_y.min(y);
+                    max_x = max_x.max(x);
+                    max_y = max_y.max(y);
+
+                    let neighbours = [
+                        (x.wrapping_sub(1), y),
+                        (x + 1, y),
+                        (x, y.wrapping_sub(1)),
+                        (x, y + 1),
+                    ];
+                    for (nx, ny) in neighbours {
+                        if nx >= w || ny >= h {
+                            continue;
+                        }
+                        let n = (ny * w + nx) as usize;
+                        if !visited[n] && mask.get_pixel(nx, ny).0[0] != 0 {
+                            visited[n] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+
+                if area >= self.min_area {
+                    components.push(Component {
+                        bounds: Roi::new(ox + min_x, oy + min_y, max_x - min_x + 1, max_y - min_y + 1),
+                        area,
+                    });
+                }
+            }
+        }
+
+        // Reading order: top to bottom, then left to right.
+        components.sort_by_key(|c| (c.bounds.y, c.bounds.x));
+        data.components = components;
+        Ok(data)
+    }
+}
+
+/// Checks each component against a template. Without a mask from an earlier stage it
+/// searches the working image instead, which must be a region of the frame: a full-frame
+/// search is far too slow to run on every frame by accident.
+pub struct TemplateVerify {
+    pub name: String,
+    pub template: Arc<RgbaImage>,
+    /// Minimum similarity in `0.0..=1.0` for a match to be kept.
+    pub min_score: f32,
+}
+
+impl TemplateVerify {
+    pub fn new(name: &str, template: Arc<RgbaImage>, min_score: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            template,
+            min_score,
+        }
+    }
+}
+
+impl Stage for TemplateVerify {
+    fn name(&self) -> &str {
+        "template"
+    }
+
+    fn run(&self, frame: &Frame, mut data: StageData) -> Result<StageData, String> {
+        let (tw, th) = self.template.dimensions();
+
+        if data.mask.is_some() {
+            // Verify each candidate blob in place and keep every one that matches. No blobs
+            // means nothing to verify.
+            let verified: Vec<TemplateMatch> = data
+                .components
+                .iter()
+                .map(|c| Roi::new(c.bounds.x, c.bounds.y, tw, th))
+                .filter_map(|bounds| {
+                    let score = similarity(&frame.image, &self.template, bounds.x, bounds.y)?;
+                    (score >= self.min_score).then(|| TemplateMatch {
+                        template: self.name.clone(),
+                        bounds,
+                        score,
+                    })
+                })
+                .collect();
+            data.confidence = verified.iter().map(|m| m.score).reduce(f32::max);
+            data.matches.extend(verified);
+            return Ok(data);
+        }
+
+        // No candidates: search the working image for the single best match.
+        if data.roi.is_none() {
+            return Err("no region to search; crop the frame first".to_string());
+        }
+        let (ox, oy) = data.origin();
+        let image = data.image.as_ref().ok_or("no image to search")?;
+        let (w, h) = image.dimensions();
+        if w < tw || h < th {
+            return Ok(data);
+        }
+
+        let mut best: Option<TemplateMatch> = None;
+        for y in 0..=h - th {
+            for x in 0..=w - tw {
+                let Some(score) = similarity(&frame.image, &self.template, ox + x, oy + y) else {
+                    continue;
+                };
+                if score >= self.min_score && best.as_ref().is_none_or(|b| score > b.score) {
+                    best = Some(TemplateMatch {
+                        template: self.name.clone(),
+                        bounds: Roi::new(ox + x, oy + y, tw, th),
+                        score,
+                    });
+                }
+            }
+        }
+
+        data.confidence = best.as_ref().map(|m| m.score);
+        data.matches.extend(best);
+        Ok(data)
+    }
+}
+
+/// 1.0 minus the mean absolute RGB difference, or `None` if the template doesn't fit.
+fn similarity(image: &RgbaImage, template: &RgbaImage, x: u32, y: u32) -> Option<f32> {
+    let (tw, th) = template.dimensions();
+    if x + tw > image.width() || y + th > image.height() {
+        return None;
+    }
+
+    let mut diff: u64 = 0;
+    for ty in 0..th {
+        for tx in 0..tw {
+            let a = image.get_pixel(x + tx, y + ty).0;
+            let b = template.get_pixel(tx, ty).0;
+            diff += (0..3).map(|c| a[c].abs_diff(b[c]) as u64).sum::<u64>();
+        }
+    }
+    let max = (tw * th) as u64 * 3 * 255;
+    Some(1.0 - diff as f32 / max as f32)
+}
+
+/// Reads text by comparing each component of the mask against known glyph masks.
+pub struct GlyphOcr {
+    pub glyphs: Vec<(char, GrayImage)>,
+    pub min_score: f32,
+}
+
+impl Stage for GlyphOcr {
+    fn name(&self) -> &str {
+        "ocr"
+    }
+
+    fn run(&self, _frame: &Frame, mut data: StageData) -> Result<StageData, String> {
+        let mask = data.mask.as_ref().ok_or("no mask to read")?;
+        let (ox, oy) = data.origin();
+
+        // Glyphs on a line are read left to right, whatever their vertical offset.
+        let mut components = data.components.clone();
+        components.sort_by_key(|c| c.bounds.x);
+
+        let mut text = String::new();
+        let mut scores = Vec::new();
+        for c in components {
+            let glyph = imageops::crop_imm(mask, c.bounds.x - ox, c.bounds.y - oy, c.bounds.width, c.bounds.height).to_image();
+            let best = self
+                .glyphs
+                .iter()
+                .map(|(ch, reference)| (*ch, glyph_score(&glyph, reference)))
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+
+            if let Some((ch, score)) = best
+                && score >= self.min_score
+            {
+                text.push(ch);
+                scores.push(score);
+            }
+        }
+
+        data.text = Some(text);
+        data.confidence = (!scores.is_empty()).then(|| scores.iter().sum::<f32>() / scores.len() as f32);
+        Ok(data)
+    }
+}
+
+/// Fraction of agreeing pixels once the glyph is scaled to the reference size.
+fn glyph_score(glyph: &GrayImage, reference: &GrayImage) -> f32 {
+    let (rw, rh) = reference.dimensions();
+    if rw == 0 || rh == 0 {
+        return 0.0;
+    }
+    let scaled = imageops::resize(glyph, rw, rh, imageops::FilterType::Nearest);
+    let agree = scaled
+        .pixels()
+        .zip(reference.pixels())
+        .filter(|(a, b)| (a.0[0] > 127) == (b.0[0] > 127))
+        .count();
+    agree as f32 / (rw * rh) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const RED: Rgba<u8> = Rgba([220, 30, 30, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn fixture(width: u32, height: u32, rects: &[(Roi, Rgba<u8>)]) -> RgbaImage {
+        let mut img = RgbaImage::from_pixel(width, height, BLACK);
+        for (roi, color) in rects {
+            for y in roi.y..roi.y + roi.height {
+                for x in roi.x..roi.x + roi.width {
+                    img.put_pixel(x, y, *color);
+                }
+            }
+        }
+        img
+    }
+
+    fn red_threshold() -> ColorThreshold {
+        ColorThreshold { min: [200, 0, 0], max: [255, 60, 60] }
+    }
+
+    #[test]
+    fn crop_clamps_to_the_frame() {
+        let run = VisionPipeline::new("crop").stage(CropRoi(Roi::new(15, 5, 10, 10))).run_image(fixture(20, 10, &[]));
+        assert_eq!(run.error, None);
+        assert_eq!(run.output.roi, Some(Roi::new(15, 5, 5, 5)));
+        assert_eq!(run.output.image.unwrap().dimensions(), (5, 5));
+    }
+
+    #[test]
+    fn crop_outside_the_frame_stops_the_pipeline() {
+        let run = VisionPipeline::new("crop")
+            .stage(CropRoi(Roi::new(30, 30, 5, 5)))
+            .stage(red_threshold())
+            .run_image(fixture(20, 10, &[]));
+        assert!(run.error.unwrap().starts_with("crop:"));
+        assert_eq!(run.timings.len(), 1);
+        assert_eq!(run.output.roi, None);
+    }
+
+    #[test]
+    fn components_are_in_frame_coordinates() {
+        let img = fixture(
+            40,
+            30,
+            &[
+                (Roi::new(5, 5, 4, 4), RED),
+                (Roi::new(20, 10, 6, 3), RED),
+                (Roi::new(35, 25, 1, 1), RED),
+            ],
+        );
+        let run = VisionPipeline::new("blobs")
+            .stage(CropRoi(Roi::new(2, 2, 38, 28)))
+            .stage(red_threshold())
+            .stage(ConnectedComponents { min_area: 2 })
+            .run_image(img);
+
+        assert_eq!(run.error, None);
+        assert_eq!(
+            run.output.components,
+            vec![
+                Component { bounds: Roi::new(5, 5, 4, 4), area: 16 },
+                Component { bounds: Roi::new(20, 10, 6, 3), area: 18 },
+            ]
+        );
+        assert_eq!(run.timings.iter().map(|t| t.stage.as_str()).collect::<Vec<_>>(), ["crop", "threshold", "components"]);
+    }
+
+    #[test]
+    fn template_verifies_each_blob() {
+        let img = fixture(40, 30, &[(Roi::new(5, 5, 4, 4), RED), (Roi::new(20, 10, 6, 3), RED)]);
+        let template = Arc::new(fixture(4, 4, &[(Roi::new(0, 0, 4, 4), RED)]));
+        let run = VisionPipeline::new("verify")
+            .stage(red_threshold())
+            .stage(ConnectedComponents { min_area: 2 })
+            .stage(TemplateVerify::new("square", template, 0.95))
+            .run_image(img);
+
+        assert_eq!(run.output.matches.len(), 1);
+        let found = &run.output.matches[0];
+        assert_eq!((found.template.as_str(), found.bounds), ("square", Roi::new(5, 5, 4, 4)));
+        assert_eq!(run.output.confidence, Some(1.0));
+    }
+
+    #[test]
+    fn template_without_blobs_finds_nothing() {
+        let template = Arc::new(fixture(4, 4, &[(Roi::new(0, 0, 4, 4), RED)]));
+        let run = VisionPipeline::new("verify")
+            .stage(CropRoi(Roi::new(0, 0, 40, 30)))
+            .stage(red_threshold())
+            .stage(ConnectedComponents { min_area: 2 })
+            .stage(TemplateVerify::new("square", template, 0.5))
+            .run_image(fixture(40, 30, &[]));
+
+        assert_eq!(run.error, None);
+        assert!(run.output.matches.is_empty());
+        assert_eq!(run.output.confidence, None);
+    }
+
+    #[test]
+    fn template_searches_a_cropped_region() {
+        let img = fixture(40, 30, &[(Roi::new(22, 12, 3, 2), WHITE), (Roi::new(25, 12, 3, 2), RED)]);
+        let template = Arc::new(fixture(6, 2, &[(Roi::new(0, 0, 3, 2), WHITE), (Roi::new(3, 0, 3, 2), RED)]));
+        let run = VisionPipeline::new("search")
+            .stage(CropRoi(Roi::new(15, 8, 20, 10)))
+            .stage(TemplateVerify::new("pair", template, 0.9))
+            .run_image(img);
+
+        assert_eq!(run.output.matches.len(), 1);
+        assert_eq!(run.output.matches[0].bounds, Roi::new(22, 12, 6, 2));
+    }
+
+    #[test]
+    fn template_refuses_a_full_frame_search() {
+        let template = Arc::new(fixture(4, 4, &[]));
+        let run = VisionPipeline::new("search")
+            .stage(TemplateVerify::new("any", template, 0.5))
+            .run_image(fixture(40, 30, &[]));
+        assert!(run.error.unwrap().contains("crop the frame first"));
+    }
+
+    #[test]
+    fn ocr_reads_glyphs_left_to_right() {
+        // An "L" (3x5) then an "I" (1x5), white on black, with the "I" drawn one pixel lower.
+        let img = fixture(
+            12,
+            8,
+            &[
+                (Roi::new(1, 1, 1, 5), WHITE),
+                (Roi::new(1, 5, 3, 1), WHITE),
+                (Roi::new(6, 2, 1, 5), WHITE),
+            ],
+        );
+        let mut l = GrayImage::new(3, 5);
+        for y in 0..5 {
+            l.put_pixel(0, y, Luma([255]));
+        }
+        for x in 0..3 {
+            l.put_pixel(x, 4, Luma([255]));
+        }
+        let i = GrayImage::from_pixel(1, 5, Luma([255]));
+
+        let run = VisionPipeline::new("ocr")
+            .stage(CropRoi(Roi::new(0, 0, 12, 8)))
+            .stage(ColorThreshold { min: [200, 200, 200], max: [255, 255, 255] })
+            .stage(ConnectedComponents { min_area: 1 })
+            .stage(GlyphOcr { glyphs: vec![('I', i), ('L', l)], min_score: 0.9 })
+            .run_image(img);
+
+        assert_eq!(run.output.text.as_deref(), Some("LI"));
+        assert_eq!(run.output.confidence, Some(1.0));
+    }
+
+    struct Counting(Arc<AtomicUsize>);
+
+    impl Stage for Counting {
+        fn name(&self) -> &str {
+            "count"
+        }
+
+        fn run(&self, _frame: &Frame, data: StageData) -> Result<StageData, String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(data)
+        }
+    }
+
+    #[test]
+    fn runs_are_cached_by_frame_sequence() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut pipeline = VisionPipeline::new("cached").stage(Counting(Arc::clone(&count)));
+        let first = Frame::new(1, fixture(4, 4, &[]));
+
+        pipeline.run(&first);
+        pipeline.run(&first);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        pipeline.run(&Frame::new(2, fixture(4, 4, &[])));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert_eq!(pipeline.last_run().unwrap().seq, 2);
+    }
+}
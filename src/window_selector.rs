@@ -0,0 +1,193 @@
+use crate::vision_engine::WindowGeometry;
+use regex::Regex;
+use screencapturekit::shareable_content::{SCShareableContent, SCWindow};
+use std::fmt;
+
+/// A window that could be captured, with everything the selectors can match on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowCandidate {
+    pub window_id: u32,
+    pub pid: i32,
+    pub title: String,
+    pub app_name: String,
+    pub bundle_id: String,
+    pub character: Option<String>,
+    pub geometry: WindowGeometry,
+    pub on_screen: bool,
+}
+
+impl WindowCandidate {
+    pub fn of(window: &SCWindow) -> Self {
+        let app = window.owning_application();
+        let title = window.title();
+        Self {
+            window_id: window.window_id(),
+            pid: app.process_id(),
+            character: parse_character_name(&title),
+            title,
+            app_name: app.application_name(),
+            bundle_id: app.bundle_identifier(),
+            geometry: WindowGeometry::of(window),
+            on_screen: window.is_on_screen(),
+        }
+    }
+
+    /// Short label for pickers: the character name when known, else the title.
+    pub fn label(&self) -> String {
+        let name = self.character.as_deref().unwrap_or(&self.title);
+        format!("{} (PID {}, window {})", name, self.pid, self.window_id)
+    }
+}
+
+/// Extracts the character from titles like `Dofus - Name` or `Name - Dofus 3.0.1`.
+pub fn parse_character_name(title: &str) -> Option<String> {
+    let (left, right) = title.split_once(" - ")?;
+    let name = if left.trim().starts_with("Dofus") {
+        right
+    } else if right.trim().starts_with("Dofus") {
+        left
+    } else {
+        return None;
+    };
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Rule picking which window(s) to capture.
+#[derive(Debug, Clone)]
+pub enum WindowSelector {
+    TitleRegex(Regex),
+    Pid(i32),
+    WindowId(u32),
+    /// Case-insensitive substring of the owning application's name or bundle id.
+    Application(String),
+    /// Exact character name, as parsed from the title.
+    Character(String),
+    /// Every selector must match.
+    All(Vec<WindowSelector>),
+}
+
+impl WindowSelector {
+    /// Windows owned by the Dofus client itself, so browser tabs or chat apps with
+    /// "Dofus" in their title don't count.
+    pub fn dofus() -> Self {
+        WindowSelector::Application("dofus".to_string())
+    }
+
+    /// Parses selectors such as `pid:1234`, `id:42`, `app:com.ankama.dofus`,
+    /// `character:Name` or `title:^Dofus`. Several can be combined with `&`. A title regex
+    /// may itself contain `&`, so `title:` takes the rest of the spec and must come last.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (head, title) = match title_start(spec) {
+            Some(at) => (&spec[..at], Some(spec[at..].trim())),
+            None => (spec, None),
+        };
+        let mut parts: Vec<&str> = head.split('&').map(str::trim).filter(|p| !p.is_empty()).collect();
+        parts.extend(title);
+        if parts.is_empty() {
+            return Ok(Self::dofus());
+        }
+        if parts.len() > 1 {
+            return parts.into_iter().map(Self::parse_one).collect::<Result<_, _>>().map(WindowSelector::All);
+        }
+        Self::parse_one(parts[0])
+    }
+
+    /// Parses a single `kind:value` selector.
+    fn parse_one(part: &str) -> Result<Self, String> {
+        let (kind, value) = part
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid selector '{}': expected kind:value", part))?;
+        let value = value.trim();
+        match kind.trim() {
+            "title" => Regex::new(value)
+                .map(WindowSelector::TitleRegex)
+                .map_err(|e| format!("Invalid title regex: {}", e)),
+            "pid" => value
+                .parse()
+                .map(WindowSelector::Pid)
+                .map_err(|_| format!("Invalid PID '{}'", value)),
+            "id" => value
+                .parse()
+                .map(WindowSelector::WindowId)
+                .map_err(|_| format!("Invalid window id '{}'", value)),
+            "app" => Ok(WindowSelector::Application(value.to_string())),
+            "character" => Ok(WindowSelector::Character(value.to_string())),
+            other => Err(format!("Unknown selector kind '{}'", other)),
+        }
+    }
+
+    pub fn matches(&self, candidate: &WindowCandidate) -> bool {
+        match self {
+            WindowSelector::TitleRegex(re) => re.is_match(&candidate.title),
+            WindowSelector::Pid(pid) => candidate.pid == *pid,
+            WindowSelector::WindowId(id) => candidate.window_id == *id,
+            WindowSelector::Application(app) => {
+                let app = app.to_lowercase();
+                candidate.app_name.to_lowercase().contains(&app) || candidate.bundle_id.to_lowercase().contains(&app)
+            }
+            WindowSelector::Character(name) => candidate.character.as_deref() == Some(name.as_str()),
+            WindowSelector::All(selectors) => selectors.iter().all(|s| s.matches(candidate)),
+        }
+    }
+}
+
+/// Byte offset of the first `title:` selector in `spec`, at the start or right after an `&`.
+fn title_start(spec: &str) -> Option<usize> {
+    std::iter::once(0)
+        .chain(spec.match_indices('&').map(|(i, _)| i + 1))
+        .find(|&at| spec[at..].trim_start().starts_with("title:"))
+}
+
+impl fmt::Display for WindowSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowSelector::TitleRegex(re) => write!(f, "title:{}", re.as_str()),
+            WindowSelector::Pid(pid) => write!(f, "pid:{}", pid),
+            WindowSelector::WindowId(id) => write!(f, "id:{}", id),
+            WindowSelector::Application(app) => write!(f, "app:{}", app),
+            WindowSelector::Character(name) => write!(f, "character:{}", name),
+            WindowSelector::All(selectors) => {
+                let parts: Vec<String> = selectors.iter().map(|s| s.to_string()).collect();
+                write!(f, "{}", parts.join(" & "))
+            }
+        }
+    }
+}
+
+/// Every window matching `selector`, on-screen windows first.
+pub fn scan_windows(selector: &WindowSelector) -> Result<Vec<WindowCandidate>, String> {
+    let content = SCShareableContent::get().map_err(|_| "Failed to get shareable content.".to_string())?;
+    let mut candidates: Vec<WindowCandidate> = content
+        .windows()
+        .iter()
+        .map(WindowCandidate::of)
+        .filter(|c| selector.matches(c))
+        .collect();
+    candidates.sort_by_key(|c| !c.on_screen);
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_regex_keeps_its_ampersands() {
+        let selector = WindowSelector::parse("app:dofus & title:^Tom & Jerry$").unwrap();
+        let WindowSelector::All(parts) = &selector else { panic!("expected All, got {:?}", selector) };
+        assert!(matches!(&parts[0], WindowSelector::Application(app) if app == "dofus"));
+        assert!(matches!(&parts[1], WindowSelector::TitleRegex(re) if re.as_str() == "^Tom & Jerry$"));
+        assert_eq!(selector.to_string(), "app:dofus & title:^Tom & Jerry$");
+    }
+
+    #[test]
+    fn title_alone_and_plain_combinations() {
+        let selector = WindowSelector::parse("title:a&b").unwrap();
+        assert!(matches!(&selector, WindowSelector::TitleRegex(re) if re.as_str() == "a&b"));
+
+        let selector = WindowSelector::parse("pid:12 & id:7").unwrap();
+        assert!(matches!(&selector, WindowSelector::All(parts) if parts.len() == 2));
+        assert!(WindowSelector::parse("pid:x").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often sleeps and joins check back.
+const POLL: Duration = Duration::from_millis(20);
+
+/// Asks background work to stop. Cheap to clone; clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for `duration` unless cancelled first. Returns false if it was.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return true;
+            }
+            thread::sleep(left.min(POLL));
+        }
+        false
+    }
+}
+
+/// Waits for `handle` until `deadline`. Returns false, leaving the thread detached, if it
+/// hasn't finished by then.
+pub fn join_until(handle: JoinHandle<()>, deadline: Instant) -> bool {
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(POLL);
+    }
+    let _ = handle.join();
+    true
+}
+
+/// A named background thread with its cancel token. Dropping it cancels the token but
+/// doesn't wait; `stop` does both.
+pub struct Worker {
+    name: String,
+    token: CancelToken,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Runs `work` on a new thread; it should return soon after its token is cancelled.
+    pub fn spawn(name: &str, work: impl FnOnce(CancelToken) + Send + 'static) -> Self {
+        let token = CancelToken::new();
+        let worker_token = token.clone();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || work(worker_token))
+            .expect("failed to spawn worker thread");
+        Self {
+            name: name.to_string(),
+            token,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// Cancels the work and waits up to `timeout` for it to return. Returns false if it
+    /// didn't.
+    pub fn stop(&mut self, timeout: Duration) -> bool {
+        self.token.cancel();
+        self.handle.take().is_none_or(|handle| join_until(handle, Instant::now() + timeout))
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
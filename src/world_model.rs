@@ -0,0 +1,383 @@
+use crate::detector::ResourceSighting;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Two sightings of the same kind closer than this (in window-relative units) are the same node.
+const SAME_NODE_DISTANCE: f32 = 0.03;
+/// Sightings this soon after a harvest are the harvest animation, not a respawn.
+const MIN_RESPAWN_SECS: i64 = 10;
+/// How long after a harvest click the node may take to disappear.
+const HARVEST_WINDOW_SECS: i64 = 30;
+
+/// A harvestable resource at a fixed spot on a map.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceNode {
+    pub id: u64,
+    pub kind: String,
+    pub map: (i32, i32),
+    /// Centre of the node as a fraction of the window size, so it survives resizes.
+    pub position: (f32, f32),
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub last_harvested: Option<i64>,
+    /// Observed harvest-to-respawn delays, in seconds.
+    pub respawn_samples: Vec<i64>,
+}
+
+impl ResourceNode {
+    fn distance_to(&self, position: (f32, f32)) -> f32 {
+        let dx = self.position.0 - position.0;
+        let dy = self.position.1 - position.1;
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapRecord {
+    pub coords: (i32, i32),
+    pub last_visit: i64,
+    pub nodes: Vec<ResourceNode>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct WorldFile {
+    next_id: u64,
+    maps: Vec<MapRecord>,
+}
+
+/// Persistent record of where resources are and when they come back, keyed by map coordinates.
+///
+/// Timestamps are unix seconds so the file stays meaningful across restarts.
+pub struct WorldModel {
+    path: PathBuf,
+    maps: HashMap<(i32, i32), MapRecord>,
+    next_id: u64,
+    /// Node clicked for harvesting and when, until it disappears or the window passes.
+    pending_harvest: Option<(u64, i64)>,
+    dirty: bool,
+}
+
+impl WorldModel {
+    /// An empty model that will be saved to `path`.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            maps: HashMap::new(),
+            next_id: 0,
+            pending_harvest: None,
+            dirty: false,
+        }
+    }
+
+    /// Loads the model from `path`, starting empty if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file: WorldFile = match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Failed to parse world model {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => WorldFile::default(),
+            Err(e) => return Err(format!("Failed to read world model {}: {}", path.display(), e)),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            maps: file.maps.into_iter().map(|m| (m.coords, m)).collect(),
+            next_id: file.next_id,
+            pending_harvest: None,
+            dirty: false,
+        })
+    }
+
+    /// Writes the model back to disk through a temporary file, so a crash never leaves it half-written.
+    pub fn save(&mut self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let mut maps: Vec<MapRecord> = self.maps.values().cloned().collect();
+        maps.sort_by_key(|m| m.coords);
+        let file = WorldFile {
+            next_id: self.next_id,
+            maps,
+        };
+        let json = serde_json::to_string_pretty(&file).map_err(|e| format!("Failed to encode world model: {}", e))?;
+
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json).map_err(|e| format!("Failed to write world model: {}", e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| format!("Failed to write world model: {}", e))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn map(&self, coords: (i32, i32)) -> Option<&MapRecord> {
+        self.maps.get(&coords)
+    }
+
+    /// Records the resources currently visible on `map`, of every kind at once: a node
+    /// missing from `sightings` counts as gone. `frame_size` converts their pixel bounds to
+    /// window-relative positions.
+    ///
+    /// A node seen again after being harvested has respawned, which teaches us its interval.
+    /// A node clicked through `expect_harvest` that is no longer seen has been harvested.
+    /// Only new nodes, harvests and respawns mark the model dirty; sighting times alone
+    /// aren't worth a write.
+    pub fn record_sightings(&mut self, map: (i32, i32), frame_size: (u32, u32), sightings: &[ResourceSighting], now: i64) {
+        let (fw, fh) = (frame_size.0.max(1) as f32, frame_size.1.max(1) as f32);
+        let record = self.maps.entry(map).or_insert_with(|| {
+            self.dirty = true;
+            MapRecord {
+                coords: map,
+                last_visit: now,
+                nodes: Vec::new(),
+            }
+        });
+        record.last_visit = now;
+
+        let mut seen = Vec::with_capacity(sightings.len());
+        for sighting in sightings {
+            let (cx, cy) = sighting.bounds.center();
+            let position = (cx as f32 / fw, cy as f32 / fh);
+
+            let existing = record
+                .nodes
+                .iter_mut()
+                .filter(|n| n.kind == sighting.kind)
+                .find(|n| n.distance_to(position) < SAME_NODE_DISTANCE);
+
+            match existing {
+                Some(node) => {
+                    node.last_seen = now;
+                    seen.push(node.id);
+                    if let Some(harvested) = node.last_harvested
+                        && now - harvested >= MIN_RESPAWN_SECS
+                    {
+                        node.respawn_samples.push(now - harvested);
+                        node.last_harvested = None;
+                        self.dirty = true;
+                    }
+                }
+                None => {
+                    record.nodes.push(ResourceNode {
+                        id: self.next_id,
+                        kind: sighting.kind.clone(),
+                        map,
+                        position,
+                        first_seen: now,
+                        last_seen: now,
+                        last_harvested: None,
+                        respawn_samples: Vec::new(),
+                    });
+                    seen.push(self.next_id);
+                    self.next_id += 1;
+                    self.dirty = true;
+                }
+            }
+        }
+
+        if let Some((node_id, clicked)) = self.pending_harvest {
+            let on_map = record.nodes.iter().any(|n| n.id == node_id);
+            if now - clicked > HARVEST_WINDOW_SECS {
+                self.pending_harvest = None;
+            } else if on_map && !seen.contains(&node_id) {
+                self.pending_harvest = None;
+                self.mark_harvested(node_id, now);
+            }
+        }
+    }
+
+    /// Notes that `node_id` was just clicked to harvest it, so its disappearance from the
+    /// next frames is recorded as a harvest.
+    pub fn expect_harvest(&mut self, node_id: u64, now: i64) {
+        self.pending_harvest = Some((node_id, now));
+    }
+
+    pub fn node(&self, node_id: u64) -> Option<&ResourceNode> {
+        self.nodes().find(|n| n.id == node_id)
+    }
+
+    pub fn mark_harvested(&mut self, node_id: u64, now: i64) -> bool {
+        let node = self
+            .maps
+            .values_mut()
+            .flat_map(|m| m.nodes.iter_mut())
+            .find(|n| n.id == node_id);
+
+        match node {
+            Some(node) => {
+                node.last_harvested = Some(now);
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Expected respawn delay for a node: its own median if it has samples, otherwise the
+    /// median over every node of the same kind.
+    pub fn respawn_interval(&self, node: &ResourceNode) -> Option<i64> {
+        if !node.respawn_samples.is_empty() {
+            return median(node.respawn_samples.clone());
+        }
+        let samples: Vec<i64> = self
+            .nodes()
+            .filter(|n| n.kind == node.kind)
+            .flat_map(|n| n.respawn_samples.iter().copied())
+            .collect();
+        median(samples)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &ResourceNode> {
+        self.maps.values().flat_map(|m| m.nodes.iter())
+    }
+
+    /// Whether a node should be harvestable at `now`. Harvested nodes with no known
+    /// interval are assumed depleted until seen again.
+    pub fn is_available(&self, node: &ResourceNode, now: i64) -> bool {
+        match node.last_harvested {
+            None => true,
+            Some(harvested) => self
+                .respawn_interval(node)
+                .is_some_and(|interval| now >= harvested + interval),
+        }
+    }
+
+    /// Every known node that should be available now, across all maps.
+    pub fn available_nodes(&self, now: i64) -> Vec<&ResourceNode> {
+        self.nodes().filter(|n| self.is_available(n, now)).collect()
+    }
+
+    pub fn available_on_map(&self, map: (i32, i32), now: i64) -> Vec<&ResourceNode> {
+        self.maps
+            .get(&map)
+            .map(|m| m.nodes.iter().filter(|n| self.is_available(n, now)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Seconds until a harvested node is expected back, if known.
+    pub fn respawns_in(&self, node: &ResourceNode, now: i64) -> Option<i64> {
+        let harvested = node.last_harvested?;
+        let interval = self.respawn_interval(node)?;
+        Some((harvested + interval - now).max(0))
+    }
+}
+
+fn median(mut samples: Vec<i64>) -> Option<i64> {
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort_unstable();
+    Some(samples[samples.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vision_pipeline::Roi;
+
+    const MAP: (i32, i32) = (5, -18);
+    const FRAME: (u32, u32) = (1000, 1000);
+
+    fn wheat(x: u32, y: u32) -> ResourceSighting {
+        ResourceSighting {
+            kind: "wheat".to_string(),
+            bounds: Roi::new(x, y, 20, 20),
+        }
+    }
+
+    fn ash(x: u32, y: u32) -> ResourceSighting {
+        ResourceSighting {
+            kind: "ash".to_string(),
+            bounds: Roi::new(x, y, 20, 20),
+        }
+    }
+
+    fn model() -> WorldModel {
+        WorldModel::new(Path::new("/nonexistent/world.json"))
+    }
+
+    #[test]
+    fn repeated_sightings_only_dirty_once() {
+        let mut world = model();
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100)], 0);
+        assert!(world.is_dirty());
+        world.dirty = false;
+
+        world.record_sightings(MAP, FRAME, &[wheat(102, 101)], 1);
+        assert!(!world.is_dirty());
+        assert_eq!(world.nodes().count(), 1);
+        assert_eq!(world.nodes().next().unwrap().last_seen, 1);
+    }
+
+    #[test]
+    fn node_gone_after_harvest_click_is_harvested() {
+        let mut world = model();
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100), wheat(500, 500)], 0);
+        let id = world.nodes().find(|n| n.position.0 < 0.2).unwrap().id;
+
+        world.expect_harvest(id, 10);
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100), wheat(500, 500)], 11);
+        assert_eq!(world.node(id).unwrap().last_harvested, None);
+
+        world.record_sightings(MAP, FRAME, &[wheat(500, 500)], 14);
+        assert_eq!(world.node(id).unwrap().last_harvested, Some(14));
+        assert_eq!(world.available_on_map(MAP, 20).len(), 1);
+    }
+
+    #[test]
+    fn other_kinds_on_the_map_do_not_hide_a_harvest() {
+        let mut world = model();
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100), ash(500, 500)], 0);
+        let wheat_id = world.nodes().find(|n| n.kind == "wheat").unwrap().id;
+        let ash_id = world.nodes().find(|n| n.kind == "ash").unwrap().id;
+
+        world.expect_harvest(ash_id, 10);
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100), ash(500, 500)], 11);
+        assert_eq!(world.node(ash_id).unwrap().last_harvested, None);
+
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100)], 12);
+        assert_eq!(world.node(ash_id).unwrap().last_harvested, Some(12));
+        assert_eq!(world.node(wheat_id).unwrap().last_harvested, None);
+        assert_eq!(world.nodes().count(), 2);
+    }
+
+    #[test]
+    fn sole_node_gone_after_harvest_click_is_harvested() {
+        let mut world = model();
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100)], 0);
+
+        world.expect_harvest(0, 10);
+        world.record_sightings(MAP, FRAME, &[], 12);
+        assert_eq!(world.node(0).unwrap().last_harvested, Some(12));
+        assert!(world.available_on_map(MAP, 20).is_empty());
+    }
+
+    #[test]
+    fn harvest_click_expires() {
+        let mut world = model();
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100)], 0);
+        world.expect_harvest(0, 0);
+        world.record_sightings(MAP, FRAME, &[], HARVEST_WINDOW_SECS + 1);
+        assert_eq!(world.node(0).unwrap().last_harvested, None);
+    }
+
+    #[test]
+    fn respawn_teaches_the_interval() {
+        let mut world = model();
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100)], 0);
+        world.mark_harvested(0, 100);
+        assert!(world.available_on_map(MAP, 150).is_empty());
+
+        world.record_sightings(MAP, FRAME, &[wheat(100, 100)], 160);
+        let node = world.node(0).unwrap().clone();
+        assert_eq!(node.respawn_samples, vec![60]);
+
+        world.mark_harvested(0, 200);
+        let node = world.node(0).unwrap().clone();
+        assert_eq!(world.respawns_in(&node, 230), Some(30));
+        assert!(world.is_available(&node, 260));
+    }
+}